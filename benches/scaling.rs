@@ -0,0 +1,69 @@
+//! Crawl pages served by the fake fetcher with 1 to 64 workers.
+//!
+//! Run with `cargo bench --bench scaling`. Pages are served without latency,
+//! so workers only parse pages and contend for shared state. Each run is
+//! compared with a crawler whose indexer and queue have a single shard, all
+//! workers sharing one mutex of each.
+
+extern crate libunibot;
+
+use libunibot::crawl::Crawler;
+use libunibot::crawl::config::CrawlerConfig;
+use libunibot::crawl::fetch::{FakeFetcher, Fetcher};
+use libunibot::crawl::shard::DEFAULT_SHARDS;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HOSTS: usize = 64;
+const PAGES: usize = 50;
+const LINKS: usize = 20;
+
+/// Crawl all pages, return the number of pages crawled per second
+fn crawl(workers: usize, shards: usize) -> f64 {
+    let fetcher = FakeFetcher::new(HOSTS, PAGES, LINKS);
+    let mut crawler = Crawler::with_shards(shards);
+    crawler.create_slaves_with(workers, || {
+        let fetcher: Box<Fetcher> = Box::new(fetcher.clone());
+        fetcher
+    });
+    for h in 0..HOSTS {
+        crawler.add_to_queue(fetcher.page_url(h, 0)).unwrap();
+    }
+    let config = CrawlerConfig::new().set_sleep_ms(0);
+    let start = Instant::now();
+    let receivers = crawler.crawl_recursive(&config).unwrap();
+    let mut pages = 0;
+    while crawler.get_running() > 0 {
+        for receiver in &receivers {
+            pages += receiver.try_iter().count();
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    for receiver in &receivers {
+        pages += receiver.try_iter().count();
+    }
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    pages as f64 / secs
+}
+
+fn main() {
+    println!("{:>7} {:>13} {:>13} {:>8} {:>8}",
+             "workers",
+             "1 shard/sec",
+             "sharded/sec",
+             "speedup",
+             "gain");
+    let mut base = None;
+    for &workers in &[1, 2, 4, 8, 16, 32, 64] {
+        let single = crawl(workers, 1);
+        let sharded = crawl(workers, DEFAULT_SHARDS);
+        let base = *base.get_or_insert(sharded);
+        println!("{:>7} {:>13.1} {:>13.1} {:>8.2} {:>8.2}",
+                 workers,
+                 single,
+                 sharded,
+                 sharded / base,
+                 sharded / single);
+    }
+}
@@ -0,0 +1,209 @@
+use common::hash_bytes;
+use error::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+/// Bloom filter with a fixed capacity and false positive rate
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    capacity: usize,
+    len: usize,
+}
+
+/// Check that fp_rate is a rate a filter can be sized for, between 0 and 1 excluded
+fn check_rate(fp_rate: f64) -> Result<()> {
+    if fp_rate > 0.0 && fp_rate < 1.0 {
+        Ok(())
+    } else {
+        bail!(ErrorKind::InvalidFalsePositiveRate(fp_rate))
+    }
+}
+
+impl BloomFilter {
+    /// Create a filter holding `capacity` items with false positive rate `fp_rate`
+    ///
+    /// Fails if `fp_rate` is not between 0 and 1 excluded.
+    pub fn new(capacity: usize, fp_rate: f64) -> Result<BloomFilter> {
+        check_rate(fp_rate)?;
+        Ok(BloomFilter::sized(capacity, fp_rate))
+    }
+
+    fn sized(capacity: usize, fp_rate: f64) -> BloomFilter {
+        let capacity = if capacity == 0 { 1 } else { capacity };
+        let ln2 = 2f64.ln();
+        let num_bits = (-(capacity as f64) * fp_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; ((num_bits + 63) / 64) as usize],
+            num_bits: num_bits,
+            num_hashes: num_hashes,
+            capacity: capacity,
+            len: 0,
+        }
+    }
+
+    fn positions(&self, item: &[u8]) -> Vec<u64> {
+        // Double hashing: position i is h1 + i * h2
+        let h1 = hash_bytes(item);
+        let mut hasher = DefaultHasher::new();
+        hasher.write(item);
+        let h2 = hasher.finish() | 1;
+        (0..self.num_hashes as u64)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+            .collect()
+    }
+
+    /// Add item, return false if it was probably already present
+    pub fn insert(&mut self, item: &[u8]) -> bool {
+        let mut added = false;
+        for pos in self.positions(item) {
+            let (word, bit) = ((pos / 64) as usize, pos % 64);
+            if self.bits[word] & (1 << bit) == 0 {
+                self.bits[word] |= 1 << bit;
+                added = true;
+            }
+        }
+        if added {
+            self.len += 1;
+        }
+        added
+    }
+
+    /// Check if item is probably present
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .into_iter()
+            .all(|pos| self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0)
+    }
+
+    /// Return the number of items added
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if no item was added
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check if filter holds as many items as its capacity
+    pub fn is_full(&self) -> bool {
+        self.len >= self.capacity
+    }
+
+    /// Return the memory used by bits, in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+/// Bloom filter growing as items are added while keeping its false positive rate.
+///
+/// When the current filter is full, a new filter twice as large with a
+/// tighter false positive rate is added, so the compound rate stays under
+/// the configured one.
+#[derive(Debug, Clone)]
+pub struct ScalableBloomFilter {
+    filters: Vec<BloomFilter>,
+    fp_rate: f64,
+    initial_capacity: usize,
+}
+
+/// Ratio between false positive rates of successive filters
+const TIGHTENING: f64 = 0.5;
+
+impl ScalableBloomFilter {
+    /// Create a filter whose first filter holds `initial_capacity` items
+    ///
+    /// Fails if `fp_rate` is not between 0 and 1 excluded.
+    pub fn new(initial_capacity: usize, fp_rate: f64) -> Result<ScalableBloomFilter> {
+        check_rate(fp_rate)?;
+        Ok(ScalableBloomFilter {
+            filters: Vec::new(),
+            fp_rate: fp_rate,
+            initial_capacity: initial_capacity,
+        })
+    }
+
+    /// Add item, return false if it was probably already present
+    pub fn insert(&mut self, item: &[u8]) -> bool {
+        if self.contains(item) {
+            return false;
+        }
+        let full = self.filters.last().map(|f| f.is_full()).unwrap_or(true);
+        if full {
+            let i = self.filters.len() as i32;
+            let capacity = self.initial_capacity << self.filters.len();
+            let fp_rate = self.fp_rate * (1.0 - TIGHTENING) * TIGHTENING.powi(i);
+            self.filters.push(BloomFilter::sized(capacity, fp_rate));
+        }
+        self.filters.last_mut().unwrap().insert(item)
+    }
+
+    /// Check if item is probably present
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filters.iter().any(|f| f.contains(item))
+    }
+
+    /// Add all items of other, the false positive rates adding up
+    pub fn absorb(&mut self, other: ScalableBloomFilter) {
+        self.filters.extend(other.filters);
+    }
+
+    /// Return the number of items added
+    pub fn len(&self) -> usize {
+        self.filters.iter().map(|f| f.len()).sum()
+    }
+
+    /// Check if no item was added
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the memory used by bits, in bytes
+    pub fn size_in_bytes(&self) -> usize {
+        self.filters.iter().map(|f| f.size_in_bytes()).sum()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::{BloomFilter, ScalableBloomFilter};
+
+    #[test]
+    fn no_false_negative() {
+        let mut filter = BloomFilter::new(1000, 0.01).unwrap();
+        for i in 0..1000 {
+            filter.insert(format!("http://example.com/{}", i).as_bytes());
+        }
+        for i in 0..1000 {
+            assert!(filter.contains(format!("http://example.com/{}", i).as_bytes()));
+        }
+        assert!(filter.len() > 990);
+    }
+
+    #[test]
+    fn scalable_false_positive_rate() {
+        let mut filter = ScalableBloomFilter::new(100, 0.01).unwrap();
+        for i in 0..10000 {
+            filter.insert(format!("http://example.com/{}", i).as_bytes());
+        }
+        assert!(filter.len() > 9900);
+        assert!(!filter.insert(b"http://example.com/42"));
+        let false_positives = (0..10000)
+            .filter(|i| filter.contains(format!("http://example.org/{}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn invalid_false_positive_rate() {
+        for &rate in &[0.0, -0.5, 1.0, 2.0, ::std::f64::NAN, ::std::f64::INFINITY] {
+            assert!(BloomFilter::new(1000, rate).is_err(), "{}", rate);
+            assert!(ScalableBloomFilter::new(1000, rate).is_err(), "{}", rate);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use check::LinkStatus;
+use error::*;
+use hyper::Url;
+use indexer::Indexer;
+use serde_json::Value;
+use std::fmt;
+use std::io::Write;
+
+/// Why a canonical url cannot stand for the pages declaring it
+#[derive(Debug, Clone, PartialEq)]
+pub enum CanonicalProblem {
+    /// The canonical page answered an error or no response
+    Broken(LinkStatus),
+    /// The canonical page redirected to another url
+    Redirect(Url),
+    /// The canonical page declared another canonical
+    Chain(Url),
+    /// The canonical page asked not to be indexed
+    Noindex,
+}
+
+impl CanonicalProblem {
+    /// Return the name of the problem, as used in JSON reports
+    pub fn name(&self) -> &'static str {
+        match *self {
+            CanonicalProblem::Broken(_) => "broken",
+            CanonicalProblem::Redirect(_) => "redirect",
+            CanonicalProblem::Chain(_) => "chain",
+            CanonicalProblem::Noindex => "noindex",
+        }
+    }
+}
+
+impl fmt::Display for CanonicalProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CanonicalProblem::Broken(ref status) => write!(f, "is broken ({})", status),
+            CanonicalProblem::Redirect(ref url) => write!(f, "redirects to {}", url),
+            CanonicalProblem::Chain(ref url) => write!(f, "declares {} as canonical", url),
+            CanonicalProblem::Noindex => write!(f, "is noindex"),
+        }
+    }
+}
+
+/// A page declaring a canonical url with a problem
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalConflict {
+    url: Url,
+    canonical: Url,
+    problem: CanonicalProblem,
+}
+
+impl CanonicalConflict {
+    /// Return the page declaring the canonical
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the canonical url declared by the page
+    pub fn get_canonical(&self) -> &Url {
+        &self.canonical
+    }
+
+    /// Return the problem of the canonical
+    pub fn get_problem(&self) -> &CanonicalProblem {
+        &self.problem
+    }
+}
+
+/// Canonical relationships of a crawl.
+///
+/// `duplicates` - Canonical urls with the other pages declaring them, sorted
+///
+/// `conflicts` - Pages whose canonical has a problem, sorted by url
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalReport {
+    duplicates: Vec<(Url, Vec<Url>)>,
+    conflicts: Vec<CanonicalConflict>,
+}
+
+impl CanonicalReport {
+    /// Return the canonical urls with the other pages declaring them
+    pub fn get_duplicates(&self) -> &Vec<(Url, Vec<Url>)> {
+        &self.duplicates
+    }
+
+    /// Return the pages whose canonical has a problem
+    pub fn get_conflicts(&self) -> &Vec<CanonicalConflict> {
+        &self.conflicts
+    }
+
+    /// Gather the canonicals declared by the pages of indexer
+    ///
+    /// Canonicals which were not crawled cannot be checked and are not conflicts.
+    pub fn new(indexer: &Indexer) -> CanonicalReport {
+        let mut canonicals = Vec::new();
+        let mut conflicts = Vec::new();
+        for (url, info) in indexer.get_all_page_infos() {
+            let canonical = match info.get_canonical() {
+                Some(canonical) if canonical != url => canonical,
+                _ => continue,
+            };
+            canonicals.push(canonical.clone());
+            if let Some(problem) = canonical_problem(indexer, canonical) {
+                conflicts.push(CanonicalConflict {
+                    url: url.clone(),
+                    canonical: canonical.clone(),
+                    problem: problem,
+                });
+            }
+        }
+        canonicals.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        canonicals.dedup();
+        conflicts.sort_by(|a, b| a.url.as_str().cmp(b.url.as_str()));
+        let duplicates = canonicals.into_iter()
+            .map(|c| {
+                let duplicates = indexer.get_duplicates(&c).into_iter().cloned().collect();
+                (c, duplicates)
+            })
+            .collect();
+        CanonicalReport {
+            duplicates: duplicates,
+            conflicts: conflicts,
+        }
+    }
+
+    /// Write the report as text, one line per page
+    pub fn write_text<W: Write>(&self, w: &mut W) -> Result<()> {
+        for &(ref canonical, ref duplicates) in &self.duplicates {
+            writeln!(w, "Canonical {}", canonical)?;
+            for duplicate in duplicates {
+                writeln!(w, "    duplicate {}", duplicate)?;
+            }
+        }
+        for conflict in &self.conflicts {
+            writeln!(w,
+                     "Conflict {}: canonical {} {}",
+                     conflict.url,
+                     conflict.canonical,
+                     conflict.problem)?;
+        }
+        writeln!(w,
+                 "{} canonicals, {} duplicates, {} conflicts",
+                 self.duplicates.len(),
+                 self.duplicates.iter().map(|&(_, ref d)| d.len()).sum::<usize>(),
+                 self.conflicts.len())?;
+        Ok(())
+    }
+
+    /// Return the report as JSON
+    pub fn to_json(&self) -> Value {
+        let duplicates: Vec<Value> = self.duplicates
+            .iter()
+            .map(|&(ref canonical, ref duplicates)| {
+                let duplicates: Vec<String> = duplicates.iter().map(|d| d.to_string()).collect();
+                json!({
+                    "canonical": canonical.to_string(),
+                    "duplicates": duplicates
+                })
+            })
+            .collect();
+        let conflicts: Vec<Value> = self.conflicts
+            .iter()
+            .map(|conflict| {
+                json!({
+                    "url": conflict.url.to_string(),
+                    "canonical": conflict.canonical.to_string(),
+                    "problem": conflict.problem.name(),
+                    "detail": conflict.problem.to_string()
+                })
+            })
+            .collect();
+        json!({
+            "duplicates": duplicates,
+            "conflicts": conflicts
+        })
+    }
+
+    /// Write the report as pretty printed JSON
+    pub fn write_json<W: Write>(&self, w: &mut W) -> Result<()> {
+        ::serde_json::to_writer_pretty(w, &self.to_json())?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Return what prevents canonical from standing for other pages, if it was crawled
+fn canonical_problem(indexer: &Indexer, canonical: &Url) -> Option<CanonicalProblem> {
+    let info = match indexer.get_page_info(canonical) {
+        Some(info) => info,
+        None => return None,
+    };
+    match LinkStatus::from_page_info(info) {
+        Some(ref status) if status.is_broken() => {
+            return Some(CanonicalProblem::Broken(status.clone()))
+        }
+        _ => {}
+    }
+    if let Some(redirect) = info.get_redirect() {
+        return Some(CanonicalProblem::Redirect(redirect.clone()));
+    }
+    match info.get_canonical() {
+        Some(other) if other != canonical => return Some(CanonicalProblem::Chain(other.clone())),
+        _ => {}
+    }
+    if info.is_noindex() {
+        return Some(CanonicalProblem::Noindex);
+    }
+    None
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use check::LinkStatus;
+    use hyper::Url;
+    use hyper::client::IntoUrl;
+    use indexer::Indexer;
+    use page::PageInfo;
+    use super::{CanonicalProblem, CanonicalReport};
+
+    fn url(path: &str) -> Url {
+        format!("http://example.com/{}", path).into_url().unwrap()
+    }
+
+    #[test]
+    fn report_conflicts() {
+        let mut indexer = Indexer::new();
+        let pages = vec![("a", Some("a"), 200, None),
+                         ("a?x=1", Some("a"), 200, None),
+                         ("b", Some("gone"), 200, None),
+                         ("gone", None, 404, None),
+                         ("c", Some("moved"), 200, None),
+                         ("moved", None, 200, Some("new")),
+                         ("d", Some("a?x=1"), 200, None),
+                         ("e", Some("uncrawled"), 200, None)];
+        for (path, canonical, status, redirect) in pages {
+            let mut info = PageInfo::new();
+            info.set_status(Some(status));
+            info.set_canonical(canonical.map(url));
+            info.set_redirect(redirect.map(url));
+            indexer.set_page_info(url(path), info);
+        }
+        let report = CanonicalReport::new(&indexer);
+        assert_eq!(report.duplicates.len(), 5);
+        assert_eq!(report.duplicates[0], (url("a"), vec![url("a?x=1")]));
+        let conflicts: Vec<(Url, CanonicalProblem)> = report.conflicts
+            .iter()
+            .map(|c| (c.url.clone(), c.problem.clone()))
+            .collect();
+        assert_eq!(conflicts,
+                   vec![(url("b"), CanonicalProblem::Broken(LinkStatus::Status(404))),
+                        (url("c"), CanonicalProblem::Redirect(url("new"))),
+                        (url("d"), CanonicalProblem::Chain(url("a")))]);
+        let mut text = Vec::new();
+        report.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("Conflict http://example.com/c: canonical http://example.com/moved \
+                               redirects to http://example.com/new\n"));
+        assert!(text.ends_with("5 canonicals, 5 duplicates, 3 conflicts\n"));
+    }
+}
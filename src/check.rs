@@ -0,0 +1,520 @@
+use common::{escape_xml, percent_decode};
+use crawl::fetch::Fetcher;
+use error::*;
+use graph::LinkGraph;
+use hyper::Url;
+use indexer::Indexer;
+use page::PageInfo;
+use serde_json::Value;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// What fetching a link target gave
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkStatus {
+    /// HTTP status of the response
+    Status(u16),
+    /// Why no response was received
+    Error(String),
+}
+
+impl LinkStatus {
+    /// Return the status of a crawled page, if it was fetched
+    pub fn from_page_info(info: &PageInfo) -> Option<LinkStatus> {
+        match (info.get_status(), info.get_error()) {
+            (Some(status), _) => Some(LinkStatus::Status(status)),
+            (None, Some(error)) => Some(LinkStatus::Error(error.to_string())),
+            (None, None) => None,
+        }
+    }
+
+    /// Check if the link is broken, answering an error or no response
+    pub fn is_broken(&self) -> bool {
+        match *self {
+            LinkStatus::Status(status) => status >= 400,
+            LinkStatus::Error(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkStatus::Status(status) => write!(f, "status {}", status),
+            LinkStatus::Error(ref error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// A page linking to a checked url, with the anchor text of the link
+#[derive(Debug, Clone, PartialEq)]
+pub struct Referrer {
+    page: Url,
+    anchor: String,
+}
+
+impl Referrer {
+    /// Return the linking page
+    pub fn get_page(&self) -> &Url {
+        &self.page
+    }
+
+    /// Return the anchor text of the link
+    pub fn get_anchor(&self) -> &str {
+        &self.anchor
+    }
+}
+
+/// A checked url and the pages linking to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkCheck {
+    url: Url,
+    status: LinkStatus,
+    referrers: Vec<Referrer>,
+}
+
+impl LinkCheck {
+    /// Return the checked url
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the status of url
+    pub fn get_status(&self) -> &LinkStatus {
+        &self.status
+    }
+
+    /// Return the pages linking to url
+    pub fn get_referrers(&self) -> &Vec<Referrer> {
+        &self.referrers
+    }
+}
+
+/// A link to a part of a page which has no element with that id or anchor name
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingAnchor {
+    url: Url,
+    fragment: String,
+    referrers: Vec<Referrer>,
+}
+
+impl MissingAnchor {
+    /// Return the linked page
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the fragment missing from the page
+    pub fn get_fragment(&self) -> &str {
+        &self.fragment
+    }
+
+    /// Return the pages linking to the fragment
+    pub fn get_referrers(&self) -> &Vec<Referrer> {
+        &self.referrers
+    }
+}
+
+/// Ask url without its body, asking it again with its body if the server
+/// does not answer HEAD properly
+pub fn check_url(fetcher: &Fetcher, url: &Url) -> LinkStatus {
+    match fetcher.head(url) {
+        Ok(ref response) if response.get_status() < 400 => {
+            return LinkStatus::Status(response.get_status())
+        }
+        _ => {}
+    }
+    match fetcher.get(url) {
+        Ok(response) => LinkStatus::Status(response.get_status()),
+        Err(e) => LinkStatus::Error(e.to_string()),
+    }
+}
+
+/// Check urls on `threads` threads, each one with a fetcher from new_fetcher
+pub fn check_urls<F>(urls: Vec<Url>,
+                     threads: usize,
+                     mut new_fetcher: F)
+                     -> HashMap<Url, LinkStatus>
+    where F: FnMut() -> Box<Fetcher>
+{
+    let urls = Arc::new(Mutex::new(urls));
+    let statuses = Arc::new(Mutex::new(HashMap::new()));
+    let mut handles = Vec::new();
+    for _ in 0..cmp::max(threads, 1) {
+        let fetcher = new_fetcher();
+        let urls = urls.clone();
+        let statuses = statuses.clone();
+        handles.push(thread::spawn(move || loop {
+            let url = match urls.lock() {
+                Ok(mut urls) => urls.pop(),
+                Err(_) => None,
+            };
+            let url = match url {
+                Some(url) => url,
+                None => break,
+            };
+            let status = check_url(&*fetcher, &url);
+            if let Ok(mut statuses) = statuses.lock() {
+                statuses.insert(url, status);
+            }
+        }));
+    }
+    for handle in handles {
+        let _ = handle.join();
+    }
+    let mut statuses = match statuses.lock() {
+        Ok(statuses) => statuses,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    ::std::mem::replace(&mut *statuses, HashMap::new())
+}
+
+/// Return the status of the pages crawled by indexer
+pub fn crawled_statuses(indexer: &Indexer) -> HashMap<Url, LinkStatus> {
+    indexer.get_all_page_infos()
+        .into_iter()
+        .filter_map(|(url, info)| LinkStatus::from_page_info(info).map(|s| (url.clone(), s)))
+        .collect()
+}
+
+/// Return the urls linked in graph whose status is unknown, sorted
+pub fn unchecked_targets(graph: &LinkGraph, statuses: &HashMap<Url, LinkStatus>) -> Vec<Url> {
+    let mut targets: Vec<Url> = graph.get_edges()
+        .iter()
+        .map(|e| graph.get_node(e.get_target()))
+        .filter(|url| !statuses.contains_key(*url))
+        .cloned()
+        .collect();
+    targets.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    targets.dedup();
+    targets
+}
+
+/// Result of a link check, every url crawled or linked with its status,
+/// and the links to missing parts of pages
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkReport {
+    links: Vec<LinkCheck>,
+    missing_anchors: Vec<MissingAnchor>,
+}
+
+impl LinkReport {
+    /// Gather the status of the urls of graph and the pages linking to them
+    pub fn new(graph: &LinkGraph, statuses: &HashMap<Url, LinkStatus>) -> LinkReport {
+        let mut links: BTreeMap<&str, LinkCheck> = statuses.iter()
+            .map(|(url, status)| {
+                (url.as_str(),
+                 LinkCheck {
+                     url: url.clone(),
+                     status: status.clone(),
+                     referrers: Vec::new(),
+                 })
+            })
+            .collect();
+        for edge in graph.get_edges() {
+            let target = graph.get_node(edge.get_target());
+            if let Some(check) = links.get_mut(target.as_str()) {
+                let referrer = Referrer {
+                    page: graph.get_node(edge.get_source()).clone(),
+                    anchor: edge.get_anchor().to_string(),
+                };
+                if !check.referrers.contains(&referrer) {
+                    check.referrers.push(referrer);
+                }
+            }
+        }
+        LinkReport {
+            links: links.into_iter().map(|(_, check)| check).collect(),
+            missing_anchors: Vec::new(),
+        }
+    }
+
+    /// Find the links of graph to a fragment missing from the anchors of the
+    /// target page recorded by indexer
+    ///
+    /// Pages whose anchors were not recorded are not checked. Empty fragments
+    /// and `#top` always point to the top of the page.
+    pub fn add_missing_anchors(&mut self, graph: &LinkGraph, indexer: &Indexer) {
+        let mut missing: BTreeMap<(&str, String), MissingAnchor> = BTreeMap::new();
+        for edge in graph.get_edges() {
+            let fragment = match edge.get_fragment() {
+                Some(f) if !f.is_empty() && f.to_lowercase() != "top" => percent_decode(f),
+                _ => continue,
+            };
+            let target = graph.get_node(edge.get_target());
+            let info = match indexer.get_page_info(target) {
+                Some(info) if info.get_status().map(|s| s < 400).unwrap_or(false) => info,
+                _ => continue,
+            };
+            match info.get_anchors() {
+                Some(anchors) if !anchors.contains(&fragment) => {}
+                _ => continue,
+            }
+            let referrer = Referrer {
+                page: graph.get_node(edge.get_source()).clone(),
+                anchor: edge.get_anchor().to_string(),
+            };
+            let entry = missing.entry((target.as_str(), fragment.clone()))
+                .or_insert_with(|| {
+                    MissingAnchor {
+                        url: target.clone(),
+                        fragment: fragment,
+                        referrers: Vec::new(),
+                    }
+                });
+            if !entry.referrers.contains(&referrer) {
+                entry.referrers.push(referrer);
+            }
+        }
+        self.missing_anchors = missing.into_iter().map(|(_, m)| m).collect();
+    }
+
+    /// Return every url crawled or linked with its status
+    pub fn get_links(&self) -> &Vec<LinkCheck> {
+        &self.links
+    }
+
+    /// Return the links to missing parts of pages
+    pub fn get_missing_anchors(&self) -> &Vec<MissingAnchor> {
+        &self.missing_anchors
+    }
+
+    /// Return the broken links
+    pub fn broken(&self) -> Vec<&LinkCheck> {
+        self.links.iter().filter(|l| l.status.is_broken()).collect()
+    }
+
+    /// Check if no link is broken and no anchor is missing
+    pub fn is_ok(&self) -> bool {
+        self.broken().is_empty() && self.missing_anchors.is_empty()
+    }
+
+    /// Write the broken links and the pages linking to them
+    pub fn write_text<W: Write>(&self, w: &mut W) -> Result<()> {
+        let broken = self.broken();
+        for link in &broken {
+            writeln!(w, "Broken {} ({})", link.url, link.status)?;
+            for referrer in &link.referrers {
+                writeln!(w, "    from {} \"{}\"", referrer.page, referrer.anchor)?;
+            }
+        }
+        for missing in &self.missing_anchors {
+            writeln!(w, "Missing anchor {}#{}", missing.url, missing.fragment)?;
+            for referrer in &missing.referrers {
+                writeln!(w, "    from {} \"{}\"", referrer.page, referrer.anchor)?;
+            }
+        }
+        writeln!(w,
+                 "{} urls checked, {} broken, {} missing anchors",
+                 self.links.len(),
+                 broken.len(),
+                 self.missing_anchors.len())?;
+        Ok(())
+    }
+
+    /// Write the report as JUnit XML, one test case per url
+    pub fn write_junit<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(w,
+                 "<testsuite name=\"unibot check\" tests=\"{}\" failures=\"{}\">",
+                 self.links.len() + self.missing_anchors.len(),
+                 self.broken().len() + self.missing_anchors.len())?;
+        for link in &self.links {
+            let host = link.url.host_str().unwrap_or("");
+            write!(w,
+                   "  <testcase classname=\"{}\" name=\"{}\"",
+                   escape_xml(host),
+                   escape_xml(link.url.as_str()))?;
+            if !link.status.is_broken() {
+                writeln!(w, "/>")?;
+                continue;
+            }
+            writeln!(w, ">")?;
+            writeln!(w,
+                     "    <failure type=\"broken-link\" message=\"{}\">",
+                     escape_xml(&link.status.to_string()))?;
+            write_junit_referrers(w, &link.referrers)?;
+            writeln!(w, "    </failure>")?;
+            writeln!(w, "  </testcase>")?;
+        }
+        for missing in &self.missing_anchors {
+            writeln!(w,
+                     "  <testcase classname=\"{}\" name=\"{}#{}\">",
+                     escape_xml(missing.url.host_str().unwrap_or("")),
+                     escape_xml(missing.url.as_str()),
+                     escape_xml(&missing.fragment))?;
+            writeln!(w,
+                     "    <failure type=\"missing-anchor\" message=\"no element with id {}\">",
+                     escape_xml(&missing.fragment))?;
+            write_junit_referrers(w, &missing.referrers)?;
+            writeln!(w, "    </failure>")?;
+            writeln!(w, "  </testcase>")?;
+        }
+        writeln!(w, "</testsuite>")?;
+        Ok(())
+    }
+
+    /// Return the report as JSON, with the broken links only
+    pub fn to_json(&self) -> Value {
+        let broken: Vec<Value> = self.broken()
+            .iter()
+            .map(|link| {
+                let (status, error) = match link.status {
+                    LinkStatus::Status(s) => (json!(s), Value::Null),
+                    LinkStatus::Error(ref e) => (Value::Null, json!(e)),
+                };
+                json!({
+                    "url": link.url.to_string(),
+                    "status": status,
+                    "error": error,
+                    "referrers": referrers_json(&link.referrers)
+                })
+            })
+            .collect();
+        let missing_anchors: Vec<Value> = self.missing_anchors
+            .iter()
+            .map(|missing| {
+                json!({
+                    "url": missing.url.to_string(),
+                    "fragment": missing.fragment,
+                    "referrers": referrers_json(&missing.referrers)
+                })
+            })
+            .collect();
+        json!({
+            "checked": self.links.len(),
+            "broken": broken,
+            "missing_anchors": missing_anchors
+        })
+    }
+
+    /// Write the report as indented JSON
+    pub fn write_json<W: Write>(&self, w: &mut W) -> Result<()> {
+        ::serde_json::to_writer_pretty(w, &self.to_json())?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+fn write_junit_referrers<W: Write>(w: &mut W, referrers: &[Referrer]) -> Result<()> {
+    for referrer in referrers {
+        writeln!(w,
+                 "Linked from {} \"{}\"",
+                 escape_xml(referrer.page.as_str()),
+                 escape_xml(&referrer.anchor))?;
+    }
+    Ok(())
+}
+
+fn referrers_json(referrers: &[Referrer]) -> Value {
+    Value::Array(referrers.iter()
+        .map(|r| {
+            json!({
+                "page": r.page.to_string(),
+                "anchor": r.anchor
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crawl::fetch::{FetchResponse, Fetcher};
+    use error::*;
+    use graph::LinkGraph;
+    use hyper::Url;
+    use hyper::client::IntoUrl;
+    use hyper::header::Headers;
+    use indexer::Indexer;
+    use page::PageInfo;
+    use scrap::LinkKind;
+    use std::collections::HashMap;
+    use super::{LinkReport, LinkStatus, check_urls, unchecked_targets};
+
+    /// Answer 405 to HEAD, and the status in the path to GET
+    #[derive(Debug)]
+    struct NoHead;
+
+    impl Fetcher for NoHead {
+        fn get(&self, url: &Url) -> Result<FetchResponse> {
+            let status = match url.path().trim_left_matches('/').parse() {
+                Ok(status) => status,
+                Err(_) => bail!(ErrorKind::InvalidResponse("no status".to_string())),
+            };
+            Ok(FetchResponse::new(url.clone(), status, Headers::new(), Vec::new()))
+        }
+
+        fn head(&self, url: &Url) -> Result<FetchResponse> {
+            Ok(FetchResponse::new(url.clone(), 405, Headers::new(), Vec::new()))
+        }
+    }
+
+    fn url(path: &str) -> Url {
+        format!("http://example.com/{}", path).into_url().unwrap()
+    }
+
+    #[test]
+    fn report_broken_links() {
+        let mut graph = LinkGraph::new();
+        graph.add_edge(url(""), url("200"), "ok".to_string(), LinkKind::Anchor);
+        graph.add_edge(url(""), url("404"), "missing".to_string(), LinkKind::Anchor);
+        graph.add_edge(url("a"), url("404"), "gone".to_string(), LinkKind::Image);
+        graph.add_edge(url(""), url("a"), "a".to_string(), LinkKind::Anchor);
+        graph.add_edge(url("a"), url("down"), "down".to_string(), LinkKind::Anchor);
+        let mut statuses = HashMap::new();
+        statuses.insert(url(""), LinkStatus::Status(200));
+        statuses.insert(url("a"), LinkStatus::Status(200));
+        let targets = unchecked_targets(&graph, &statuses);
+        assert_eq!(targets, vec![url("200"), url("404"), url("down")]);
+        statuses.extend(check_urls(targets, 2, || Box::new(NoHead)));
+        let report = LinkReport::new(&graph, &statuses);
+        assert_eq!(report.links.len(), 5);
+        let broken = report.broken();
+        assert_eq!(broken.len(), 2);
+        assert_eq!(broken[0].url, url("404"));
+        assert_eq!(broken[0].status, LinkStatus::Status(404));
+        assert_eq!(broken[0].referrers.len(), 2);
+        assert_eq!(broken[0].referrers[1].anchor, "gone");
+        assert!(broken[1].status.is_broken());
+        assert!(!report.is_ok());
+
+        let mut junit = Vec::new();
+        report.write_junit(&mut junit).unwrap();
+        let junit = String::from_utf8(junit).unwrap();
+        assert!(junit.contains("tests=\"5\" failures=\"2\""));
+        assert!(junit.contains("<failure type=\"broken-link\" message=\"status 404\">"));
+        let json = report.to_json();
+        assert_eq!(json["broken"][0]["referrers"][0]["anchor"], "missing");
+    }
+
+    #[test]
+    fn report_missing_anchors() {
+        let mut graph = LinkGraph::new();
+        let mut indexer = Indexer::new();
+        for &(page, status, anchors) in &[("a", 200, Some(&["intro", "café"][..])),
+                                          ("b", 200, None),
+                                          ("c", 404, Some(&[][..]))] {
+            let mut info = PageInfo::new();
+            info.set_status(Some(status));
+            info.set_anchors(anchors.map(|a| a.iter().map(|a| a.to_string()).collect()));
+            indexer.set_page_info(url(page), info);
+        }
+        for fragment in &["a#intro", "a#nope", "a#caf%C3%A9", "a#top", "a#", "b#x", "c#x"] {
+            graph.add_edge(url(""), url(fragment), fragment.to_string(), LinkKind::Anchor);
+        }
+        graph.add_edge(url("a"), url("a#nope"), "self".to_string(), LinkKind::Anchor);
+        let mut report = LinkReport::new(&graph, &HashMap::new());
+        report.add_missing_anchors(&graph, &indexer);
+        assert_eq!(report.missing_anchors.len(), 1);
+        assert_eq!(report.missing_anchors[0].url, url("a"));
+        assert_eq!(report.missing_anchors[0].fragment, "nope");
+        assert_eq!(report.missing_anchors[0].referrers.len(), 2);
+        assert!(!report.is_ok());
+        let json = report.to_json();
+        assert_eq!(json["missing_anchors"][0]["fragment"], "nope");
+    }
+}
@@ -1,19 +1,9 @@
+use error::*;
 use hyper::Url;
 use hyper::client::IntoUrl;
-
-pub fn beautify_url<S: AsRef<str>>(url: S) -> String {
-    let mut new_url = String::new();
-    let url = url.as_ref();
-    let mut last_char = ' ';
-    for c in url.chars() {
-        if c == '/' && last_char == '/' {
-            continue;
-        }
-        new_url.push(c);
-        last_char = c;
-    }
-    new_url
-}
+use serde_json::{self, Value};
+use std::io::Write;
+use std::sync::Mutex;
 
 /// Convert an href to an url
 pub fn href_to_url(url: &Url, href: &str) -> Option<Url> {
@@ -29,20 +19,171 @@ pub fn href_to_url(url: &Url, href: &str) -> Option<Url> {
             _ => return None,
         }
     } else if href.starts_with('/') {
-        let mut url = url.clone();
-        url.set_path(href);
-        url
-    } else if href.starts_with("javascript") {
+        match url.join(href) {
+            Ok(u) => u,
+            _ => return None,
+        }
+    } else if href.starts_with("javascript") || has_scheme(href) {
+        // mailto:, tel:, data: and other links which are not pages
         return None;
     } else {
         let path = url.path();
         if path.ends_with(href) {
             return None;
         }
-        let mut url = url.clone();
-        let href = beautify_url(format!("{}/{}", url, href));
-        url.set_path(&href);
-        url
+        match url.join(href) {
+            Ok(u) => u,
+            _ => return None,
+        }
     };
     Some(url)
 }
+
+/// Check if href starts with a scheme, like `mailto:`
+fn has_scheme(href: &str) -> bool {
+    match href.find(':') {
+        Some(i) => {
+            let scheme = &href[..i];
+            scheme.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) &&
+            scheme.chars().all(|c| c.is_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+/// Decode the `%XX` escapes of an url part, keeping invalid ones as they are
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(high * 16 + low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Return the name of the file storing the body of url
+pub fn store_file_name(url: &Url) -> String {
+    url.to_string().replace(':', "").replace('/', "_").replace('\\', "_")
+}
+
+/// Hash bytes with 64 bits FNV-1a, stable across runs and platforms
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Escape text to be written in xml
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a field to be written in csv
+pub fn escape_csv(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Convert an HTTP date (`Wed, 21 Oct 2015 07:28:00 GMT`) to a W3C date (`2015-10-21`)
+pub fn http_date_to_w3c(date: &str) -> Option<String> {
+    const MONTHS: [&'static str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug",
+                                        "sep", "oct", "nov", "dec"];
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() < 4 {
+        return None;
+    }
+    let month = parts[2].to_lowercase();
+    let month = match MONTHS.iter().position(|m| *m == month) {
+        Some(m) => m + 1,
+        None => return None,
+    };
+    match (parts[1].parse::<u8>(), parts[3].parse::<u16>()) {
+        (Ok(day), Ok(year)) => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+        _ => None,
+    }
+}
+
+/// Writer shared by crawling threads, writing one JSON value per line
+pub struct JsonLines(Mutex<Box<Write + Send>>);
+
+impl JsonLines {
+    pub fn new(writer: Box<Write + Send>) -> JsonLines {
+        JsonLines(Mutex::new(writer))
+    }
+
+    /// Write value on its own line and flush it, so lines of threads do not mix
+    pub fn write(&self, value: &Value) -> Result<()> {
+        let mut writer = match self.0.lock() {
+            Ok(writer) => writer,
+            Err(e) => bail!(ErrorKind::PoisonError(e.to_string())),
+        };
+        serde_json::to_writer(&mut *writer, value)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use super::{href_to_url, percent_decode};
+
+    #[test]
+    fn resolve_hrefs() {
+        let page = "http://example.com/docs/page.html".into_url().unwrap();
+        let resolve = |href| href_to_url(&page, href).map(|u| u.to_string());
+        assert_eq!(resolve("other.html"), Some("http://example.com/docs/other.html".to_string()));
+        assert_eq!(resolve("../index.html"), Some("http://example.com/index.html".to_string()));
+        assert_eq!(resolve("/a?b=c"), Some("http://example.com/a?b=c".to_string()));
+        assert_eq!(resolve("?page=2"),
+                   Some("http://example.com/docs/page.html?page=2".to_string()));
+        assert_eq!(resolve("#usage"), Some("http://example.com/docs/page.html#usage".to_string()));
+        assert_eq!(resolve("//cdn.example.com/x"), Some("http://cdn.example.com/x".to_string()));
+        assert_eq!(resolve("https://example.org/"), Some("https://example.org/".to_string()));
+        assert_eq!(resolve("mailto:me@example.com"), None);
+        assert_eq!(resolve("javascript:void(0)"), None);
+    }
+
+    #[test]
+    fn decode_percent_escapes() {
+        assert_eq!(percent_decode("caf%C3%A9%20au%20lait"), "café au lait");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+}
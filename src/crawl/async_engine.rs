@@ -0,0 +1,294 @@
+use error::*;
+use futures::{Async, Future, Poll, Stream};
+use futures::stream::FuturesUnordered;
+use futures_cpupool::CpuPool;
+use graph::LinkGraph;
+use hyper::Url;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use super::async_fetch::AsyncFetcher;
+use super::config::CrawlerConfig;
+use super::dns::DnsCache;
+use super::frontier::FrontierEntry;
+use super::politeness::Politeness;
+use super::pool::PoolConfig;
+use super::process;
+use super::sync::{self, SharedIndexer, SharedQueue};
+use tokio_core::reactor::{Core, Handle, Timeout};
+
+/// Time after which a request is abandoned
+const TIMEOUT_SECS: u64 = 30;
+
+/// Maximum number of entries popped and waiting for their host to be free
+const MAX_DEFERRED: usize = 1000;
+
+/// Crawl queued urls until queue is empty or stop is set, return the number of pages crawled.
+///
+/// Requests run on a single reactor thread, up to `config.concurrency()` at
+/// once, while pages are parsed on a pool of `threads` threads. Names are
+/// resolved through `dns`. Requests to a host, or to an address if
+/// `limits.group_by_ip()`, are spaced by `politeness` and at most
+/// `limits.max_per_host()` of them are in flight, entries of a busy host
+/// waiting aside until it is free.
+pub fn crawl(indexer: SharedIndexer,
+             queue: SharedQueue,
+             graph: Arc<Mutex<LinkGraph>>,
+             stop: Arc<AtomicBool>,
+             config: CrawlerConfig,
+             dns: DnsCache,
+             politeness: Arc<Politeness>,
+             limits: PoolConfig,
+             threads: usize,
+             tx: Sender<Url>)
+             -> Result<usize> {
+    let mut core = Core::new()?;
+    let pool = CpuPool::new(if threads == 0 { 1 } else { threads });
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let fetcher = AsyncFetcher::new(core.handle(), dns.clone(), timeout)?;
+    let engine = Engine {
+        handle: core.handle(),
+        fetcher: fetcher,
+        pool: pool,
+        indexer: indexer,
+        queue: queue,
+        graph: graph,
+        stop: stop,
+        config: Arc::new(config),
+        dns: dns,
+        politeness: politeness,
+        limits: limits,
+        tx: tx,
+        in_flight: FuturesUnordered::new(),
+        open: HashMap::new(),
+        deferred: VecDeque::new(),
+        wake: None,
+        next_shard: 0,
+        crawled: 0,
+    };
+    core.run(engine)
+}
+
+/// Future keeping requests in flight until there is nothing left to crawl
+struct Engine {
+    handle: Handle,
+    fetcher: AsyncFetcher,
+    pool: CpuPool,
+    indexer: SharedIndexer,
+    queue: SharedQueue,
+    graph: Arc<Mutex<LinkGraph>>,
+    stop: Arc<AtomicBool>,
+    config: Arc<CrawlerConfig>,
+    dns: DnsCache,
+    politeness: Arc<Politeness>,
+    limits: PoolConfig,
+    tx: Sender<Url>,
+    /// Pages being crawled, resolving to the group of their host
+    in_flight: FuturesUnordered<Box<Future<Item = String, Error = (String, Error)>>>,
+    /// Number of pages in flight per group of hosts
+    open: HashMap<String, usize>,
+    /// Entries popped while their host was busy
+    deferred: VecDeque<FrontierEntry>,
+    /// Timer waking the engine when the slot of a deferred entry is due
+    wake: Option<Timeout>,
+    next_shard: usize,
+    crawled: usize,
+}
+
+impl Engine {
+    /// Return the group whose requests count towards the limit of the host of url
+    fn group_of(&self, url: &Url) -> String {
+        let host = url.host_str().unwrap_or("").to_string();
+        if !self.limits.group_by_ip() {
+            return host;
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        self.dns.resolve_ip(&host, port).map(|ip| ip.to_string()).unwrap_or(host)
+    }
+
+    /// Book a request to the host of url if it is free, else return how long to wait
+    ///
+    /// A zero wait means the host is at its connection limit, until one of its
+    /// pages is done.
+    fn reserve(&self, url: &Url) -> Option<Duration> {
+        let open = self.open.get(&self.group_of(url)).cloned().unwrap_or(0);
+        if open >= self.limits.max_per_host() {
+            return Some(Duration::from_millis(0));
+        }
+        let wait = self.politeness.try_reserve(url);
+        if wait > Duration::from_millis(0) {
+            Some(wait)
+        } else {
+            None
+        }
+    }
+
+    /// Start crawling the next entry whose host is free, return false if there is none
+    ///
+    /// Entries put aside earlier go first. Popped entries whose host is busy
+    /// are put aside, up to `MAX_DEFERRED` of them.
+    fn start_next(&mut self) -> Result<bool> {
+        let mut wait: Option<Duration> = None;
+        let mut i = 0;
+        while i < self.deferred.len() {
+            match self.reserve(self.deferred[i].get_url()) {
+                Some(w) => {
+                    wait = shortest(wait, w);
+                    i += 1;
+                }
+                None => {
+                    if let Some(entry) = self.deferred.remove(i) {
+                        if self.start(entry) {
+                            return Ok(true);
+                        }
+                    }
+                }
+            }
+        }
+        while self.deferred.len() < MAX_DEFERRED {
+            let entry = match sync::pop_queue(&self.queue, self.next_shard) {
+                Ok(entry) => entry,
+                Err(Error(ErrorKind::QueueEmpty, _)) => break,
+                Err(e) => return Err(e),
+            };
+            self.next_shard = self.next_shard.wrapping_add(1);
+            match self.reserve(entry.get_url()) {
+                Some(w) => {
+                    wait = shortest(wait, w);
+                    self.deferred.push_back(entry);
+                }
+                None => {
+                    if self.start(entry) {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+        // Hosts at their connection limit are freed by their pages in flight
+        self.wake = match wait {
+            Some(wait) if wait > Duration::from_millis(0) => {
+                Some(Timeout::new(wait, &self.handle)?)
+            }
+            _ => None,
+        };
+        Ok(false)
+    }
+
+    /// Start crawling entry, return false if its url was already crawled
+    fn start(&mut self, entry: FrontierEntry) -> bool {
+        if process::claim(&self.indexer, entry.get_url()).is_err() {
+            return false;
+        }
+        let group = self.group_of(entry.get_url());
+        *self.open.entry(group.clone()).or_insert(0) += 1;
+        let pool = self.pool.clone();
+        let indexer = self.indexer.clone();
+        let queue = self.queue.clone();
+        let graph = self.graph.clone();
+        let config = self.config.clone();
+        let tx = self.tx.clone();
+        // A page not modified is processed from its stored body
+        let previous = config.previous_manifest()
+            .and_then(|m| m.get_validators(entry.get_url()))
+            .and_then(|p| if config.is_stored(entry.get_url()) { Some(p) } else { None })
+            .cloned();
+        let failed_indexer = self.indexer.clone();
+        let failed_url = entry.get_url().clone();
+        let fetch = self.fetcher
+            .get_conditional(entry.get_url(), previous.as_ref())
+            .map_err(move |e| {
+                let _ = process::record_error(&failed_indexer, &failed_url, &e);
+                e
+            });
+        let page = fetch.and_then(move |response| {
+                // Parsing takes a while, keep it off the reactor thread
+                pool.spawn_fn(move || -> Result<()> {
+                    process::record_response(&indexer,
+                                             entry.get_url(),
+                                             &response,
+                                             previous.as_ref())?;
+                    process::process_response(&config, &indexer, &queue, &graph, &entry, response);
+                    let _ = tx.send(entry.get_url().clone());
+                    Ok(())
+                })
+            })
+            .then(move |page| match page {
+                Ok(()) => Ok(group),
+                Err(e) => Err((group, e)),
+            });
+        self.in_flight.push(Box::new(page));
+        true
+    }
+
+    /// Count a page of group as done
+    fn release(&mut self, group: &str) {
+        if let Some(open) = self.open.get_mut(group) {
+            *open -= 1;
+        }
+    }
+
+    /// Queue again the entries put aside, to crawl them later
+    fn requeue_deferred(&mut self) -> Result<()> {
+        while let Some(entry) = self.deferred.pop_front() {
+            self.queue.lock_url(entry.get_url())?.push(entry);
+        }
+        Ok(())
+    }
+}
+
+/// Return the shortest of wait and w
+fn shortest(wait: Option<Duration>, w: Duration) -> Option<Duration> {
+    match wait {
+        Some(wait) if wait <= w => Some(wait),
+        _ => Some(w),
+    }
+}
+
+impl Future for Engine {
+    type Item = usize;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<usize, Error> {
+        loop {
+            let stop = sync::get_stop(&self.stop);
+            while !stop && self.in_flight.len() < self.config.concurrency() {
+                if !self.start_next()? {
+                    break;
+                }
+            }
+            match self.in_flight.poll() {
+                Ok(Async::Ready(Some(group))) => {
+                    self.release(&group);
+                    self.crawled += 1;
+                    continue;
+                }
+                // A page that could not be crawled does not stop the others
+                Err((group, _e)) => {
+                    self.release(&group);
+                    continue;
+                }
+                Ok(Async::Ready(None)) => {
+                    if stop || self.deferred.is_empty() && sync::is_queue_empty(&self.queue) {
+                        self.requeue_deferred()?;
+                        return Ok(Async::Ready(self.crawled));
+                    }
+                    if self.deferred.is_empty() {
+                        continue;
+                    }
+                }
+                Ok(Async::NotReady) => {}
+            }
+            // Wait for a page to be done, or for the slot of an entry put aside
+            let due = match self.wake {
+                Some(ref mut wake) => wake.poll()?.is_ready(),
+                None => false,
+            };
+            if !due {
+                return Ok(Async::NotReady);
+            }
+            self.wake = None;
+        }
+    }
+}
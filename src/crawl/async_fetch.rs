@@ -0,0 +1,280 @@
+use error::*;
+use futures::{future, Future};
+use futures::future::Loop;
+use futures_cpupool::CpuPool;
+use hyper::header::{Headers, Location};
+use hyper::Url;
+use manifest::ManifestEntry;
+use native_tls::TlsConnector;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use super::dns::DnsCache;
+use super::fetch::{self, FetchResponse};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{read_to_end, write_all};
+use tokio_tls::TlsConnectorExt;
+
+/// Future resolving to a downloaded page
+pub type FetchFuture = Box<Future<Item = FetchResponse, Error = Error>>;
+
+/// Maximum number of redirections followed
+const MAX_REDIRECTS: usize = 5;
+
+/// Number of threads resolving names at once
+const RESOLVE_THREADS: usize = 4;
+
+/// Fetcher downloading pages without blocking, for the async engine.
+///
+/// Each request uses its own HTTP/1.1 connection, closed by the server once
+/// the response is sent. Names are resolved through the dns cache on a pool of
+/// their own, since resolving blocks.
+#[derive(Clone)]
+pub struct AsyncFetcher {
+    handle: Handle,
+    dns: DnsCache,
+    resolver: CpuPool,
+    tls: TlsConnector,
+    timeout: Duration,
+}
+
+impl AsyncFetcher {
+    /// Create a fetcher running requests on the reactor of `handle`
+    pub fn new(handle: Handle, dns: DnsCache, timeout: Duration) -> Result<AsyncFetcher> {
+        let tls = TlsConnector::builder()
+            .and_then(|b| b.build())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(AsyncFetcher {
+            handle: handle,
+            dns: dns,
+            resolver: CpuPool::new(RESOLVE_THREADS),
+            tls: tls,
+            timeout: timeout,
+        })
+    }
+
+    /// Download url, following redirections
+    pub fn get(&self, url: &Url) -> FetchFuture {
+        self.get_conditional(url, None)
+    }
+
+    /// Download url like `get`, unless it did not change since the crawl which found previous
+    pub fn get_conditional(&self, url: &Url, previous: Option<&ManifestEntry>) -> FetchFuture {
+        let fetcher = self.clone();
+        let headers = previous.map(fetch::conditional_headers).unwrap_or_else(Headers::new);
+        Box::new(future::loop_fn((url.clone(), 0), move |(url, redirects)| {
+            // Validators are those of the first url, not of where it redirects
+            let headers = if redirects == 0 {
+                headers.clone()
+            } else {
+                Headers::new()
+            };
+            fetcher.get_once(url, &headers).map(move |response| {
+                match redirect_target(&response) {
+                    Some(target) if redirects < MAX_REDIRECTS => {
+                        Loop::Continue((target, redirects + 1))
+                    }
+                    _ => Loop::Break(response),
+                }
+            })
+        }))
+    }
+
+    /// Download url without following redirections
+    fn get_once(&self, url: Url, headers: &Headers) -> FetchFuture {
+        let host = match url.host_str() {
+            Some(host) => host.to_string(),
+            None => {
+                let e = ErrorKind::InvalidResponse("url has no host".into());
+                return Box::new(future::err(e.into()));
+            }
+        };
+        let port = url.port_or_known_default().unwrap_or(80);
+        let request = request(&url, headers);
+        let handle = self.handle.clone();
+        let name = host.clone();
+        let dns = self.dns.clone();
+        let connected = self.resolver
+            .spawn_fn(move || dns.resolve(&name, port).map(|addrs| (dns, name, addrs)))
+            .and_then(move |(dns, name, addrs)| connect(dns, handle, name, port, addrs))
+            .map_err(Error::from);
+        let raw: Box<Future<Item = Vec<u8>, Error = Error>> = if url.scheme() == "https" {
+            let tls = self.tls.clone();
+            Box::new(connected.and_then(move |tcp| {
+                    tls.connect_async(&host, tcp)
+                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e).into())
+                })
+                .and_then(move |stream| exchange(stream, request)))
+        } else {
+            Box::new(connected.and_then(move |tcp| exchange(tcp, request)))
+        };
+        let response = raw.and_then(move |raw| parse_response(url, &raw));
+        let timeout = match Timeout::new(self.timeout, &self.handle) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(e.into())),
+        };
+        let timeout = timeout.then(|_| -> Result<FetchResponse> {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out").into())
+        });
+        Box::new(response.select(timeout).map(|(r, _)| r).map_err(|(e, _)| e))
+    }
+}
+
+/// Return the GET request of url with headers
+fn request(url: &Url, headers: &Headers) -> Vec<u8> {
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_string(),
+    };
+    let mut request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: unibot\r\nAccept: */*\r\n\
+                               Connection: close\r\n",
+                              path,
+                              host);
+    for header in headers.iter() {
+        request.push_str(&format!("{}: {}\r\n", header.name(), header.value_string()));
+    }
+    request.push_str("\r\n");
+    request.into_bytes()
+}
+
+/// Connect to each address of host in turn until one accepts the connection
+fn connect(dns: DnsCache,
+           handle: Handle,
+           host: String,
+           port: u16,
+           addrs: Vec<SocketAddr>)
+           -> Box<Future<Item = TcpStream, Error = io::Error>> {
+    let error = io::Error::new(io::ErrorKind::NotFound, "no address to connect to");
+    Box::new(future::loop_fn((addrs.into_iter(), error), move |(mut addrs, error)| {
+        let addr = match addrs.next() {
+            Some(addr) => addr,
+            None => return future::Either::A(future::err(error)),
+        };
+        let dns = dns.clone();
+        let host = host.clone();
+        future::Either::B(TcpStream::connect(&addr, &handle).then(move |stream| match stream {
+            Ok(stream) => {
+                dns.set_connected(&host, port, addr);
+                Ok(Loop::Break(stream))
+            }
+            Err(e) => Ok(Loop::Continue((addrs, e))),
+        }))
+    }))
+}
+
+/// Send request on stream and read the whole response
+fn exchange<S>(stream: S, request: Vec<u8>) -> Box<Future<Item = Vec<u8>, Error = Error>>
+    where S: AsyncRead + AsyncWrite + 'static
+{
+    Box::new(write_all(stream, request)
+        .and_then(|(stream, _)| read_to_end(stream, Vec::new()))
+        .map(|(_, raw)| raw)
+        .map_err(Error::from))
+}
+
+/// Return the url a redirection response points to
+fn redirect_target(response: &FetchResponse) -> Option<Url> {
+    match response.get_status() {
+        301 | 302 | 303 | 307 | 308 => {}
+        _ => return None,
+    }
+    response.get_headers()
+        .get::<Location>()
+        .and_then(|location| response.get_url().join(location).ok())
+}
+
+/// Parse a raw HTTP/1.1 response to url
+pub fn parse_response(url: Url, raw: &[u8]) -> Result<FetchResponse> {
+    let end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => end,
+        None => bail!(ErrorKind::InvalidResponse("no end of headers".into())),
+    };
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok());
+    let status = match status {
+        Some(status) => status,
+        None => bail!(ErrorKind::InvalidResponse("bad status line".into())),
+    };
+    let mut headers = Headers::new();
+    for line in lines {
+        if let Some(colon) = line.find(':') {
+            let name = line[..colon].trim().to_string();
+            headers.append_raw(name, line[colon + 1..].trim().as_bytes().to_vec());
+        }
+    }
+    let body = &raw[end + 4..];
+    let chunked = headers.get_raw("Transfer-Encoding")
+        .map(|values| values.iter().any(|v| String::from_utf8_lossy(v).contains("chunked")))
+        .unwrap_or(false);
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok(FetchResponse::new(url, status, headers, body))
+}
+
+/// Decode a body sent with chunked transfer encoding
+fn dechunk(mut raw: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = match raw.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None => bail!(ErrorKind::InvalidResponse("truncated chunk".into())),
+        };
+        let size = String::from_utf8_lossy(&raw[..end]);
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) => size,
+            Err(_) => bail!(ErrorKind::InvalidResponse("bad chunk size".into())),
+        };
+        if size == 0 {
+            return Ok(body);
+        }
+        let start = end + 2;
+        if raw.len() < start + size {
+            bail!(ErrorKind::InvalidResponse("truncated chunk".into()));
+        }
+        body.extend_from_slice(&raw[start..start + size]);
+        raw = &raw[(start + size + 2).min(raw.len())..];
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use hyper::header::LastModified;
+    use super::parse_response;
+
+    #[test]
+    fn parse_plain_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\
+                    Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT\r\n\r\n<html></html>";
+        let url = "http://example.com/".into_url().unwrap();
+        let response = parse_response(url, raw).unwrap();
+        assert_eq!(response.get_status(), 200);
+        assert!(response.get_headers().get::<LastModified>().is_some());
+        assert_eq!(*response.get_body(), b"<html></html>".to_vec());
+    }
+
+    #[test]
+    fn parse_chunked_response() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n";
+        let url = "http://example.com/".into_url().unwrap();
+        let response = parse_response(url, raw).unwrap();
+        assert_eq!(response.get_status(), 404);
+        assert_eq!(*response.get_body(), b"hello, world".to_vec());
+        assert!(parse_response("http://example.com/".into_url().unwrap(), b"HTTP/1.1").is_err());
+    }
+}
@@ -1,14 +1,40 @@
+use common::store_file_name;
 use hyper::Url;
+use manifest::Manifest;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use rules::Scraper;
+use simhash::SimHashIndex;
+use structured::ItemSink;
+use tables::TableSink;
+use std::sync::{Arc, Mutex};
+use super::frontier::Strategy;
+use super::revisit::RevisitConfig;
 
 #[derive(Clone)]
 pub struct CrawlerConfig {
     filter: Arc<Fn(&Url, &Url) -> bool + Send + Sync>,
     store_path: Option<PathBuf>,
     sleep_ms: u64,
+    host_delay_ms: u64,
+    by_ip: bool,
+    record_links: bool,
+    check_anchors: bool,
+    record_metadata: bool,
+    structured_data: Option<Arc<ItemSink>>,
+    scraper: Option<Arc<Scraper>>,
+    tables: Option<Arc<TableSink>>,
+    obey_directives: bool,
+    collapse_duplicates: bool,
+    near_duplicates: Option<Arc<Mutex<SimHashIndex>>>,
+    skip_near_duplicates: bool,
+    strategy: Strategy,
+    frontier_dir: Option<PathBuf>,
+    frontier_window: usize,
+    concurrency: usize,
+    previous: Option<Arc<Manifest>>,
+    revisit: Option<RevisitConfig>,
 }
 
 impl CrawlerConfig {
@@ -17,6 +43,24 @@ impl CrawlerConfig {
             filter: Arc::new(|_, _| true),
             store_path: None,
             sleep_ms: 1000,
+            host_delay_ms: 0,
+            by_ip: false,
+            record_links: false,
+            check_anchors: false,
+            record_metadata: false,
+            structured_data: None,
+            scraper: None,
+            tables: None,
+            obey_directives: true,
+            collapse_duplicates: false,
+            near_duplicates: None,
+            skip_near_duplicates: false,
+            strategy: Strategy::default(),
+            frontier_dir: None,
+            frontier_window: 100000,
+            concurrency: 1000,
+            previous: None,
+            revisit: None,
         }
     }
 
@@ -32,6 +76,89 @@ impl CrawlerConfig {
         self.sleep_ms
     }
 
+    pub fn host_delay_ms(&self) -> u64 {
+        self.host_delay_ms
+    }
+
+    pub fn by_ip(&self) -> bool {
+        self.by_ip
+    }
+
+    pub fn record_links(&self) -> bool {
+        self.record_links
+    }
+
+    pub fn check_anchors(&self) -> bool {
+        self.check_anchors
+    }
+
+    pub fn record_metadata(&self) -> bool {
+        self.record_metadata
+    }
+
+    /// Return the stream receiving the structured data of pages
+    pub fn structured_data(&self) -> Option<&ItemSink> {
+        self.structured_data.as_ref().map(|s| &**s)
+    }
+
+    /// Return the scraper writing records of pages matching its rules
+    pub fn scraper(&self) -> Option<&Scraper> {
+        self.scraper.as_ref().map(|s| &**s)
+    }
+
+    /// Return the directory receiving the data tables of pages as CSV
+    pub fn tables(&self) -> Option<&TableSink> {
+        self.tables.as_ref().map(|t| &**t)
+    }
+
+    pub fn obey_directives(&self) -> bool {
+        self.obey_directives
+    }
+
+    pub fn collapse_duplicates(&self) -> bool {
+        self.collapse_duplicates
+    }
+
+    /// Return the fingerprints of the pages crawled, if near duplicates are detected
+    pub fn near_duplicates(&self) -> Option<&Mutex<SimHashIndex>> {
+        self.near_duplicates.as_ref().map(|i| &**i)
+    }
+
+    pub fn skip_near_duplicates(&self) -> bool {
+        self.skip_near_duplicates
+    }
+
+    pub fn strategy(&self) -> &Strategy {
+        &self.strategy
+    }
+
+    pub fn frontier_dir(&self) -> Option<&Path> {
+        self.frontier_dir.as_ref().map(|p| p.as_path())
+    }
+
+    pub fn frontier_window(&self) -> usize {
+        self.frontier_window
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Return the manifest of the previous crawl
+    pub fn previous_manifest(&self) -> Option<&Manifest> {
+        self.previous.as_ref().map(|m| &**m)
+    }
+
+    /// Return the revisit settings, if crawl is continuous
+    pub fn revisit(&self) -> Option<&RevisitConfig> {
+        self.revisit.as_ref()
+    }
+
+    /// Return the file storing the body of url
+    fn store_file(&self, url: &Url) -> Option<PathBuf> {
+        self.store_path.as_ref().map(|dir_path| dir_path.join(store_file_name(url)))
+    }
+
     pub fn store(&self, url: &Url, body: &[u8]) {
         if let Some(ref dir_path) = self.store_path {
             if !dir_path.exists() {
@@ -40,8 +167,10 @@ impl CrawlerConfig {
                     Err(_) => return,
                 }
             }
-            let url_str = url.to_string().replace(':', "").replace('/', "_").replace('\\', "_");
-            let path = dir_path.join(url_str);
+            let path = match self.store_file(url) {
+                Some(path) => path,
+                None => return,
+            };
             let mut file = match File::create(&path) {
                 Ok(f) => f,
                 Err(_) => return,
@@ -50,6 +179,24 @@ impl CrawlerConfig {
         }
     }
 
+    /// Check if the body of url was stored by this crawl or a previous one
+    pub fn is_stored(&self, url: &Url) -> bool {
+        self.store_file(url).map(|path| path.is_file()).unwrap_or(false)
+    }
+
+    /// Return the body of url stored by this crawl or a previous one
+    pub fn stored(&self, url: &Url) -> Option<Vec<u8>> {
+        let mut file = match self.store_file(url).map(File::open) {
+            Some(Ok(file)) => file,
+            _ => return None,
+        };
+        let mut body = Vec::new();
+        match file.read_to_end(&mut body) {
+            Ok(_) => Some(body),
+            Err(_) => None,
+        }
+    }
+
     pub fn set_filter<F>(mut self, filter: F) -> CrawlerConfig
         where F: 'static + Send + Sync + Fn(&Url, &Url) -> bool
     {
@@ -62,6 +209,112 @@ impl CrawlerConfig {
         self
     }
 
+    /// Set the minimum time between two requests to the same host
+    pub fn set_host_delay_ms(mut self, host_delay_ms: u64) -> CrawlerConfig {
+        self.host_delay_ms = host_delay_ms;
+        self
+    }
+
+    /// Space requests per resolved address rather than per host name
+    pub fn set_by_ip(mut self, by_ip: bool) -> CrawlerConfig {
+        self.by_ip = by_ip;
+        self
+    }
+
+    pub fn set_record_links(mut self, record_links: bool) -> CrawlerConfig {
+        self.record_links = record_links;
+        self
+    }
+
+    /// Record the anchors of pages and the links to them, to check links to fragments
+    pub fn set_check_anchors(mut self, check_anchors: bool) -> CrawlerConfig {
+        self.check_anchors = check_anchors;
+        self
+    }
+
+    /// Record the title, description, headings and other metadata of pages
+    pub fn set_record_metadata(mut self, record_metadata: bool) -> CrawlerConfig {
+        self.record_metadata = record_metadata;
+        self
+    }
+
+    /// Write the JSON-LD, Microdata and RDFa items of pages to sink
+    pub fn set_structured_data(mut self, sink: Option<ItemSink>) -> CrawlerConfig {
+        self.structured_data = sink.map(Arc::new);
+        self
+    }
+
+    /// Write a record of each page matching the rules of scraper
+    pub fn set_scraper(mut self, scraper: Option<Scraper>) -> CrawlerConfig {
+        self.scraper = scraper.map(Arc::new);
+        self
+    }
+
+    /// Write the data tables of pages to sink
+    pub fn set_tables(mut self, sink: Option<TableSink>) -> CrawlerConfig {
+        self.tables = sink.map(Arc::new);
+        self
+    }
+
+    /// Honor noindex and nofollow directives of pages, on by default
+    ///
+    /// Audits may turn it off to store and follow every page.
+    pub fn set_obey_directives(mut self, obey_directives: bool) -> CrawlerConfig {
+        self.obey_directives = obey_directives;
+        self
+    }
+
+    /// Do not store pages declaring another page as their canonical
+    pub fn set_collapse_duplicates(mut self, collapse_duplicates: bool) -> CrawlerConfig {
+        self.collapse_duplicates = collapse_duplicates;
+        self
+    }
+
+    /// Record the SimHash of pages, detecting near duplicates within distance bits
+    ///
+    /// With `skip_links`, links of a near duplicate of a page already crawled are not followed.
+    pub fn set_near_duplicates(mut self,
+                               distance: Option<u32>,
+                               skip_links: bool)
+                               -> CrawlerConfig {
+        self.near_duplicates = distance.map(|d| Arc::new(Mutex::new(SimHashIndex::new(d))));
+        self.skip_near_duplicates = skip_links;
+        self
+    }
+
+    pub fn set_strategy(mut self, strategy: Strategy) -> CrawlerConfig {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Spill frontier to dir beyond `window` urls in memory, strategy is then ignored
+    pub fn set_frontier_dir<P: AsRef<Path>>(mut self,
+                                            dir: Option<P>,
+                                            window: usize)
+                                            -> CrawlerConfig {
+        self.frontier_dir = dir.map(|d| d.as_ref().to_path_buf());
+        self.frontier_window = window;
+        self
+    }
+
+    /// Ask pages of the previous crawl only if they changed since, reusing stored bodies
+    pub fn set_previous_manifest(mut self, previous: Option<Manifest>) -> CrawlerConfig {
+        self.previous = previous.map(Arc::new);
+        self
+    }
+
+    /// Keep crawling once queue is empty, revisiting pages as revisit says
+    pub fn set_revisit(mut self, revisit: Option<RevisitConfig>) -> CrawlerConfig {
+        self.revisit = revisit;
+        self
+    }
+
+    /// Set the number of requests in flight with the async engine
+    pub fn set_concurrency(mut self, concurrency: usize) -> CrawlerConfig {
+        self.concurrency = if concurrency == 0 { 1 } else { concurrency };
+        self
+    }
+
     pub fn set_store_path<P: AsRef<Path>>(mut self, path: Option<P>) -> CrawlerConfig {
         match path {
             Some(path) => self.store_path = Some(path.as_ref().to_path_buf()),
@@ -0,0 +1,472 @@
+use error::*;
+use hyper::Url;
+use std::cmp;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use super::frontier::{Frontier, FrontierEntry};
+
+/// Maximum number of entries in a segment file
+const SEGMENT_ENTRIES: usize = 10000;
+
+/// Name of the file holding the origin of a partition
+const ORIGIN_FILE: &'static str = "origin";
+
+/// Entries of one host spilled to disk.
+///
+/// Entries are written to numbered segment files, each line only holds the
+/// part of the path that differs from the previous line.
+#[derive(Debug)]
+struct Partition {
+    dir: PathBuf,
+    origin: String,
+    segments: VecDeque<u64>,
+    writer: Option<BufWriter<File>>,
+    written: usize,
+    last_path: String,
+    reader: Option<SegmentReader>,
+    len: usize,
+}
+
+/// Oldest segment of a partition being read, with the path of the last line read
+#[derive(Debug)]
+struct SegmentReader {
+    lines: Lines<BufReader<File>>,
+    last_path: String,
+}
+
+/// Return the path, depth and anchor of a segment line, last_path being the path of
+/// the previous line
+fn parse_line(line: &str, last_path: &str) -> (String, usize, String) {
+    let (head, anchor) = match line.find('\t') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    };
+    let mut parts = head.splitn(3, ' ');
+    let shared = parts.next().and_then(|s| s.parse::<usize>().ok()).unwrap_or(0);
+    let depth = parts.next().and_then(|s| s.parse::<usize>().ok()).unwrap_or(0);
+    let suffix = parts.next().unwrap_or("");
+    let shared = cmp::min(shared, last_path.len());
+    (format!("{}{}", &last_path[..shared], suffix), depth, anchor.to_string())
+}
+
+impl Partition {
+    fn create(dir: PathBuf, origin: String) -> Result<Partition> {
+        fs::create_dir_all(&dir)?;
+        let mut file = File::create(dir.join(ORIGIN_FILE))?;
+        writeln!(file, "{}", origin)?;
+        Ok(Partition {
+            dir: dir,
+            origin: origin,
+            segments: VecDeque::new(),
+            writer: None,
+            written: 0,
+            last_path: String::new(),
+            reader: None,
+            len: 0,
+        })
+    }
+
+    fn open(dir: PathBuf) -> Result<Partition> {
+        let mut origin = String::new();
+        BufReader::new(File::open(dir.join(ORIGIN_FILE))?).read_line(&mut origin)?;
+        let origin = origin.trim().to_string();
+        let mut segments = Vec::new();
+        for file in fs::read_dir(&dir)? {
+            let path = file?.path();
+            let seq = match path.extension().and_then(|e| e.to_str()) {
+                Some("seg") => {
+                    path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok())
+                }
+                _ => None,
+            };
+            if let Some(seq) = seq {
+                segments.push(seq);
+            }
+        }
+        segments.sort();
+        let mut partition = Partition {
+            dir: dir,
+            origin: origin,
+            segments: segments.into_iter().collect(),
+            writer: None,
+            written: 0,
+            last_path: String::new(),
+            reader: None,
+            len: 0,
+        };
+        for &seq in &partition.segments {
+            let file = File::open(partition.segment_path(seq))?;
+            partition.len += BufReader::new(file).lines().count();
+        }
+        Ok(partition)
+    }
+
+    fn segment_path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("{:010}.seg", seq))
+    }
+
+    fn write(&mut self, entry: &FrontierEntry) -> Result<()> {
+        let url = entry.get_url().as_str();
+        if !url.starts_with(&self.origin) {
+            bail!(ErrorKind::InvalidFrontier(format!("{} is not in {}", url, self.origin)));
+        }
+        let path = &url[self.origin.len()..];
+        if self.writer.is_none() || self.written >= SEGMENT_ENTRIES {
+            self.close()?;
+            let seq = self.segments.back().map(|s| s + 1).unwrap_or(0);
+            let file = OpenOptions::new().create(true)
+                .append(true)
+                .open(self.segment_path(seq))?;
+            self.segments.push_back(seq);
+            self.writer = Some(BufWriter::new(file));
+        }
+        let shared = common_prefix(&self.last_path, path);
+        let anchor = entry.get_anchor().replace(|c: char| c == '\t' || c == '\n' || c == '\r', " ");
+        if let Some(ref mut writer) = self.writer {
+            writeln!(writer, "{} {} {}\t{}", shared, entry.get_depth(), &path[shared..], anchor)?;
+        }
+        self.last_path = path.to_string();
+        self.written += 1;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        self.written = 0;
+        self.last_path.clear();
+        Ok(())
+    }
+
+    /// Start reading the oldest segment if none is being read, return false if there is none
+    fn open_segment(&mut self) -> Result<bool> {
+        if self.reader.is_some() {
+            return Ok(true);
+        }
+        let seq = match self.segments.front() {
+            Some(s) => *s,
+            None => return Ok(false),
+        };
+        if self.segments.len() == 1 {
+            self.close()?;
+        }
+        let file = File::open(self.segment_path(seq))?;
+        self.reader = Some(SegmentReader {
+            lines: BufReader::new(file).lines(),
+            last_path: String::new(),
+        });
+        Ok(true)
+    }
+
+    /// Read the next entry of the segment being read, removing the segment once
+    /// read. Return None at the end of the segment.
+    fn next_in_segment(&mut self) -> Result<Option<FrontierEntry>> {
+        loop {
+            let line = match self.reader.as_mut().and_then(|r| r.lines.next()) {
+                Some(line) => line?,
+                None => {
+                    self.reader = None;
+                    if let Some(seq) = self.segments.pop_front() {
+                        fs::remove_file(self.segment_path(seq))?;
+                    }
+                    return Ok(None);
+                }
+            };
+            self.len = self.len.saturating_sub(1);
+            let (path, depth, anchor) = match self.reader {
+                Some(ref mut reader) => {
+                    let parsed = parse_line(&line, &reader.last_path);
+                    reader.last_path = parsed.0.clone();
+                    parsed
+                }
+                None => continue,
+            };
+            if let Ok(url) = Url::parse(&format!("{}{}", self.origin, path)) {
+                let mut entry = FrontierEntry::new(url);
+                entry.set_depth(depth);
+                entry.set_anchor(anchor);
+                return Ok(Some(entry));
+            }
+        }
+    }
+
+    /// Read up to max entries, oldest first
+    fn read(&mut self, max: usize) -> Result<Vec<FrontierEntry>> {
+        let mut entries = Vec::new();
+        while entries.len() < max && self.open_segment()? {
+            if let Some(entry) = self.next_in_segment()? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Read the rest of the segment being read, if any, so no segment is left half read
+    fn finish_segment(&mut self) -> Result<Vec<FrontierEntry>> {
+        let mut entries = Vec::new();
+        if self.reader.is_some() {
+            while let Some(entry) = self.next_in_segment()? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    let mut len = 0;
+    for (ca, cb) in a.chars().zip(b.chars()) {
+        if ca != cb {
+            break;
+        }
+        len += ca.len_utf8();
+    }
+    len
+}
+
+/// Return origin of url (`scheme://host:port`) and the name of its partition
+fn origin(url: &Url) -> (String, String) {
+    let host = url.host_str().unwrap_or("");
+    let origin = match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    };
+    let name = origin.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    (origin, name)
+}
+
+/// Frontier keeping a bounded window of entries in memory and spilling the
+/// rest to segment files on disk, partitioned by host.
+///
+/// Memory is refilled with up to a window of entries of one host at a time,
+/// taking hosts in turn. A url is queued once in memory, but nothing is kept
+/// in memory about spilled urls, so memory stays flat whatever the size of the
+/// frontier: a url queued again before being crawled may be spilled twice, the
+/// crawler skipping it once indexed. Spilled segments survive the process:
+/// opening the same directory again resumes the frontier, and `checkpoint`
+/// spills the window too.
+#[derive(Debug)]
+pub struct DiskFrontier {
+    dir: PathBuf,
+    window: usize,
+    memory: VecDeque<FrontierEntry>,
+    memory_urls: HashSet<Url>,
+    partitions: BTreeMap<String, Partition>,
+    turn: VecDeque<String>,
+}
+
+impl DiskFrontier {
+    /// Open frontier in dir, resuming entries spilled by a previous crawl
+    pub fn open<P: AsRef<Path>>(dir: P, window: usize) -> Result<DiskFrontier> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut partitions = BTreeMap::new();
+        let mut turn = VecDeque::new();
+        for file in fs::read_dir(&dir)? {
+            let path = file?.path();
+            if !path.join(ORIGIN_FILE).exists() {
+                continue;
+            }
+            let partition = Partition::open(path)?;
+            let (_, name) = match Url::parse(&partition.origin) {
+                Ok(u) => origin(&u),
+                Err(_) => continue,
+            };
+            if partition.len > 0 {
+                turn.push_back(name.clone());
+            }
+            partitions.insert(name, partition);
+        }
+        Ok(DiskFrontier {
+            dir: dir,
+            window: if window == 0 { 1 } else { window },
+            memory: VecDeque::new(),
+            memory_urls: HashSet::new(),
+            partitions: partitions,
+            turn: turn,
+        })
+    }
+
+    /// Return the number of entries spilled to disk
+    pub fn spilled(&self) -> usize {
+        self.partitions.values().map(|p| p.len).sum()
+    }
+
+    fn spill(&mut self, entry: &FrontierEntry) -> Result<()> {
+        let (origin, name) = origin(entry.get_url());
+        if !self.partitions.contains_key(&name) {
+            let partition = Partition::create(self.dir.join(&name), origin)?;
+            self.partitions.insert(name.clone(), partition);
+        }
+        let partition = self.partitions.get_mut(&name).unwrap();
+        if partition.len == 0 {
+            self.turn.push_back(name);
+        }
+        partition.write(entry)
+    }
+
+    /// Load the next entries of the next host in memory, up to the window
+    fn refill(&mut self) -> Result<()> {
+        let max = cmp::max(self.window.saturating_sub(self.memory.len()), 1);
+        while let Some(name) = self.turn.pop_front() {
+            let (entries, remaining) = match self.partitions.get_mut(&name) {
+                Some(p) => (p.read(max)?, p.len),
+                None => continue,
+            };
+            if remaining > 0 {
+                self.turn.push_back(name);
+            }
+            if entries.is_empty() {
+                continue;
+            }
+            for entry in entries {
+                if self.memory_urls.insert(entry.get_url().clone()) {
+                    self.memory.push_back(entry);
+                }
+            }
+            return Ok(());
+        }
+        Ok(())
+    }
+}
+
+impl Frontier for DiskFrontier {
+    fn push(&mut self, entry: FrontierEntry) {
+        if self.memory_urls.contains(entry.get_url()) {
+            return;
+        }
+        if self.memory.len() < self.window && self.turn.is_empty() {
+            self.memory_urls.insert(entry.get_url().clone());
+            self.memory.push_back(entry);
+            return;
+        }
+        if self.spill(&entry).is_err() && self.memory_urls.insert(entry.get_url().clone()) {
+            // Keep entry in memory rather than losing it
+            self.memory.push_back(entry);
+        }
+    }
+
+    fn pop(&mut self) -> Option<FrontierEntry> {
+        if self.memory.is_empty() {
+            let _ = self.refill();
+        }
+        let entry = self.memory.pop_front();
+        if let Some(ref e) = entry {
+            self.memory_urls.remove(e.get_url());
+        }
+        entry
+    }
+
+    /// Check if url is queued in memory, spilled urls being unknown
+    fn contains(&self, url: &Url) -> bool {
+        self.memory_urls.contains(url)
+    }
+
+    fn len(&self) -> usize {
+        self.memory.len() + self.spilled()
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        self.memory.iter().map(|e| e.get_url().clone()).collect()
+    }
+
+    /// Spill all entries to disk and flush segments
+    fn checkpoint(&mut self) -> Result<()> {
+        // The lines of a segment being read are read again on resume, spill them anew
+        for partition in self.partitions.values_mut() {
+            for entry in partition.finish_segment()? {
+                if self.memory_urls.insert(entry.get_url().clone()) {
+                    self.memory.push_back(entry);
+                }
+            }
+        }
+        while let Some(entry) = self.memory.pop_front() {
+            self.memory_urls.remove(entry.get_url());
+            self.spill(&entry)?;
+        }
+        for partition in self.partitions.values_mut() {
+            partition.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskFrontier {
+    fn drop(&mut self) {
+        for partition in self.partitions.values_mut() {
+            let _ = partition.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use std::env;
+    use std::fs;
+    use super::DiskFrontier;
+    use super::super::frontier::{Frontier, FrontierEntry};
+
+    fn entry(url: &str, depth: usize) -> FrontierEntry {
+        let mut entry = FrontierEntry::new(url.into_url().unwrap());
+        entry.set_depth(depth);
+        entry.set_anchor("some\tanchor".to_string());
+        entry
+    }
+
+    #[test]
+    fn spill_and_resume() {
+        let dir = env::temp_dir().join("unibot_disk_frontier_test");
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let mut frontier = DiskFrontier::open(&dir, 2).unwrap();
+            frontier.push(entry("http://a.com/", 0));
+            frontier.push(entry("http://a.com/docs/1", 1));
+            frontier.push(entry("http://a.com/docs/2", 2));
+            frontier.push(entry("http://b.com:8080/x?q=1", 1));
+            frontier.push(entry("http://a.com/docs/10", 2));
+            frontier.push(entry("http://a.com/docs/1", 3));
+            assert!(frontier.contains(&"http://a.com/docs/1".into_url().unwrap()));
+            assert_eq!(frontier.len(), 5);
+            assert_eq!(frontier.spilled(), 3);
+            assert_eq!(frontier.pop().unwrap().get_url().as_str(), "http://a.com/");
+            frontier.checkpoint().unwrap();
+            assert_eq!(frontier.spilled(), 4);
+        }
+
+        let mut urls = Vec::new();
+        {
+            // Refills are capped at the window, the rest of the segment stays on disk
+            let mut frontier = DiskFrontier::open(&dir, 2).unwrap();
+            assert_eq!(frontier.len(), 4);
+            urls.push(frontier.pop().unwrap().get_url().to_string());
+            assert!(frontier.memory.len() <= 1);
+            assert_eq!(frontier.len(), 3);
+            frontier.checkpoint().unwrap();
+        }
+
+        let mut frontier = DiskFrontier::open(&dir, 2).unwrap();
+        assert_eq!(frontier.len(), 3);
+        while let Some(e) = frontier.pop() {
+            if e.get_url().as_str() == "http://a.com/docs/10" {
+                assert_eq!(e.get_depth(), 2);
+                assert_eq!(e.get_anchor(), "some anchor");
+            }
+            urls.push(e.get_url().to_string());
+        }
+        urls.sort();
+        assert_eq!(urls,
+                   vec!["http://a.com/docs/1",
+                        "http://a.com/docs/10",
+                        "http://a.com/docs/2",
+                        "http://b.com:8080/x?q=1"]);
+        assert!(frontier.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
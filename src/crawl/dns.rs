@@ -0,0 +1,333 @@
+use error::*;
+use hyper;
+use hyper::net::{HttpStream, NetworkConnector};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Function resolving a host and port to its addresses
+pub type Resolver = Arc<Fn(&str, u16) -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+/// Counters of a DNS cache.
+///
+/// `lookups` - Names resolved by the resolver
+///
+/// `hits` - Names found in the cache or in the overrides
+///
+/// `failures` - Lookups which failed, cached as negative entries
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DnsStats {
+    lookups: usize,
+    hits: usize,
+    failures: usize,
+}
+
+impl DnsStats {
+    /// Return the number of names resolved by the resolver
+    pub fn get_lookups(&self) -> usize {
+        self.lookups
+    }
+
+    /// Return the number of names found in the cache or in the overrides
+    pub fn get_hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Return the number of lookups which failed
+    pub fn get_failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl fmt::Display for DnsStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} DNS lookups, {} cache hits, {} failures",
+               self.lookups,
+               self.hits,
+               self.failures)
+    }
+}
+
+type Key = (String, u16);
+
+#[derive(Debug)]
+struct DnsEntry {
+    /// Addresses of the name, the last one connected to first, empty if resolution failed
+    addrs: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+struct DnsState {
+    entries: HashMap<Key, DnsEntry>,
+    overrides: HashMap<Key, SocketAddr>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    stats: DnsStats,
+}
+
+/// DNS cache shared by slaves.
+///
+/// Addresses are kept `ttl`, failed resolutions `negative_ttl`, so a dead
+/// host is not looked up again for each of its queued urls. Overrides always
+/// win, like `curl --resolve`. The address last connected to comes first, so
+/// grouping by IP follows the address requests actually go to.
+#[derive(Clone)]
+pub struct DnsCache {
+    state: Arc<Mutex<DnsState>>,
+    resolver: Resolver,
+}
+
+impl DnsCache {
+    /// Create a cache using the system resolver
+    pub fn new() -> DnsCache {
+        DnsCache::with_resolver(Arc::new(|host: &str, port: u16| {
+            (host, port).to_socket_addrs().map(|addrs| addrs.collect())
+        }))
+    }
+
+    /// Create a cache using resolver
+    pub fn with_resolver(resolver: Resolver) -> DnsCache {
+        DnsCache {
+            state: Arc::new(Mutex::new(DnsState {
+                entries: HashMap::new(),
+                overrides: HashMap::new(),
+                ttl: Duration::from_secs(300),
+                negative_ttl: Duration::from_secs(30),
+                stats: DnsStats::default(),
+            })),
+            resolver: resolver,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<DnsState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Set how long resolved and failed names are cached
+    pub fn set_ttl(&self, ttl: Duration, negative_ttl: Duration) {
+        let mut state = self.state();
+        state.ttl = ttl;
+        state.negative_ttl = negative_ttl;
+    }
+
+    /// Resolve host and port to addr, whatever the resolver says
+    pub fn add_override(&self, host: &str, port: u16, addr: SocketAddr) {
+        self.state().overrides.insert((host.to_lowercase(), port), addr);
+    }
+
+    /// Return the counters of the cache
+    pub fn stats(&self) -> DnsStats {
+        self.state().stats
+    }
+
+    /// Return the addresses to try in turn to connect to host and port
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let key = (host.to_lowercase(), port);
+        {
+            let mut state = self.state();
+            if let Some(addr) = state.overrides.get(&key).cloned() {
+                state.stats.hits += 1;
+                return Ok(vec![addr]);
+            }
+            let cached = state.entries.get(&key).and_then(|e| if e.expires > Instant::now() {
+                Some(e.addrs.clone())
+            } else {
+                None
+            });
+            if let Some(addrs) = cached {
+                state.stats.hits += 1;
+                if addrs.is_empty() {
+                    return Err(not_found("cached failure to resolve host"));
+                }
+                return Ok(addrs);
+            }
+        }
+        // Resolving blocks, let other slaves use the cache meanwhile
+        let addrs = (self.resolver)(host, port).unwrap_or_else(|_| Vec::new());
+        let mut state = self.state();
+        state.stats.lookups += 1;
+        let ttl = if addrs.is_empty() {
+            state.stats.failures += 1;
+            state.negative_ttl
+        } else {
+            state.ttl
+        };
+        state.entries.insert(key,
+                             DnsEntry {
+                                 addrs: addrs.clone(),
+                                 expires: Instant::now() + ttl,
+                             });
+        if addrs.is_empty() {
+            return Err(not_found("failed to resolve host"));
+        }
+        Ok(addrs)
+    }
+
+    /// Record that host and port were reached at addr, to try it first from now on
+    pub fn set_connected(&self, host: &str, port: u16, addr: SocketAddr) {
+        let key = (host.to_lowercase(), port);
+        let mut state = self.state();
+        if let Some(entry) = state.entries.get_mut(&key) {
+            if let Some(i) = entry.addrs.iter().position(|a| *a == addr) {
+                let addr = entry.addrs.remove(i);
+                entry.addrs.insert(0, addr);
+            }
+        }
+    }
+
+    /// Return the address host is reached at, or None if it does not resolve
+    pub fn resolve_ip(&self, host: &str, port: u16) -> Option<IpAddr> {
+        self.resolve(host, port).ok().and_then(|addrs| addrs.first().map(|addr| addr.ip()))
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DnsCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DnsCache")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+fn not_found(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, message)
+}
+
+/// Parse a `host:port:addr` override, addr being an IPv4 or a bracketed IPv6 address
+pub fn parse_override(s: &str) -> Result<(String, u16, SocketAddr)> {
+    let mut parts = s.splitn(3, ':');
+    let host = parts.next().unwrap_or("");
+    let port = parts.next().and_then(|p| p.parse::<u16>().ok());
+    let addr = parts.next()
+        .map(|a| a.trim_matches(|c| c == '[' || c == ']'))
+        .and_then(|a| a.parse::<IpAddr>().ok());
+    match (port, addr) {
+        (Some(port), Some(addr)) if !host.is_empty() => {
+            Ok((host.to_string(), port, SocketAddr::new(addr, port)))
+        }
+        _ => bail!(ErrorKind::InvalidResolve(s.to_string())),
+    }
+}
+
+/// Connector opening TCP connections to the addresses of a DNS cache
+#[derive(Debug, Clone)]
+pub struct DnsConnector {
+    dns: DnsCache,
+}
+
+impl DnsConnector {
+    pub fn new(dns: DnsCache) -> DnsConnector {
+        DnsConnector { dns: dns }
+    }
+}
+
+impl NetworkConnector for DnsConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for Http")
+                .into());
+        }
+        let mut error = not_found("no address to connect to");
+        for addr in self.dns.resolve(host, port)? {
+            match TcpStream::connect(addr) {
+                Ok(stream) => {
+                    self.dns.set_connected(host, port, addr);
+                    return Ok(HttpStream(stream));
+                }
+                Err(e) => error = e,
+            }
+        }
+        Err(error.into())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::net::NetworkConnector;
+    use std::io;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use super::{DnsCache, DnsConnector, parse_override};
+
+    fn counting_cache(calls: Arc<AtomicUsize>) -> DnsCache {
+        DnsCache::with_resolver(Arc::new(move |host: &str, port: u16| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            if host == "dead.test" {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such host"));
+            }
+            Ok(vec![format!("10.0.0.1:{}", port).parse().unwrap()])
+        }))
+    }
+
+    #[test]
+    fn cache_and_negative_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let dns = counting_cache(calls.clone());
+        assert_eq!(dns.resolve("a.test", 80).unwrap(),
+                   vec!["10.0.0.1:80".parse().unwrap()]);
+        dns.resolve("A.test", 80).unwrap();
+        assert!(dns.resolve("dead.test", 80).is_err());
+        assert!(dns.resolve("dead.test", 80).is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let stats = dns.stats();
+        assert_eq!((stats.lookups, stats.hits, stats.failures), (2, 2, 1));
+
+        dns.set_ttl(Duration::from_millis(0), Duration::from_millis(0));
+        dns.resolve("b.test", 80).unwrap();
+        dns.resolve("b.test", 80).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn overrides() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let dns = counting_cache(calls.clone());
+        let (host, port, addr) = parse_override("example.com:443:127.0.0.1").unwrap();
+        dns.add_override(&host, port, addr);
+        assert_eq!(dns.resolve("example.com", 443).unwrap(),
+                   vec!["127.0.0.1:443".parse().unwrap()]);
+        dns.resolve("example.com", 80).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (_, _, addr) = parse_override("example.com:8080:[::1]").unwrap();
+        assert_eq!(addr, "[::1]:8080".parse().unwrap());
+        assert!(parse_override("example.com:80").is_err());
+        assert!(parse_override("example.com:http:127.0.0.1").is_err());
+        assert!(parse_override(":80:127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn connect_to_reachable_address() {
+        // Only 127.0.0.1 listens, 127.0.0.2 refuses connections to the port
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let dns = DnsCache::with_resolver(Arc::new(|_: &str, port: u16| {
+            Ok(vec![format!("127.0.0.2:{}", port).parse().unwrap(),
+                    format!("127.0.0.1:{}", port).parse().unwrap()])
+        }));
+        assert_eq!(dns.resolve("multi.test", port).unwrap().len(), 2);
+        assert_eq!(dns.resolve_ip("multi.test", port), Some("127.0.0.2".parse().unwrap()));
+
+        DnsConnector::new(dns.clone()).connect("multi.test", port, "http").unwrap();
+        assert_eq!(dns.resolve_ip("multi.test", port), Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(dns.resolve("multi.test", port).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,348 @@
+use common::hash_bytes;
+use error::*;
+use hyper;
+use hyper::client::Client;
+use hyper::header::{ContentType, Headers};
+use hyper::method::Method;
+use hyper::net::HttpsConnector;
+use hyper::Url;
+use hyper_native_tls::NativeTlsClient;
+use manifest::ManifestEntry;
+use std::fmt::Debug;
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+use super::dns::{DnsCache, DnsConnector};
+use super::pool::{ConnectionPool, PoolConfig};
+
+/// Pool of HTTP and HTTPS connections
+pub type HttpsPool = ConnectionPool<HttpsConnector<NativeTlsClient, DnsConnector>>;
+
+/// A downloaded page.
+///
+/// `url` - Final url of the page, after redirections
+///
+/// `status` - HTTP status code
+///
+/// `headers` - Response headers
+///
+/// `body` - Response body
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+    url: Url,
+    status: u16,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl FetchResponse {
+    /// Create a response to a request of url, redirected or not
+    pub fn new(url: Url, status: u16, headers: Headers, body: Vec<u8>) -> FetchResponse {
+        FetchResponse {
+            url: url,
+            status: status,
+            headers: headers,
+            body: body,
+        }
+    }
+
+    /// Return the url of the response, after redirects
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the HTTP status code
+    pub fn get_status(&self) -> u16 {
+        self.status
+    }
+
+    /// Return the response headers
+    pub fn get_headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Return the response body
+    pub fn get_body(&self) -> &Vec<u8> {
+        &self.body
+    }
+
+    /// Return the response body, consuming the response
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// Check if status is a success (2xx)
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+}
+
+/// Download pages for a slave
+pub trait Fetcher: Debug + Send {
+    /// Download url
+    fn get(&self, url: &Url) -> Result<FetchResponse>;
+
+    /// Download url unless it did not change since the crawl which found previous,
+    /// answering 304 then
+    fn get_conditional(&self, url: &Url, _previous: &ManifestEntry) -> Result<FetchResponse> {
+        self.get(url)
+    }
+
+    /// Ask url without its body, the body of the response is empty
+    fn head(&self, url: &Url) -> Result<FetchResponse> {
+        self.get(url)
+    }
+}
+
+/// Fetcher downloading pages over HTTP and HTTPS
+#[derive(Debug)]
+pub struct HyperFetcher {
+    client: Client,
+}
+
+impl HyperFetcher {
+    /// Create a fetcher with its own connection pool and DNS cache
+    pub fn new() -> HyperFetcher {
+        HyperFetcher::with_pool(new_pool(PoolConfig::new(), DnsCache::new()))
+    }
+
+    /// Create a fetcher sending requests on connections of pool
+    pub fn with_pool(pool: HttpsPool) -> HyperFetcher {
+        HyperFetcher { client: Client::with_connector(pool) }
+    }
+
+    /// Give up on responses not read or requests not written within timeout
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.client.set_read_timeout(timeout);
+        self.client.set_write_timeout(timeout);
+    }
+
+    fn send(&self,
+            method: Method,
+            url: &Url,
+            headers: &Headers)
+            -> Result<hyper::client::Response> {
+        match self.client.request(method.clone(), url.clone()).headers(headers.clone()).send() {
+            // The server closed an idle connection, send again on another one
+            Err(hyper::Error::Io(ref e)) if e.kind() == io::ErrorKind::ConnectionAborted => {
+                Ok(self.client.request(method, url.clone()).headers(headers.clone()).send()?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    fn get_with(&self, url: &Url, headers: &Headers) -> Result<FetchResponse> {
+        let mut response = self.send(Method::Get, url, headers)?;
+        let mut body = Vec::new();
+        response.read_to_end(&mut body)?;
+        Ok(FetchResponse::new(response.url.clone(),
+                              response.status.to_u16(),
+                              response.headers.clone(),
+                              body))
+    }
+}
+
+/// Create a pool of HTTP and HTTPS connections to hosts resolved with dns
+pub fn new_pool(config: PoolConfig, dns: DnsCache) -> HttpsPool {
+    let ssl = NativeTlsClient::new().unwrap();
+    let connector = HttpsConnector::with_connector(ssl, DnsConnector::new(dns.clone()));
+    ConnectionPool::with_dns(connector, config, Some(dns))
+}
+
+impl Default for HyperFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetcher for HyperFetcher {
+    fn get(&self, url: &Url) -> Result<FetchResponse> {
+        self.get_with(url, &Headers::new())
+    }
+
+    fn get_conditional(&self, url: &Url, previous: &ManifestEntry) -> Result<FetchResponse> {
+        self.get_with(url, &conditional_headers(previous))
+    }
+
+    fn head(&self, url: &Url) -> Result<FetchResponse> {
+        let response = self.send(Method::Head, url, &Headers::new())?;
+        Ok(FetchResponse::new(response.url.clone(),
+                              response.status.to_u16(),
+                              response.headers.clone(),
+                              Vec::new()))
+    }
+}
+
+/// Return the headers asking a page only if it changed since previous
+pub fn conditional_headers(previous: &ManifestEntry) -> Headers {
+    let mut headers = Headers::new();
+    if let Some(etag) = previous.get_etag() {
+        headers.set_raw("If-None-Match", vec![etag.as_bytes().to_vec()]);
+    }
+    if let Some(last_modified) = previous.get_last_modified() {
+        headers.set_raw("If-Modified-Since", vec![last_modified.as_bytes().to_vec()]);
+    }
+    headers
+}
+
+/// Fetcher serving generated pages, to test and benchmark crawls without network.
+///
+/// Pages are `http://host<h>.test/` and `http://host<h>.test/page<p>`. Each
+/// page links to the next one of its host and to pseudo random pages, one
+/// link out of four going to another host.
+#[derive(Debug, Clone)]
+pub struct FakeFetcher {
+    hosts: usize,
+    pages: usize,
+    links: usize,
+    latency: Duration,
+}
+
+impl FakeFetcher {
+    /// Create a fetcher serving `pages` pages with `links` links on each of `hosts` hosts
+    pub fn new(hosts: usize, pages: usize, links: usize) -> FakeFetcher {
+        FakeFetcher {
+            hosts: if hosts == 0 { 1 } else { hosts },
+            pages: if pages == 0 { 1 } else { pages },
+            links: links,
+            latency: Duration::from_millis(0),
+        }
+    }
+
+    /// Wait latency before answering each request
+    pub fn set_latency(mut self, latency: Duration) -> FakeFetcher {
+        self.latency = latency;
+        self
+    }
+
+    /// Return the url of page p of host h
+    pub fn page_url(&self, h: usize, p: usize) -> Url {
+        let url = if p == 0 {
+            format!("http://host{}.test/", h)
+        } else {
+            format!("http://host{}.test/page{}", h, p)
+        };
+        Url::parse(&url).unwrap()
+    }
+
+    /// Return the number of pages served
+    pub fn len(&self) -> usize {
+        self.hosts * self.pages
+    }
+
+    /// Check if no page is served, which never happens
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn parse(&self, url: &Url) -> Option<(usize, usize)> {
+        let host = url.host_str().unwrap_or("");
+        if !host.starts_with("host") || !host.ends_with(".test") {
+            return None;
+        }
+        let h = match host[4..host.len() - 5].parse::<usize>() {
+            Ok(h) if h < self.hosts => h,
+            _ => return None,
+        };
+        let p = match url.path() {
+            "/" => 0,
+            path if path.starts_with("/page") => {
+                match path[5..].parse::<usize>() {
+                    Ok(p) if p > 0 && p < self.pages => p,
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some((h, p))
+    }
+
+    fn page(&self, h: usize, p: usize) -> String {
+        let mut html = format!("<html><head><title>Page {} of host {}</title></head><body>",
+                               p,
+                               h);
+        let next = self.page_url(h, (p + 1) % self.pages);
+        html.push_str(&format!("<a href=\"{}\">next</a>", next));
+        for i in 1..self.links {
+            let hash = hash_bytes(format!("{}/{}/{}", h, p, i).as_bytes()) as usize;
+            let target_host = if i % 4 == 0 { hash % self.hosts } else { h };
+            let target = self.page_url(target_host, (hash >> 16) % self.pages);
+            html.push_str(&format!("<a href=\"{}\">link {}</a>", target, i));
+        }
+        html.push_str("</body></html>");
+        html
+    }
+}
+
+impl Fetcher for FakeFetcher {
+    fn get(&self, url: &Url) -> Result<FetchResponse> {
+        if self.latency > Duration::from_millis(0) {
+            thread::sleep(self.latency);
+        }
+        let mut headers = Headers::new();
+        headers.set(ContentType::html());
+        let (status, body) = match self.parse(url) {
+            Some((h, p)) => {
+                let body = self.page(h, p).into_bytes();
+                let etag = format!("\"{:016x}\"", hash_bytes(&body));
+                headers.set_raw("ETag", vec![etag.into_bytes()]);
+                (200, body)
+            }
+            None => (404, Vec::new()),
+        };
+        Ok(FetchResponse::new(url.clone(), status, headers, body))
+    }
+
+    /// Pages never change, answer 304 to the ETag sent with them
+    fn get_conditional(&self, url: &Url, previous: &ManifestEntry) -> Result<FetchResponse> {
+        let mut response = self.get(url)?;
+        let etag = response.headers
+            .get_raw("ETag")
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).into_owned());
+        if response.is_success() && etag.is_some() &&
+           etag.as_ref().map(|s| s.as_str()) == previous.get_etag() {
+            response.status = 304;
+            response.body.clear();
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use manifest::ManifestEntry;
+    use super::{FakeFetcher, Fetcher};
+
+    #[test]
+    fn fake_pages() {
+        let fetcher = FakeFetcher::new(4, 10, 8);
+        let page = fetcher.get(&"http://host3.test/page9".into_url().unwrap()).unwrap();
+        assert_eq!(page.status, 200);
+        let body = String::from_utf8(page.body).unwrap();
+        assert!(body.contains("<a href=\"http://host3.test/\">next</a>"));
+        assert_eq!(body.matches("<a ").count(), 8);
+        for url in &["http://host4.test/", "http://host0.test/page10", "http://example.com/"] {
+            let page = fetcher.get(&(*url).into_url().unwrap()).unwrap();
+            assert_eq!(page.status, 404);
+        }
+    }
+
+    #[test]
+    fn fake_not_modified() {
+        let fetcher = FakeFetcher::new(1, 2, 1);
+        let url = fetcher.page_url(0, 1);
+        let page = fetcher.get(&url).unwrap();
+        let etag = page.headers
+            .get_raw("ETag")
+            .map(|v| String::from_utf8_lossy(&v[0]).into_owned());
+        let mut previous = ManifestEntry::default();
+        previous.set_etag(Some("\"other\"".to_string()));
+        assert_eq!(fetcher.get_conditional(&url, &previous).unwrap().status, 200);
+        previous.set_etag(etag);
+        let page = fetcher.get_conditional(&url, &previous).unwrap();
+        assert_eq!(page.status, 304);
+        assert!(page.body.is_empty());
+    }
+}
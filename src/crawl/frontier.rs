@@ -0,0 +1,568 @@
+use error::*;
+use hyper::Url;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+/// An url waiting to be crawled.
+///
+/// `url` - Url to crawl
+///
+/// `depth` - Number of links followed from a seed to reach url
+///
+/// `anchor` - Anchor text of the link that led to url
+///
+/// `backlinks` - Number of times url was found while it was queued
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrontierEntry {
+    url: Url,
+    depth: usize,
+    anchor: String,
+    backlinks: usize,
+}
+
+impl FrontierEntry {
+    /// Create a seed entry
+    pub fn new(url: Url) -> FrontierEntry {
+        FrontierEntry {
+            url: url,
+            depth: 0,
+            anchor: String::new(),
+            backlinks: 0,
+        }
+    }
+
+    /// Create an entry for a link found on this entry page
+    pub fn child(&self, url: Url, anchor: String) -> FrontierEntry {
+        FrontierEntry {
+            url: url,
+            depth: self.depth + 1,
+            anchor: anchor,
+            backlinks: 0,
+        }
+    }
+
+    /// Return the url to crawl
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the number of links followed from a seed to reach url
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Set the number of links followed from a seed to reach url
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// Return the anchor text of the link that led to url
+    pub fn get_anchor(&self) -> &str {
+        &self.anchor
+    }
+
+    /// Set the anchor text of the link that led to url
+    pub fn set_anchor(&mut self, anchor: String) {
+        self.anchor = anchor;
+    }
+
+    /// Return the number of times url was found while it was queued
+    pub fn get_backlinks(&self) -> usize {
+        self.backlinks
+    }
+}
+
+/// Urls waiting to be crawled, in the order they should be crawled.
+///
+/// Pushing an url that is already queued does not queue it twice.
+pub trait Frontier: fmt::Debug + Send {
+    /// Queue an entry
+    fn push(&mut self, entry: FrontierEntry);
+
+    /// Return the next entry to crawl
+    fn pop(&mut self) -> Option<FrontierEntry>;
+
+    /// Check if url is queued
+    fn contains(&self, url: &Url) -> bool;
+
+    /// Return the number of queued urls
+    fn len(&self) -> usize;
+
+    /// Check if no url is queued
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove and return all entries, in crawl order
+    fn drain(&mut self) -> Vec<FrontierEntry> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.pop() {
+            entries.push(entry);
+        }
+        entries
+    }
+
+    /// Return all queued urls
+    fn urls(&self) -> Vec<Url>;
+
+    /// Return the priority of the next entry, frontiers sharing a crawl popping
+    /// the entry of highest priority first
+    ///
+    /// None if frontier is empty, or if its order does not span several
+    /// frontiers, like a per-host round robin.
+    fn head_priority(&mut self) -> Option<f64> {
+        None
+    }
+
+    /// Save queued entries so they can be resumed, if frontier supports it
+    fn checkpoint(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Scoring function of best-first strategy, highest score is crawled first
+pub type Scorer = Arc<Fn(&FrontierEntry) -> f64 + Send + Sync>;
+
+/// Order in which urls are crawled
+#[derive(Clone)]
+pub enum Strategy {
+    /// Crawl urls in discovery order
+    BreadthFirst,
+    /// Crawl last discovered urls first
+    DepthFirst,
+    /// Crawl urls with the highest score first
+    BestFirst(Scorer),
+    /// Crawl one url of each host in turn
+    HostRoundRobin,
+}
+
+impl Strategy {
+    /// Return the strategy from its name (`bfs`, `dfs`, `best` or `host`)
+    ///
+    /// Best-first strategy uses the `score_depth` scorer.
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "bfs" => Some(Strategy::BreadthFirst),
+            "dfs" => Some(Strategy::DepthFirst),
+            "best" => Some(Strategy::BestFirst(score_depth())),
+            "host" => Some(Strategy::HostRoundRobin),
+            _ => None,
+        }
+    }
+
+    /// Create an empty frontier using this strategy
+    pub fn frontier(&self) -> Box<Frontier> {
+        match *self {
+            Strategy::BreadthFirst => Box::new(FifoFrontier::new()),
+            Strategy::DepthFirst => Box::new(FifoFrontier::new_lifo()),
+            Strategy::BestFirst(ref scorer) => Box::new(BestFirstFrontier::new(scorer.clone())),
+            Strategy::HostRoundRobin => Box::new(RoundRobinFrontier::new()),
+        }
+    }
+}
+
+impl fmt::Debug for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Strategy::BreadthFirst => write!(f, "BreadthFirst"),
+            Strategy::DepthFirst => write!(f, "DepthFirst"),
+            Strategy::BestFirst(_) => write!(f, "BestFirst"),
+            Strategy::HostRoundRobin => write!(f, "HostRoundRobin"),
+        }
+    }
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::BreadthFirst
+    }
+}
+
+/// Score shallow urls higher
+pub fn score_depth() -> Scorer {
+    Arc::new(|entry| -(entry.depth as f64))
+}
+
+/// Score urls by the number of links to them
+pub fn score_backlinks() -> Scorer {
+    Arc::new(|entry| entry.backlinks as f64)
+}
+
+/// Score urls containing a pattern with its weight
+pub fn score_patterns(patterns: Vec<(String, f64)>) -> Scorer {
+    Arc::new(move |entry| {
+        let url = entry.url.as_str();
+        patterns.iter().filter(|&&(ref p, _)| url.contains(p.as_str())).map(|&(_, w)| w).sum()
+    })
+}
+
+/// Score urls by the number of keywords in their anchor text
+pub fn score_keywords(keywords: Vec<String>) -> Scorer {
+    let keywords: Vec<String> = keywords.iter().map(|k| k.to_lowercase()).collect();
+    Arc::new(move |entry| {
+        let anchor = entry.anchor.to_lowercase();
+        keywords.iter().filter(|k| anchor.contains(k.as_str())).count() as f64
+    })
+}
+
+/// Score urls with known scores, for example PageRank of a previous crawl
+pub fn score_known(scores: HashMap<Url, f64>) -> Scorer {
+    Arc::new(move |entry| scores.get(&entry.url).cloned().unwrap_or(0.0))
+}
+
+/// Score urls with the weighted sum of scorers
+pub fn score_sum(scorers: Vec<(Scorer, f64)>) -> Scorer {
+    Arc::new(move |entry| scorers.iter().map(|&(ref s, w)| w * s(entry)).sum())
+}
+
+/// Breadth-first (FIFO) or depth-first (LIFO) frontier
+#[derive(Debug, Default)]
+pub struct FifoFrontier {
+    queue: VecDeque<FrontierEntry>,
+    urls: HashSet<Url>,
+    lifo: bool,
+}
+
+impl FifoFrontier {
+    pub fn new() -> FifoFrontier {
+        FifoFrontier::default()
+    }
+
+    pub fn new_lifo() -> FifoFrontier {
+        FifoFrontier { lifo: true, ..FifoFrontier::default() }
+    }
+}
+
+impl Frontier for FifoFrontier {
+    fn push(&mut self, entry: FrontierEntry) {
+        if self.urls.insert(entry.url.clone()) {
+            self.queue.push_back(entry);
+        }
+    }
+
+    fn pop(&mut self) -> Option<FrontierEntry> {
+        let entry = if self.lifo {
+            self.queue.pop_back()
+        } else {
+            self.queue.pop_front()
+        };
+        if let Some(ref e) = entry {
+            self.urls.remove(&e.url);
+        }
+        entry
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        self.urls.contains(url)
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        let mut urls: Vec<Url> = self.queue.iter().map(|e| e.url.clone()).collect();
+        if self.lifo {
+            urls.reverse();
+        }
+        urls
+    }
+
+    /// Lowest depth first for breadth-first, highest depth first for depth-first
+    fn head_priority(&mut self) -> Option<f64> {
+        if self.lifo {
+            self.queue.back().map(|e| e.depth as f64)
+        } else {
+            self.queue.front().map(|e| -(e.depth as f64))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Scored {
+    score: f64,
+    seq: u64,
+    entry: FrontierEntry,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Scored) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    // Highest score first, then first queued
+    fn cmp(&self, other: &Scored) -> Ordering {
+        match self.score.partial_cmp(&other.score).unwrap_or(Ordering::Equal) {
+            Ordering::Equal => other.seq.cmp(&self.seq),
+            o => o,
+        }
+    }
+}
+
+/// Frontier crawling urls with the highest score first.
+///
+/// When an url is pushed again, its backlinks count is incremented and it
+/// is scored again. Older scores are dropped once they outnumber the urls queued.
+pub struct BestFirstFrontier {
+    heap: BinaryHeap<Scored>,
+    queued: HashMap<Url, FrontierEntry>,
+    scorer: Scorer,
+    seq: u64,
+}
+
+impl BestFirstFrontier {
+    pub fn new(scorer: Scorer) -> BestFirstFrontier {
+        BestFirstFrontier {
+            heap: BinaryHeap::new(),
+            queued: HashMap::new(),
+            scorer: scorer,
+            seq: 0,
+        }
+    }
+
+    /// Keep only the latest score of each queued url if most scores are stale
+    fn compact(&mut self) {
+        if self.heap.len() <= 2 * self.queued.len() {
+            return;
+        }
+        let mut latest: HashMap<Url, Scored> = HashMap::new();
+        for scored in mem::replace(&mut self.heap, BinaryHeap::new()).into_vec() {
+            if !self.queued.contains_key(&scored.entry.url) {
+                continue;
+            }
+            if latest.get(&scored.entry.url).map(|s| s.seq < scored.seq).unwrap_or(true) {
+                latest.insert(scored.entry.url.clone(), scored);
+            }
+        }
+        self.heap = latest.into_iter().map(|(_, scored)| scored).collect();
+    }
+}
+
+impl fmt::Debug for BestFirstFrontier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BestFirstFrontier")
+            .field("queued", &self.queued.len())
+            .finish()
+    }
+}
+
+impl Frontier for BestFirstFrontier {
+    fn push(&mut self, mut entry: FrontierEntry) {
+        if let Some(queued) = self.queued.get_mut(&entry.url) {
+            queued.backlinks += 1;
+            entry = queued.clone();
+        } else {
+            self.queued.insert(entry.url.clone(), entry.clone());
+        }
+        self.seq += 1;
+        self.heap.push(Scored {
+            score: (self.scorer)(&entry),
+            seq: self.seq,
+            entry: entry,
+        });
+        self.compact();
+    }
+
+    fn pop(&mut self) -> Option<FrontierEntry> {
+        while let Some(scored) = self.heap.pop() {
+            // Older scores of an url pushed several times are skipped
+            if let Some(entry) = self.queued.remove(&scored.entry.url) {
+                self.compact();
+                return Some(entry);
+            }
+        }
+        None
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        self.queued.contains_key(url)
+    }
+
+    fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        let mut heap: Vec<&Scored> =
+            self.heap.iter().filter(|s| self.queued.contains_key(&s.entry.url)).collect();
+        heap.sort_by(|a, b| b.cmp(a));
+        let mut seen = HashSet::new();
+        heap.into_iter()
+            .map(|s| &s.entry.url)
+            .filter(|u| seen.insert(*u))
+            .cloned()
+            .collect()
+    }
+
+    /// Score of the next entry
+    fn head_priority(&mut self) -> Option<f64> {
+        // Scores of urls already popped are dropped
+        while self.heap.peek().map(|s| !self.queued.contains_key(&s.entry.url)).unwrap_or(false) {
+            self.heap.pop();
+        }
+        self.heap.peek().map(|s| s.score)
+    }
+}
+
+/// Frontier crawling one url of each host in turn
+#[derive(Debug, Default)]
+pub struct RoundRobinFrontier {
+    hosts: VecDeque<String>,
+    queues: HashMap<String, VecDeque<FrontierEntry>>,
+    urls: HashSet<Url>,
+}
+
+impl RoundRobinFrontier {
+    pub fn new() -> RoundRobinFrontier {
+        RoundRobinFrontier::default()
+    }
+}
+
+impl Frontier for RoundRobinFrontier {
+    fn push(&mut self, entry: FrontierEntry) {
+        if !self.urls.insert(entry.url.clone()) {
+            return;
+        }
+        let host = entry.url.host_str().unwrap_or("").to_string();
+        if !self.queues.contains_key(&host) {
+            self.hosts.push_back(host.clone());
+        }
+        self.queues.entry(host).or_insert_with(VecDeque::new).push_back(entry);
+    }
+
+    fn pop(&mut self) -> Option<FrontierEntry> {
+        let host = match self.hosts.pop_front() {
+            Some(h) => h,
+            None => return None,
+        };
+        let (entry, empty) = match self.queues.get_mut(&host) {
+            Some(queue) => (queue.pop_front(), queue.is_empty()),
+            None => (None, true),
+        };
+        if empty {
+            self.queues.remove(&host);
+        } else {
+            self.hosts.push_back(host);
+        }
+        if let Some(ref e) = entry {
+            self.urls.remove(&e.url);
+        }
+        entry
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        self.urls.contains(url)
+    }
+
+    fn len(&self) -> usize {
+        self.urls.len()
+    }
+
+    fn urls(&self) -> Vec<Url> {
+        self.hosts
+            .iter()
+            .filter_map(|h| self.queues.get(h))
+            .flat_map(|q| q.iter().map(|e| e.url.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use super::{BestFirstFrontier, Frontier, FrontierEntry, Strategy, score_backlinks,
+                score_keywords, score_sum};
+
+    fn entry(url: &str, depth: usize, anchor: &str) -> FrontierEntry {
+        let mut entry = FrontierEntry::new(url.into_url().unwrap());
+        entry.depth = depth;
+        entry.anchor = anchor.to_string();
+        entry
+    }
+
+    fn pop_all(frontier: &mut Box<Frontier>) -> Vec<String> {
+        frontier.drain().into_iter().map(|e| e.url.to_string()).collect()
+    }
+
+    fn push_all(frontier: &mut Box<Frontier>) {
+        frontier.push(entry("http://a.com/1", 0, ""));
+        frontier.push(entry("http://a.com/2", 1, "cheap rust books"));
+        frontier.push(entry("http://b.com/1", 2, "rust"));
+        frontier.push(entry("http://a.com/1", 3, ""));
+    }
+
+    #[test]
+    fn breadth_first() {
+        let mut frontier = Strategy::BreadthFirst.frontier();
+        push_all(&mut frontier);
+        assert_eq!(frontier.len(), 3);
+        assert!(frontier.contains(&"http://b.com/1".into_url().unwrap()));
+        assert_eq!(pop_all(&mut frontier),
+                   vec!["http://a.com/1", "http://a.com/2", "http://b.com/1"]);
+    }
+
+    #[test]
+    fn depth_first() {
+        let mut frontier = Strategy::DepthFirst.frontier();
+        push_all(&mut frontier);
+        assert_eq!(pop_all(&mut frontier),
+                   vec!["http://b.com/1", "http://a.com/2", "http://a.com/1"]);
+    }
+
+    #[test]
+    fn best_first_by_depth() {
+        let mut frontier = Strategy::from_name("best").unwrap().frontier();
+        frontier.push(entry("http://a.com/deep", 2, ""));
+        frontier.push(entry("http://a.com/top", 0, ""));
+        frontier.push(entry("http://a.com/mid", 1, ""));
+        assert_eq!(pop_all(&mut frontier),
+                   vec!["http://a.com/top", "http://a.com/mid", "http://a.com/deep"]);
+    }
+
+    #[test]
+    fn best_first_by_backlinks_and_keywords() {
+        let scorer = score_sum(vec![(score_backlinks(), 1.0),
+                                    (score_keywords(vec!["Rust".to_string()]), 0.5)]);
+        let mut frontier = Strategy::BestFirst(scorer).frontier();
+        push_all(&mut frontier);
+        assert_eq!(frontier.len(), 3);
+        assert_eq!(frontier.pop().unwrap().url.as_str(), "http://a.com/1");
+        let urls: Vec<String> = frontier.urls().iter().map(|u| u.to_string()).collect();
+        assert_eq!(urls, vec!["http://a.com/2", "http://b.com/1"]);
+        assert_eq!(pop_all(&mut frontier), vec!["http://a.com/2", "http://b.com/1"]);
+        assert!(frontier.is_empty());
+    }
+
+    #[test]
+    fn best_first_drops_stale_scores() {
+        let mut frontier = BestFirstFrontier::new(score_backlinks());
+        for _ in 0..5 {
+            frontier.push(entry("http://a.com/1", 0, ""));
+        }
+        frontier.push(entry("http://a.com/2", 0, ""));
+        assert!(frontier.heap.len() <= 2 * frontier.len());
+        assert_eq!(frontier.pop().unwrap().backlinks, 4);
+        assert_eq!(frontier.heap.len(), 1);
+    }
+
+    #[test]
+    fn host_round_robin() {
+        let mut frontier = Strategy::HostRoundRobin.frontier();
+        push_all(&mut frontier);
+        assert_eq!(pop_all(&mut frontier),
+                   vec!["http://a.com/1", "http://b.com/1", "http://a.com/2"]);
+    }
+}
@@ -1,78 +1,206 @@
+#[cfg(feature = "async")]
+mod async_engine;
+#[cfg(feature = "async")]
+pub mod async_fetch;
 pub mod config;
+pub mod disk_frontier;
+pub mod dns;
+pub mod fetch;
+pub mod frontier;
+pub mod politeness;
+pub mod pool;
+pub mod revisit;
+pub mod shard;
+mod process;
 mod slave;
 mod sync;
 
 use error::*;
+use graph::LinkGraph;
 use hyper::client::IntoUrl;
 use hyper::Url;
 use indexer::Indexer;
-use std::collections::VecDeque;
+use manifest::Manifest;
+use sitemap;
+use std::collections::{HashMap, VecDeque};
+use std::mem;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::mpsc::{self, Receiver};
 use std::thread;
+use std::time::Duration;
 
 use self::config::CrawlerConfig;
+use self::disk_frontier::DiskFrontier;
+use self::dns::{DnsCache, DnsStats};
+use self::fetch::{Fetcher, HttpsPool, HyperFetcher};
+use self::frontier::{Frontier, Strategy};
+use self::politeness::Politeness;
+use self::revisit::RevisitScheduler;
+use self::pool::{PoolConfig, PoolStats};
+use self::shard::{DEFAULT_SHARDS, Sharded};
 use self::slave::CrawlerSlave;
+use self::sync::{SharedIndexer, SharedQueue};
 
 // Add settings to go deeper or else
 #[derive(Debug)]
 pub struct Crawler {
     slaves: Vec<CrawlerSlave>,
-    indexer: Arc<Mutex<Indexer>>,
-    queue: Arc<Mutex<VecDeque<Url>>>,
+    pool: HttpsPool,
+    dns: DnsCache,
+    politeness: Arc<Politeness>,
+    revisits: Arc<Mutex<RevisitScheduler>>,
+    indexer: SharedIndexer,
+    queue: SharedQueue,
+    graph: Arc<Mutex<LinkGraph>>,
     running: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
     stop: Arc<AtomicBool>,
+    failure: Arc<Mutex<Option<Error>>>,
 }
 
 impl Crawler {
     pub fn new() -> Crawler {
+        Crawler::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Create a crawler splitting indexer and queue into `shards` shards
+    pub fn with_shards(shards: usize) -> Crawler {
+        let dns = DnsCache::new();
         let mut crawler = Crawler {
             slaves: Vec::new(),
-            indexer: Arc::new(Mutex::new(Indexer::new())),
-            queue: Arc::new(Mutex::new(VecDeque::new())),
+            pool: fetch::new_pool(PoolConfig::new(), dns.clone()),
+            politeness: Arc::new(Politeness::new(dns.clone())),
+            dns: dns,
+            revisits: Arc::new(Mutex::new(RevisitScheduler::default())),
+            indexer: Arc::new(Sharded::new(shards, Indexer::new)),
+            queue: Arc::new(Sharded::new(shards, || Strategy::default().frontier())),
+            graph: Arc::new(Mutex::new(LinkGraph::new())),
             running: Arc::new(AtomicUsize::new(0)),
+            active: Arc::new(AtomicUsize::new(0)),
             stop: Arc::new(AtomicBool::new(false)),
+            failure: Arc::new(Mutex::new(None)),
         };
-        crawler.add_slave();
+        let fetcher = HyperFetcher::with_pool(crawler.pool.clone());
+        crawler.add_slave(Box::new(fetcher));
         crawler
     }
 
     /// Add a slave to slaves list
-    fn add_slave(&mut self) {
+    fn add_slave(&mut self, fetcher: Box<Fetcher>) {
+        let id = self.slaves.len();
         let indexer = self.indexer();
         let queue = self.queue();
+        let graph = self.graph();
         let running = self.running();
+        let active = self.active.clone();
         let stop = self.stop();
-        self.slaves.push(CrawlerSlave::new_shared(indexer, queue, running, stop));
+        let mut slave =
+            CrawlerSlave::new_shared(fetcher, indexer, queue, graph, running, active, stop);
+        slave.set_first_shard(id);
+        slave.set_politeness(self.politeness.clone());
+        slave.set_revisits(self.revisits.clone());
+        self.slaves.push(slave);
     }
 
-    /// Create a set of new slave
+    /// Create a set of new slave, all of them sharing the connection pool
     pub fn create_slaves(&mut self, number: usize) {
+        let pool = self.pool.clone();
+        self.create_slaves_with(number, || Box::new(HyperFetcher::with_pool(pool.clone())));
+    }
+
+    /// Change the settings of the connection pool
+    pub fn set_pool_config(&mut self, config: PoolConfig) {
+        self.pool.set_config(config);
+    }
+
+    /// Return the counters of the connection pool
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Return the DNS cache shared by slaves, to set its TTL or add overrides
+    pub fn dns(&self) -> DnsCache {
+        self.dns.clone()
+    }
+
+    /// Return the counters of the DNS cache
+    pub fn dns_stats(&self) -> DnsStats {
+        self.dns.stats()
+    }
+
+    /// Create a set of new slave, each one downloading pages with a new fetcher
+    pub fn create_slaves_with<F>(&mut self, number: usize, mut new_fetcher: F)
+        where F: FnMut() -> Box<Fetcher>
+    {
         self.slaves.clear();
         for _ in 0..number {
-            self.add_slave();
+            self.add_slave(new_fetcher());
         }
         if self.slaves.is_empty() {
-            self.add_slave();
+            self.add_slave(new_fetcher());
         }
     }
 
     /// Return a copy of indexer
-    pub fn indexer(&self) -> Arc<Mutex<Indexer>> {
+    pub fn indexer(&self) -> SharedIndexer {
         self.indexer.clone()
     }
 
+    /// Replace indexer shards, for example by ones with a seen filter
+    pub fn set_indexer<F>(&mut self, mut new_indexer: F) -> Result<()>
+        where F: FnMut() -> Result<Indexer>
+    {
+        for i in 0..self.indexer.len() {
+            *self.indexer.lock(i)? = new_indexer()?;
+        }
+        Ok(())
+    }
+
+    /// Take all indexer shards, merged into a single indexer
+    pub fn take_indexer(&mut self) -> Result<Indexer> {
+        let mut indexer = Indexer::new();
+        for i in 0..self.indexer.len() {
+            let shard = mem::replace(&mut *self.indexer.lock(i)?, Indexer::new());
+            indexer.absorb(shard);
+        }
+        Ok(indexer)
+    }
+
+    /// Return the manifest of pages crawled, to re-crawl them conditionally
+    pub fn manifest(&self) -> Result<Manifest> {
+        let mut manifest = Manifest::new();
+        for i in 0..self.indexer.len() {
+            manifest.add_indexer(&*self.indexer.lock(i)?);
+        }
+        Ok(manifest)
+    }
+
     /// Return a copy of queue
-    pub fn queue(&self) -> Arc<Mutex<VecDeque<Url>>> {
+    pub fn queue(&self) -> SharedQueue {
         self.queue.clone()
     }
 
+    /// Return a copy of link graph
+    pub fn graph(&self) -> Arc<Mutex<LinkGraph>> {
+        self.graph.clone()
+    }
+
+    /// Return a copy of the revisit schedule of continuous crawls
+    pub fn revisits(&self) -> Arc<Mutex<RevisitScheduler>> {
+        self.revisits.clone()
+    }
+
     /// Return a copy of running
     pub fn running(&self) -> Arc<AtomicUsize> {
         self.running.clone()
     }
 
+    /// Take the error which stopped the last async crawl, if any
+    pub fn take_failure(&self) -> Option<Error> {
+        sync::lock(&self.failure).ok().and_then(|mut failure| failure.take())
+    }
+
     /// Return a copy of stop
     pub fn stop(&self) -> Arc<AtomicBool> {
         self.stop.clone()
@@ -83,6 +211,36 @@ impl Crawler {
         sync::add_to_queue(&self.indexer, &self.queue, url)
     }
 
+    /// Add to queue all urls listed in the sitemaps of site
+    ///
+    /// If `prioritize` is set, urls are queued by priority then by last
+    /// modification date. Return the number of urls listed. Sitemaps are
+    /// fetched by the first slave, there must be one.
+    pub fn add_sitemaps<U: IntoUrl>(&mut self, site: U, prioritize: bool) -> Result<usize> {
+        let site = site.into_url()?;
+        let mut entries = match self.slaves.first() {
+            Some(slave) => sitemap::discover(&site, |url| slave.fetch(url)),
+            None => bail!(ErrorKind::NoSlaves),
+        };
+        if prioritize {
+            sitemap::prioritize(&mut entries);
+        }
+        for entry in &entries {
+            sync::add_to_queue(&self.indexer, &self.queue, entry.get_loc().clone())?;
+        }
+        Ok(entries.len())
+    }
+
+    /// Order queue by score, for example PageRank of a previous crawl
+    pub fn order_queue(&mut self, scores: &HashMap<Url, f64>) -> Result<()> {
+        sync::order_queue(&self.queue, scores)
+    }
+
+    /// Save queued urls so a later crawl can resume them
+    pub fn checkpoint(&mut self) -> Result<()> {
+        sync::checkpoint_queue(&self.queue)
+    }
+
     /// Get all items from queue
     pub fn queue_items(&self) -> Result<VecDeque<Url>> {
         sync::queue_items(&self.queue)
@@ -108,8 +266,33 @@ impl Crawler {
         self.slaves[0].crawl()
     }
 
+    /// Set up queue shards with the frontier asked by config
+    fn prepare_queue(&mut self, config: &CrawlerConfig) -> Result<()> {
+        match config.frontier_dir() {
+            Some(dir) => {
+                // Each shard spills its hosts to its own directory
+                let window = config.frontier_window() / self.queue.len() + 1;
+                sync::set_frontiers(&self.queue, |i| {
+                        let dir = dir.join(format!("shard-{:02}", i));
+                        let frontier: Box<Frontier> = Box::new(DiskFrontier::open(dir, window)?);
+                        Ok(frontier)
+                    })?;
+            }
+            None => sync::set_strategy(&self.queue, config.strategy())?,
+        }
+        Ok(())
+    }
+
     /// Crawl site recursively until queue is empty with a filter
+    ///
+    /// If config has revisit settings, crawl continues until stop is set,
+    /// revisiting crawled pages.
     pub fn crawl_recursive(&mut self, config: &CrawlerConfig) -> Result<Vec<Receiver<Url>>> {
+        self.prepare_queue(config)?;
+        if let Some(revisit) = config.revisit() {
+            sync::lock(&self.revisits)?.set_config(revisit.clone());
+        }
+        self.politeness.configure(Duration::from_millis(config.host_delay_ms()), config.by_ip());
         let mut rxs = Vec::new();
         while let Some(mut slave) = self.slaves.pop() {
             sync::add_running(&self.running);
@@ -120,6 +303,53 @@ impl Crawler {
         }
         Ok(rxs)
     }
+
+    /// Crawl site recursively like `crawl_recursive`, with the async engine
+    ///
+    /// Up to `config.concurrency()` requests are in flight on one thread, pages
+    /// being parsed on as many threads as there are slaves, names resolved
+    /// through the dns cache of the crawler. Requests are spaced by the host
+    /// delay of config and limited per host like the connection pool. Sleep
+    /// time and revisits of config are not applied. An error stopping the
+    /// crawl is kept for `take_failure`.
+    #[cfg(feature = "async")]
+    pub fn crawl_async(&mut self, config: &CrawlerConfig) -> Result<Vec<Receiver<Url>>> {
+        self.prepare_queue(config)?;
+        sync::set_stop(&self.stop, false);
+        let (tx, rx) = mpsc::channel();
+        let indexer = self.indexer();
+        let queue = self.queue();
+        let graph = self.graph();
+        let stop = self.stop();
+        let running = self.running();
+        let config = config.clone();
+        let dns = self.dns();
+        self.politeness.configure(Duration::from_millis(config.host_delay_ms()), config.by_ip());
+        let politeness = self.politeness.clone();
+        let limits = self.pool.config();
+        let threads = self.slaves.len();
+        let failure = self.failure.clone();
+        sync::add_running(&running);
+        thread::spawn(move || {
+            let crawled = async_engine::crawl(indexer,
+                                              queue,
+                                              graph,
+                                              stop,
+                                              config,
+                                              dns,
+                                              politeness,
+                                              limits,
+                                              threads,
+                                              tx);
+            if let Err(e) = crawled {
+                if let Ok(mut failure) = sync::lock(&failure) {
+                    *failure = Some(e);
+                }
+            }
+            sync::remove_running(&running);
+        });
+        Ok(vec![rx])
+    }
 }
 
 impl Default for Crawler {
@@ -0,0 +1,158 @@
+use hyper::Url;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::dns::DnsCache;
+
+#[derive(Debug)]
+struct PolitenessState {
+    delay: Duration,
+    by_ip: bool,
+    next: HashMap<String, Instant>,
+}
+
+/// Spacing of requests to a host shared by slaves.
+///
+/// When grouping by IP, hosts resolving to the same address share their
+/// spacing, so a server with thousands of virtual hosts is not overloaded.
+#[derive(Debug)]
+pub struct Politeness {
+    dns: DnsCache,
+    state: Mutex<PolitenessState>,
+}
+
+impl Politeness {
+    /// Create a politeness resolving hosts with dns, without delay
+    pub fn new(dns: DnsCache) -> Politeness {
+        Politeness {
+            dns: dns,
+            state: Mutex::new(PolitenessState {
+                delay: Duration::from_millis(0),
+                by_ip: false,
+                next: HashMap::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<PolitenessState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Set the minimum time between two requests to a host, or to an address if by_ip
+    pub fn configure(&self, delay: Duration, by_ip: bool) {
+        let mut state = self.state();
+        state.delay = delay;
+        state.by_ip = by_ip;
+    }
+
+    /// Return the host of url, or its address when grouping by IP
+    fn key_of(&self, url: &Url, by_ip: bool) -> String {
+        let host = url.host_str().unwrap_or("").to_string();
+        if !by_ip {
+            return host;
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        self.dns.resolve_ip(&host, port).map(|ip| ip.to_string()).unwrap_or(host)
+    }
+
+    /// Book the next slot to request url and return how long to wait for it
+    pub fn reserve(&self, url: &Url) -> Duration {
+        let (delay, by_ip) = {
+            let state = self.state();
+            (state.delay, state.by_ip)
+        };
+        if delay == Duration::from_millis(0) {
+            return delay;
+        }
+        let key = self.key_of(url, by_ip);
+        let now = Instant::now();
+        let mut state = self.state();
+        let slot = match state.next.get(&key) {
+            Some(&next) if next > now => next,
+            _ => now,
+        };
+        state.next.insert(key, slot + delay);
+        slot - now
+    }
+
+    /// Book the slot to request url if it is free now, else return how long until it is
+    ///
+    /// A zero duration means the slot was booked, otherwise nothing is booked.
+    pub fn try_reserve(&self, url: &Url) -> Duration {
+        let (delay, by_ip) = {
+            let state = self.state();
+            (state.delay, state.by_ip)
+        };
+        if delay == Duration::from_millis(0) {
+            return delay;
+        }
+        let key = self.key_of(url, by_ip);
+        let now = Instant::now();
+        let mut state = self.state();
+        if let Some(&next) = state.next.get(&key) {
+            if next > now {
+                return next - now;
+            }
+        }
+        state.next.insert(key, now + delay);
+        Duration::from_millis(0)
+    }
+
+    /// Wait until url may be requested
+    pub fn wait(&self, url: &Url) {
+        let wait = self.reserve(url);
+        if wait > Duration::from_millis(0) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use super::Politeness;
+    use super::super::dns::DnsCache;
+
+    #[test]
+    fn space_requests_per_host_or_ip() {
+        let dns = DnsCache::with_resolver(Arc::new(|_: &str, port: u16| {
+            Ok(vec![format!("10.0.0.1:{}", port).parse().unwrap()])
+        }));
+        let politeness = Politeness::new(dns);
+        let a = "http://a.example.com/".into_url().unwrap();
+        let b = "http://b.example.com/".into_url().unwrap();
+        assert_eq!(politeness.reserve(&a), Duration::from_millis(0));
+
+        let second = Duration::from_secs(1);
+        politeness.configure(second, false);
+        assert_eq!(politeness.reserve(&a), Duration::from_millis(0));
+        assert_eq!(politeness.reserve(&b), Duration::from_millis(0));
+        assert!(politeness.reserve(&a) > second / 2);
+
+        politeness.configure(second, true);
+        assert_eq!(politeness.reserve(&a), Duration::from_millis(0));
+        assert!(politeness.reserve(&b) > second / 2);
+        assert!(politeness.reserve(&a) > second + second / 2);
+    }
+
+    #[test]
+    fn try_reserve_books_free_slots_only() {
+        let politeness = Politeness::new(DnsCache::new());
+        let a = "http://a.example.com/".into_url().unwrap();
+        let b = "http://b.example.com/".into_url().unwrap();
+        let second = Duration::from_secs(1);
+        politeness.configure(second, false);
+        assert_eq!(politeness.try_reserve(&a), Duration::from_millis(0));
+        assert!(politeness.try_reserve(&a) > second / 2);
+        assert!(politeness.try_reserve(&a) > second / 2);
+        assert_eq!(politeness.try_reserve(&b), Duration::from_millis(0));
+        // Nothing was booked by the refused tries
+        assert!(politeness.reserve(&a) <= second);
+    }
+}
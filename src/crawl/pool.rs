@@ -0,0 +1,629 @@
+use hyper;
+use hyper::net::{NetworkConnector, NetworkStream};
+use super::dns::DnsCache;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Settings of a connection pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolConfig {
+    max_per_host: usize,
+    max_idle_per_host: usize,
+    idle_timeout: Duration,
+    group_by_ip: bool,
+}
+
+impl PoolConfig {
+    pub fn new() -> PoolConfig {
+        PoolConfig {
+            max_per_host: 6,
+            max_idle_per_host: 6,
+            idle_timeout: Duration::from_secs(90),
+            group_by_ip: false,
+        }
+    }
+
+    pub fn max_per_host(&self) -> usize {
+        self.max_per_host
+    }
+
+    pub fn max_idle_per_host(&self) -> usize {
+        self.max_idle_per_host
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub fn group_by_ip(&self) -> bool {
+        self.group_by_ip
+    }
+
+    /// Set the maximum number of connections open to a host, slaves wait beyond it
+    pub fn set_max_per_host(mut self, max: usize) -> PoolConfig {
+        self.max_per_host = if max == 0 { 1 } else { max };
+        self
+    }
+
+    /// Set the maximum number of idle connections kept to a host
+    pub fn set_max_idle_per_host(mut self, max: usize) -> PoolConfig {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Set how long an idle connection is kept before being closed
+    pub fn set_idle_timeout(mut self, timeout: Duration) -> PoolConfig {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Apply per host limits to resolved addresses, virtual hosts of a server sharing them
+    pub fn set_group_by_ip(mut self, group_by_ip: bool) -> PoolConfig {
+        self.group_by_ip = group_by_ip;
+        self
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counters of a connection pool.
+///
+/// `opened` - Connections opened
+///
+/// `reused` - Requests sent on an idle connection
+///
+/// `expired` - Idle connections closed after the idle timeout
+///
+/// `waited` - Requests which waited for a connection to a busy host
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PoolStats {
+    opened: usize,
+    reused: usize,
+    expired: usize,
+    waited: usize,
+}
+
+impl PoolStats {
+    /// Return the number of connections opened
+    pub fn get_opened(&self) -> usize {
+        self.opened
+    }
+
+    /// Return the number of requests sent on an idle connection
+    pub fn get_reused(&self) -> usize {
+        self.reused
+    }
+
+    /// Return the number of idle connections closed after the idle timeout
+    pub fn get_expired(&self) -> usize {
+        self.expired
+    }
+
+    /// Return the number of requests which waited for a connection to a busy host
+    pub fn get_waited(&self) -> usize {
+        self.waited
+    }
+
+    /// Return the part of requests sent on a reused connection
+    pub fn reuse_rate(&self) -> f64 {
+        let total = self.opened + self.reused;
+        if total == 0 {
+            return 0.0;
+        }
+        self.reused as f64 / total as f64
+    }
+}
+
+impl fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} connections opened, {} reused ({:.1}%), {} expired, {} waits",
+               self.opened,
+               self.reused,
+               self.reuse_rate() * 100.0,
+               self.expired,
+               self.waited)
+    }
+}
+
+type Key = (String, u16, String);
+
+#[derive(Debug)]
+struct IdleStream<S> {
+    stream: S,
+    group: String,
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct PoolState<S> {
+    idle: HashMap<Key, Vec<IdleStream<S>>>,
+    /// Open connections per host, or per address when grouping by IP
+    open: HashMap<String, usize>,
+    config: PoolConfig,
+    stats: PoolStats,
+}
+
+impl<S> PoolState<S> {
+    /// Take an idle connection to key and its group, closing the expired ones
+    fn take_idle(&mut self, key: &Key) -> Option<(S, String)> {
+        let timeout = self.config.idle_timeout;
+        let mut expired = Vec::new();
+        let mut stream = None;
+        let empty = match self.idle.get_mut(key) {
+            Some(idle) => {
+                while let Some(conn) = idle.pop() {
+                    if conn.since.elapsed() < timeout {
+                        stream = Some((conn.stream, conn.group));
+                        break;
+                    }
+                    expired.push(conn.group);
+                }
+                idle.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.idle.remove(key);
+        }
+        self.stats.expired += expired.len();
+        for group in &expired {
+            self.release(group, 1);
+        }
+        stream
+    }
+
+    /// Close an idle connection of group to another host, return false if there is none
+    fn evict_idle(&mut self, group: &str) -> bool {
+        let key = self.idle
+            .iter()
+            .find(|&(_, idle)| idle.iter().any(|conn| conn.group == group))
+            .map(|(key, _)| key.clone());
+        let key = match key {
+            Some(key) => key,
+            None => return false,
+        };
+        let empty = match self.idle.get_mut(&key) {
+            Some(idle) => {
+                if let Some(i) = idle.iter().position(|conn| conn.group == group) {
+                    idle.remove(i);
+                }
+                idle.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.idle.remove(&key);
+        }
+        self.release(group, 1);
+        true
+    }
+
+    /// Forget n connections of group which are closed
+    fn release(&mut self, group: &str, n: usize) {
+        let remove = match self.open.get_mut(group) {
+            Some(open) => {
+                *open = open.saturating_sub(n);
+                *open == 0
+            }
+            None => false,
+        };
+        if remove {
+            self.open.remove(group);
+        }
+    }
+}
+
+struct PoolInner<C: NetworkConnector> {
+    connector: C,
+    dns: Option<DnsCache>,
+    state: Mutex<PoolState<C::Stream>>,
+    released: Condvar,
+}
+
+/// Connection pool shared by slaves, limiting connections per host.
+///
+/// Connections are kept alive once a response is read, so the next request
+/// to the same host skips the TCP and TLS handshakes whichever slave sends it.
+pub struct ConnectionPool<C: NetworkConnector> {
+    inner: Arc<PoolInner<C>>,
+}
+
+impl<C: NetworkConnector> ConnectionPool<C> {
+    pub fn new(connector: C, config: PoolConfig) -> ConnectionPool<C> {
+        ConnectionPool::with_dns(connector, config, None)
+    }
+
+    /// Create a pool looking up in dns the addresses of hosts when grouping by IP
+    pub fn with_dns(connector: C, config: PoolConfig, dns: Option<DnsCache>) -> ConnectionPool<C> {
+        ConnectionPool {
+            inner: Arc::new(PoolInner {
+                connector: connector,
+                dns: dns,
+                state: Mutex::new(PoolState {
+                    idle: HashMap::new(),
+                    open: HashMap::new(),
+                    config: config,
+                    stats: PoolStats::default(),
+                }),
+                released: Condvar::new(),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<PoolState<C::Stream>> {
+        match self.inner.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Return the pool settings
+    pub fn config(&self) -> PoolConfig {
+        self.state().config
+    }
+
+    /// Change the pool settings, for connections made from now on
+    pub fn set_config(&self, config: PoolConfig) {
+        self.state().config = config;
+        self.inner.released.notify_all();
+    }
+
+    /// Return the pool counters
+    pub fn stats(&self) -> PoolStats {
+        self.state().stats
+    }
+
+    /// Return the group whose connections count towards the limit of host
+    fn group_of(&self, host: &str, port: u16) -> String {
+        if !self.config().group_by_ip {
+            return host.to_string();
+        }
+        self.inner
+            .dns
+            .as_ref()
+            .and_then(|dns| dns.resolve_ip(host, port))
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| host.to_string())
+    }
+}
+
+impl<C: NetworkConnector> Clone for ConnectionPool<C> {
+    fn clone(&self) -> Self {
+        ConnectionPool { inner: self.inner.clone() }
+    }
+}
+
+impl<C: NetworkConnector> fmt::Debug for ConnectionPool<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("config", &self.config())
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<C, S> NetworkConnector for ConnectionPool<C>
+    where C: NetworkConnector<Stream = S> + Send + Sync + 'static,
+          S: NetworkStream + Send
+{
+    type Stream = PooledStream<C>;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<PooledStream<C>> {
+        let key = (host.to_string(), port, scheme.to_string());
+        let group = self.group_of(host, port);
+        {
+            let mut state = self.state();
+            let mut waited = false;
+            loop {
+                if let Some((stream, group)) = state.take_idle(&key) {
+                    state.stats.reused += 1;
+                    return Ok(PooledStream::new(self, key, group, stream, true));
+                }
+                let max = state.config.max_per_host;
+                let open = state.open.get(&group).cloned().unwrap_or(0);
+                if open < max {
+                    state.open.insert(group.clone(), open + 1);
+                    state.stats.opened += 1;
+                    break;
+                }
+                if state.evict_idle(&group) {
+                    continue;
+                }
+                if !waited {
+                    state.stats.waited += 1;
+                    waited = true;
+                }
+                state = match self.inner.released.wait(state) {
+                    Ok(state) => state,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+            }
+        }
+        match self.inner.connector.connect(host, port, scheme) {
+            Ok(stream) => Ok(PooledStream::new(self, key, group, stream, false)),
+            Err(e) => {
+                self.state().release(&group, 1);
+                self.inner.released.notify_one();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A connection given back to its pool when dropped, unless it was closed
+pub struct PooledStream<C: NetworkConnector> {
+    key: Key,
+    group: String,
+    stream: Option<C::Stream>,
+    reused: bool,
+    has_read: bool,
+    closed: AtomicBool,
+    pool: Arc<PoolInner<C>>,
+}
+
+impl<C: NetworkConnector> PooledStream<C> {
+    fn new(pool: &ConnectionPool<C>,
+           key: Key,
+           group: String,
+           stream: C::Stream,
+           reused: bool)
+           -> PooledStream<C> {
+        PooledStream {
+            key: key,
+            group: group,
+            stream: Some(stream),
+            reused: reused,
+            has_read: false,
+            closed: AtomicBool::new(false),
+            pool: pool.inner.clone(),
+        }
+    }
+
+    fn stream(&self) -> &C::Stream {
+        self.stream.as_ref().expect("pooled stream is only taken when dropped")
+    }
+
+    fn stream_mut(&mut self) -> &mut C::Stream {
+        self.stream.as_mut().expect("pooled stream is only taken when dropped")
+    }
+}
+
+impl<C: NetworkConnector> fmt::Debug for PooledStream<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledStream")
+            .field("key", &self.key)
+            .field("reused", &self.reused)
+            .finish()
+    }
+}
+
+impl<C: NetworkConnector> Read for PooledStream<C>
+    where C::Stream: NetworkStream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream_mut().read(buf)?;
+        if n > 0 {
+            self.has_read = true;
+            return Ok(n);
+        }
+        // Server closed the connection, maybe while it was idle
+        self.closed.store(true, Ordering::Relaxed);
+        if self.reused && !self.has_read {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                      "Pooled connection closed by server"));
+        }
+        Ok(0)
+    }
+}
+
+impl<C: NetworkConnector> Write for PooledStream<C>
+    where C::Stream: NetworkStream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream_mut().flush()
+    }
+}
+
+impl<C> NetworkStream for PooledStream<C>
+    where C: NetworkConnector + Send + Sync + 'static,
+          C::Stream: NetworkStream + Send
+{
+    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+        self.stream_mut().peer_addr()
+    }
+
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream().set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.stream().set_write_timeout(dur)
+    }
+
+    fn close(&mut self, how: Shutdown) -> io::Result<()> {
+        self.closed.store(true, Ordering::Relaxed);
+        self.stream_mut().close(how)
+    }
+
+    fn set_previous_response_expected_no_content(&mut self, expected: bool) {
+        self.stream_mut().set_previous_response_expected_no_content(expected)
+    }
+
+    fn previous_response_expected_no_content(&self) -> bool {
+        self.stream().previous_response_expected_no_content()
+    }
+}
+
+impl<C: NetworkConnector> Drop for PooledStream<C> {
+    fn drop(&mut self) {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return,
+        };
+        let mut state = match self.pool.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let idle = state.idle.get(&self.key).map(|i| i.len()).unwrap_or(0);
+        if self.closed.load(Ordering::Relaxed) || idle >= state.config.max_idle_per_host {
+            state.release(&self.group, 1);
+        } else {
+            state.idle.entry(self.key.clone()).or_insert_with(Vec::new).push(IdleStream {
+                stream: stream,
+                group: self.group.clone(),
+                since: Instant::now(),
+            });
+        }
+        self.pool.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper;
+    use hyper::net::{NetworkConnector, NetworkStream};
+    use std::io::{self, Read, Write};
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use super::{ConnectionPool, PoolConfig};
+    use super::super::dns::DnsCache;
+
+    #[derive(Debug, Default)]
+    struct MockConnector {
+        connects: AtomicUsize,
+    }
+
+    #[derive(Debug, Clone)]
+    struct MockStream;
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            buf[0] = b'x';
+            Ok(1)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl NetworkStream for MockStream {
+        fn peer_addr(&mut self) -> io::Result<SocketAddr> {
+            Ok("127.0.0.1:80".parse().unwrap())
+        }
+
+        fn set_read_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_write_timeout(&self, _dur: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl NetworkConnector for MockConnector {
+        type Stream = MockStream;
+
+        fn connect(&self, _host: &str, _port: u16, _scheme: &str) -> hyper::Result<MockStream> {
+            self.connects.fetch_add(1, Ordering::SeqCst);
+            Ok(MockStream)
+        }
+    }
+
+    #[test]
+    fn reuse_connections() {
+        let pool = ConnectionPool::new(MockConnector::default(), PoolConfig::new());
+        let first = pool.connect("example.com", 80, "http").unwrap();
+        let second = pool.connect("example.com", 80, "http").unwrap();
+        drop(first);
+        drop(second);
+        for _ in 0..3 {
+            pool.connect("example.com", 80, "http").unwrap();
+        }
+        pool.connect("google.com", 443, "https").unwrap();
+        let stats = pool.stats();
+        assert_eq!(stats.opened, 3);
+        assert_eq!(stats.reused, 3);
+        assert_eq!(stats.reuse_rate(), 0.5);
+    }
+
+    #[test]
+    fn closed_and_expired_connections() {
+        let config = PoolConfig::new().set_idle_timeout(Duration::from_millis(0));
+        let pool = ConnectionPool::new(MockConnector::default(), config);
+        pool.connect("example.com", 80, "http").unwrap();
+        pool.connect("example.com", 80, "http").unwrap();
+        assert_eq!(pool.stats().expired, 1);
+        pool.set_config(PoolConfig::new());
+        let mut stream = pool.connect("example.com", 80, "http").unwrap();
+        stream.close(::std::net::Shutdown::Both).unwrap();
+        drop(stream);
+        pool.connect("example.com", 80, "http").unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.opened, stats.reused), (3, 1));
+    }
+
+    #[test]
+    fn limit_connections_per_host() {
+        use std::thread;
+        let config = PoolConfig::new().set_max_per_host(1);
+        let pool = ConnectionPool::new(MockConnector::default(), config);
+        let stream = pool.connect("example.com", 80, "http").unwrap();
+        let other = pool.clone();
+        let waiter = thread::spawn(move || {
+            other.connect("example.com", 80, "http").unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.stats().waited, 1);
+        drop(stream);
+        waiter.join().unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.opened, stats.reused), (1, 1));
+    }
+
+    #[test]
+    fn limit_connections_per_ip() {
+        use std::sync::Arc;
+        let dns = DnsCache::with_resolver(Arc::new(|_: &str, port: u16| {
+            Ok(vec![format!("10.0.0.1:{}", port).parse().unwrap()])
+        }));
+        let config = PoolConfig::new().set_max_per_host(2).set_group_by_ip(true);
+        let pool = ConnectionPool::with_dns(MockConnector::default(), config, Some(dns));
+        let a = pool.connect("a.example.com", 80, "http").unwrap();
+        drop(pool.connect("b.example.com", 80, "http").unwrap());
+        // The idle connection to b makes room for c, both hosts sharing an address
+        let c = pool.connect("c.example.com", 80, "http").unwrap();
+        let other = pool.clone();
+        let waiter = ::std::thread::spawn(move || {
+            other.connect("b.example.com", 80, "http").unwrap();
+        });
+        ::std::thread::sleep(Duration::from_millis(50));
+        let stats = pool.stats();
+        assert_eq!((stats.opened, stats.reused, stats.waited), (3, 0, 1));
+        drop(a);
+        waiter.join().unwrap();
+        drop(c);
+        assert_eq!(pool.stats().opened, 4);
+    }
+}
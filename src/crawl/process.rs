@@ -0,0 +1,273 @@
+use common::{hash_bytes, href_to_url};
+use directives::Directives;
+use error::*;
+use graph::LinkGraph;
+use hyper::header::LastModified;
+use hyper::Url;
+use manifest::ManifestEntry;
+use metadata::PageMetadata;
+use page::PageInfo;
+use scrap::{scrap_alternates, scrap_anchors, scrap_canonical, scrap_links, scrap_text};
+use simhash::simhash;
+use structured;
+use tables;
+use select::document::Document;
+use std::sync::Mutex;
+use std::time::Instant;
+use super::config::CrawlerConfig;
+use super::fetch::FetchResponse;
+use super::frontier::FrontierEntry;
+use super::revisit::RevisitScheduler;
+use super::sync::{self, SharedIndexer, SharedQueue};
+
+/// Index url before fetching it, so it is fetched only once
+pub fn claim(indexer: &SharedIndexer, url: &Url) -> Result<()> {
+    indexer.lock_url(url)?.add_url(url.clone())
+}
+
+/// Claim url like `claim`, or return what the previous visit learnt if it is revisited
+pub fn claim_or_revisit(indexer: &SharedIndexer,
+                        revisits: &Mutex<RevisitScheduler>,
+                        url: &Url)
+                        -> Result<Option<ManifestEntry>> {
+    if let Err(e) = claim(indexer, url) {
+        if !sync::lock(revisits)?.take_released(url) {
+            return Err(e);
+        }
+        let known = indexer.lock_url(url)?.get_page_info(url).map(ManifestEntry::from_page_info);
+        return Ok(Some(known.unwrap_or_default()));
+    }
+    Ok(None)
+}
+
+/// Schedule the next visit of url, sooner if its body changed since the last one
+pub fn schedule_revisit(indexer: &SharedIndexer,
+                        revisits: &Mutex<RevisitScheduler>,
+                        url: &Url)
+                        -> Result<()> {
+    let hash = indexer.lock_url(url)?.get_page_info(url).and_then(|info| info.get_hash());
+    sync::lock(revisits)?.record(url, hash, Instant::now());
+    Ok(())
+}
+
+/// Schedule url to be tried again after the max interval, its visit having failed
+pub fn schedule_retry(revisits: &Mutex<RevisitScheduler>, url: &Url) -> Result<()> {
+    sync::lock(revisits)?.record_failure(url, Instant::now());
+    Ok(())
+}
+
+/// Record in page info what the response tells about url
+///
+/// A page not modified keeps the status, validators and hash of previous, unless the
+/// response gives new validators.
+pub fn record_response(indexer: &SharedIndexer,
+                       url: &Url,
+                       response: &FetchResponse,
+                       previous: Option<&ManifestEntry>)
+                       -> Result<()> {
+    let mut info = PageInfo::new();
+    info.set_status(Some(response.get_status()));
+    if response.get_url() != url {
+        info.set_redirect(Some(response.get_url().clone()));
+    }
+    info.set_last_modified(response.get_headers().get::<LastModified>().map(|h| h.to_string()));
+    info.set_etag(response.get_headers()
+        .get_raw("ETag")
+        .and_then(|v| v.first())
+        .map(|v| String::from_utf8_lossy(v).into_owned()));
+    match previous {
+        Some(previous) if response.get_status() == 304 => {
+            info.set_status(previous.get_status().or(Some(response.get_status())));
+            info.set_not_modified(true);
+            if info.get_etag().is_none() {
+                info.set_etag(previous.get_etag().map(|s| s.to_string()));
+            }
+            if info.get_last_modified().is_none() {
+                info.set_last_modified(previous.get_last_modified().map(|s| s.to_string()));
+            }
+            info.set_hash(previous.get_hash());
+        }
+        _ => info.set_hash(Some(hash_bytes(response.get_body()))),
+    }
+    indexer.lock_url(url)?.set_page_info(url.clone(), info);
+    Ok(())
+}
+
+/// Record in page info that url could not be fetched
+pub fn record_error(indexer: &SharedIndexer, url: &Url, error: &Error) -> Result<()> {
+    let mut info = PageInfo::new();
+    info.set_error(Some(error.to_string()));
+    indexer.lock_url(url)?.set_page_info(url.clone(), info);
+    Ok(())
+}
+
+/// Process the page of entry, storing its body if it is kept and was modified
+///
+/// The body of a page not modified is the one stored by the previous crawl. Only pages
+/// with a stored body are asked conditionally, one without is not processed.
+pub fn process_response(config: &CrawlerConfig,
+                        indexer: &SharedIndexer,
+                        queue: &SharedQueue,
+                        graph: &Mutex<LinkGraph>,
+                        entry: &FrontierEntry,
+                        response: FetchResponse) {
+    let directives = Directives::from_headers(&response.get_headers());
+    if response.get_status() == 304 {
+        if let Some(body) = config.stored(entry.get_url()) {
+            process_page(config, indexer, queue, graph, entry, &body, directives);
+        }
+        return;
+    }
+    if process_page(config, indexer, queue, graph, entry, response.get_body(), directives) {
+        config.store(entry.get_url(), response.get_body());
+    }
+}
+
+/// Check if the page of url is kept: stored and its data extracted
+///
+/// A page asking not to be indexed is not kept, unless config ignores directives, nor is
+/// a duplicate of its canonical if config collapses duplicates.
+fn is_kept(config: &CrawlerConfig,
+           indexer: &SharedIndexer,
+           url: &Url,
+           directives: Directives)
+           -> bool {
+    if directives.is_noindex() && config.obey_directives() {
+        return false;
+    }
+    !(config.collapse_duplicates() && is_duplicate(indexer, url))
+}
+
+/// Check if the page of url declared another page as its canonical
+fn is_duplicate(indexer: &SharedIndexer, url: &Url) -> bool {
+    match indexer.lock_url(url) {
+        Ok(indexer) => indexer.get_page_info(url).map(|i| !i.is_canonical(url)).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Record the directives and links of the page of entry, write its data to the sinks of
+/// config if it is kept, and queue the links to follow
+///
+/// Links with `rel="nofollow"`, or of a page asking not to be followed, are
+/// recorded but not queued, unless config ignores directives, as are links of
+/// a near duplicate of a page already crawled if config says so. Return whether
+/// the page is kept, given the directives of its headers and document.
+fn process_page(config: &CrawlerConfig,
+                indexer: &SharedIndexer,
+                queue: &SharedQueue,
+                graph: &Mutex<LinkGraph>,
+                entry: &FrontierEntry,
+                body: &[u8],
+                directives: Directives)
+                -> bool {
+    let v_url = entry.get_url();
+    let body = String::from_utf8_lossy(body).to_string();
+    let doc = Document::from(body.as_str());
+    let directives = directives.merge(Directives::from_document(&doc));
+    let fingerprint = config.near_duplicates().and_then(|_| simhash(&scrap_text(&doc)));
+    update_page_info(config, indexer, v_url, &doc, directives, fingerprint);
+    let kept = is_kept(config, indexer, v_url, directives);
+    if kept {
+        if let Some(sink) = config.structured_data() {
+            let _ = sink.write_page(v_url, &structured::extract(v_url, &doc));
+        }
+        if let Some(scraper) = config.scraper() {
+            let _ = scraper.write_page(v_url, &doc);
+        }
+        if let Some(sink) = config.tables() {
+            let _ = sink.write_page(v_url, &tables::extract(&doc));
+        }
+    }
+    let mut follow = !directives.is_nofollow() || !config.obey_directives();
+    if let (Some(index), Some(fingerprint)) = (config.near_duplicates(), fingerprint) {
+        if let Ok(mut index) = sync::lock(index) {
+            let original = index.find(fingerprint).cloned();
+            match original {
+                Some(ref original) if original != v_url => {
+                    follow = follow && !config.skip_near_duplicates();
+                }
+                Some(_) => {}
+                None => index.insert(v_url.clone(), fingerprint),
+            }
+        }
+    }
+    let mut edges = Vec::new();
+    for link in scrap_links(&doc) {
+        let in_page = link.get_href().starts_with('#');
+        if in_page && !config.check_anchors() {
+            continue;
+        }
+        let mut url = match href_to_url(v_url, link.get_href()) {
+            Some(u) => u,
+            None => continue,
+        };
+        if config.record_links() {
+            edges.push((url.clone(), link.get_text().to_string(), link.get_kind()));
+        }
+        if in_page || !link.get_kind().is_followed() {
+            continue;
+        }
+        if !follow || (config.obey_directives() && link.get_rel().iter().any(|r| r == "nofollow")) {
+            continue;
+        }
+        // Parts of a page are the same page
+        url.set_fragment(None);
+        if config.filter(v_url, &url) {
+            let child = entry.child(url, link.get_text().to_string());
+            if let Err(_e) = sync::add_entry(indexer, queue, child) {
+                continue;
+            }
+        }
+    }
+    if !edges.is_empty() {
+        if let Ok(mut graph) = sync::lock(graph) {
+            // A revisited page already has its links recorded
+            if graph.has_edges_from(v_url) {
+                edges.clear();
+            }
+            for (url, anchor, kind) in edges {
+                graph.add_edge(v_url.clone(), url, anchor, kind);
+            }
+        }
+    }
+    kept
+}
+
+/// Record in page info the directives, canonical, alternates and fingerprint of document,
+/// and its anchors and metadata if config says so
+fn update_page_info(config: &CrawlerConfig,
+                    indexer: &SharedIndexer,
+                    url: &Url,
+                    doc: &Document,
+                    directives: Directives,
+                    fingerprint: Option<u64>) {
+    let canonical = scrap_canonical(doc).and_then(|href| href_to_url(url, &href));
+    let alternates = scrap_alternates(doc)
+        .into_iter()
+        .filter_map(|(lang, href)| href_to_url(url, &href).map(|u| (lang, u)))
+        .collect();
+    let anchors = if config.check_anchors() {
+        Some(scrap_anchors(doc).into_iter().collect())
+    } else {
+        None
+    };
+    let metadata = if config.record_metadata() {
+        Some(PageMetadata::from_document(url, doc))
+    } else {
+        None
+    };
+    let mut indexer = match indexer.lock_url(url) {
+        Ok(i) => i,
+        Err(_) => return,
+    };
+    if let Some(info) = indexer.get_page_info_mut(url) {
+        info.set_noindex(directives.is_noindex());
+        info.set_nofollow(directives.is_nofollow());
+        info.set_simhash(fingerprint);
+        info.set_canonical(canonical);
+        info.set_alternates(alternates);
+        info.set_anchors(anchors);
+        info.set_metadata(metadata);
+    }
+}
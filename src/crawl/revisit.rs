@@ -0,0 +1,242 @@
+use hyper::Url;
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Shortest interval between two visits of a page
+const MIN_INTERVAL_SECS: u64 = 1;
+
+/// Settings of the revisits of a continuous crawl.
+///
+/// Each page is revisited between the min and max intervals of its site, the
+/// interval halving when the page changed since the last visit and doubling
+/// when it did not. Intervals are at least `MIN_INTERVAL_SECS`, a page failing
+/// to be fetched is tried again after the max interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevisitConfig {
+    min_interval: Duration,
+    max_interval: Duration,
+    sites: HashMap<String, (Duration, Duration)>,
+}
+
+impl RevisitConfig {
+    pub fn new() -> RevisitConfig {
+        RevisitConfig {
+            min_interval: Duration::from_secs(60),
+            max_interval: Duration::from_secs(24 * 3600),
+            sites: HashMap::new(),
+        }
+    }
+
+    /// Set the intervals of pages whose site has no intervals of its own
+    pub fn set_interval(mut self, min: Duration, max: Duration) -> RevisitConfig {
+        let min = cmp::max(min, Duration::from_secs(MIN_INTERVAL_SECS));
+        self.min_interval = min;
+        self.max_interval = cmp::max(min, max);
+        self
+    }
+
+    /// Set the intervals of the pages of host
+    pub fn set_site_interval(mut self, host: &str, min: Duration, max: Duration) -> RevisitConfig {
+        let min = cmp::max(min, Duration::from_secs(MIN_INTERVAL_SECS));
+        self.sites.insert(host.to_lowercase(), (min, cmp::max(min, max)));
+        self
+    }
+
+    /// Return the min and max intervals of url
+    pub fn interval_of(&self, url: &Url) -> (Duration, Duration) {
+        url.host_str()
+            .and_then(|host| self.sites.get(&host.to_lowercase()))
+            .cloned()
+            .unwrap_or((self.min_interval, self.max_interval))
+    }
+}
+
+impl Default for RevisitConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+struct Visit {
+    interval: Duration,
+    hash: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Due {
+    at: Instant,
+    url: Url,
+}
+
+impl PartialOrd for Due {
+    fn partial_cmp(&self, other: &Due) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Due {
+    // Earliest first
+    fn cmp(&self, other: &Due) -> Ordering {
+        match other.at.cmp(&self.at) {
+            Ordering::Equal => other.url.cmp(&self.url),
+            o => o,
+        }
+    }
+}
+
+/// Schedule of the pages to revisit, shared by slaves
+#[derive(Debug)]
+pub struct RevisitScheduler {
+    config: RevisitConfig,
+    visits: HashMap<Url, Visit>,
+    due: BinaryHeap<Due>,
+    released: HashSet<Url>,
+}
+
+impl RevisitScheduler {
+    pub fn new(config: RevisitConfig) -> RevisitScheduler {
+        RevisitScheduler {
+            config: config,
+            visits: HashMap::new(),
+            due: BinaryHeap::new(),
+            released: HashSet::new(),
+        }
+    }
+
+    /// Change the settings, for visits recorded from now on
+    pub fn set_config(&mut self, config: RevisitConfig) {
+        self.config = config;
+    }
+
+    /// Record a visit of url whose body hashes to hash, and return when to visit it again
+    pub fn record(&mut self, url: &Url, hash: Option<u64>, now: Instant) -> Duration {
+        let (min, max) = self.config.interval_of(url);
+        let interval = match self.visits.get(url) {
+            None => min,
+            Some(visit) if visit.hash != hash => visit.interval / 2,
+            Some(visit) => visit.interval * 2,
+        };
+        let interval = cmp::min(max, cmp::max(min, interval));
+        self.visits.insert(url.clone(),
+                           Visit {
+                               interval: interval,
+                               hash: hash,
+                           });
+        self.due.push(Due {
+            at: now + interval,
+            url: url.clone(),
+        });
+        interval
+    }
+
+    /// Record a failed visit of url, and return when to try it again, after the max interval
+    pub fn record_failure(&mut self, url: &Url, now: Instant) -> Duration {
+        let (_, max) = self.config.interval_of(url);
+        let hash = self.visits.get(url).and_then(|visit| visit.hash);
+        self.visits.insert(url.clone(),
+                           Visit {
+                               interval: max,
+                               hash: hash,
+                           });
+        self.due.push(Due {
+            at: now + max,
+            url: url.clone(),
+        });
+        max
+    }
+
+    /// Take the urls to visit again by now
+    pub fn take_due(&mut self, now: Instant) -> Vec<Url> {
+        let mut urls = Vec::new();
+        while self.due.peek().map(|d| d.at <= now).unwrap_or(false) {
+            if let Some(due) = self.due.pop() {
+                self.released.insert(due.url.clone());
+                urls.push(due.url);
+            }
+        }
+        urls
+    }
+
+    /// Check if url was taken to be revisited, forgetting it
+    pub fn take_released(&mut self, url: &Url) -> bool {
+        self.released.remove(url)
+    }
+
+    /// Return when the next url is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.peek().map(|d| d.at)
+    }
+
+    /// Return the number of pages scheduled
+    pub fn len(&self) -> usize {
+        self.visits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visits.is_empty()
+    }
+}
+
+impl Default for RevisitScheduler {
+    fn default() -> Self {
+        Self::new(RevisitConfig::new())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use std::time::{Duration, Instant};
+    use super::{RevisitConfig, RevisitScheduler};
+
+    #[test]
+    fn adapt_interval() {
+        let minute = Duration::from_secs(60);
+        let config = RevisitConfig::new()
+            .set_interval(minute, minute * 8)
+            .set_site_interval("static.example.com", minute * 10, minute * 20);
+        let mut scheduler = RevisitScheduler::new(config);
+        let url = "http://example.com/".into_url().unwrap();
+        let now = Instant::now();
+        assert_eq!(scheduler.record(&url, Some(1), now), minute);
+        assert_eq!(scheduler.record(&url, Some(1), now), minute * 2);
+        assert_eq!(scheduler.record(&url, Some(1), now), minute * 4);
+        assert_eq!(scheduler.record(&url, Some(1), now), minute * 8);
+        assert_eq!(scheduler.record(&url, Some(1), now), minute * 8);
+        assert_eq!(scheduler.record(&url, Some(2), now), minute * 4);
+
+        let url = "http://static.example.com/".into_url().unwrap();
+        assert_eq!(scheduler.record(&url, None, now), minute * 10);
+        assert_eq!(scheduler.record(&url, Some(3), now), minute * 10);
+        assert_eq!(scheduler.record(&url, Some(3), now), minute * 20);
+        assert_eq!(scheduler.record_failure(&url, now), minute * 20);
+        assert_eq!(scheduler.record(&url, Some(4), now), minute * 10);
+
+        let config = RevisitConfig::new().set_interval(Duration::from_secs(0), minute);
+        let url = "http://example.com/".into_url().unwrap();
+        assert_eq!(RevisitScheduler::new(config).record(&url, None, now),
+                   Duration::from_secs(1));
+    }
+
+    #[test]
+    fn release_due_urls() {
+        let second = Duration::from_secs(1);
+        let mut scheduler = RevisitScheduler::new(RevisitConfig::new()
+            .set_interval(second, second * 10)
+            .set_site_interval("b.example.com", second * 3, second * 10));
+        let a = "http://a.example.com/".into_url().unwrap();
+        let b = "http://b.example.com/".into_url().unwrap();
+        let now = Instant::now();
+        scheduler.record(&b, None, now);
+        scheduler.record(&a, None, now);
+        assert_eq!(scheduler.next_due(), Some(now + second));
+        assert!(scheduler.take_due(now).is_empty());
+        assert_eq!(scheduler.take_due(now + second * 2), vec![a.clone()]);
+        assert_eq!(scheduler.take_due(now + second * 5), vec![b.clone()]);
+        assert!(scheduler.take_released(&a));
+        assert!(!scheduler.take_released(&a));
+        assert_eq!(scheduler.len(), 2);
+    }
+}
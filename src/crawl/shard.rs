@@ -0,0 +1,67 @@
+use common::hash_bytes;
+use error::*;
+use hyper::Url;
+use std::sync::{Mutex, MutexGuard};
+use super::sync;
+
+/// Number of shards used by default
+pub const DEFAULT_SHARDS: usize = 64;
+
+/// Values split into shards locked independently, urls being spread by host.
+///
+/// All urls of an host always go to the same shard, so slaves crawling
+/// different hosts rarely wait for each other.
+#[derive(Debug)]
+pub struct Sharded<T> {
+    shards: Vec<Mutex<T>>,
+}
+
+impl<T> Sharded<T> {
+    /// Create `count` shards, each one created by `new_shard`
+    pub fn new<F: FnMut() -> T>(count: usize, mut new_shard: F) -> Sharded<T> {
+        let count = if count == 0 { 1 } else { count };
+        Sharded { shards: (0..count).map(|_| Mutex::new(new_shard())).collect() }
+    }
+
+    /// Return the number of shards
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Check if there is no shard, which never happens
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Return the index of the shard holding url
+    pub fn shard_of(&self, url: &Url) -> usize {
+        let host = url.host_str().unwrap_or("");
+        (hash_bytes(host.as_bytes()) % self.shards.len() as u64) as usize
+    }
+
+    /// Return a mutex guard of shard i
+    pub fn lock(&self, i: usize) -> Result<MutexGuard<T>> {
+        sync::lock(&self.shards[i % self.shards.len()])
+    }
+
+    /// Return a mutex guard of the shard holding url
+    pub fn lock_url(&self, url: &Url) -> Result<MutexGuard<T>> {
+        self.lock(self.shard_of(url))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use super::Sharded;
+
+    #[test]
+    fn same_host_same_shard() {
+        let sharded = Sharded::new(8, Vec::new);
+        let a = "http://example.com/a".into_url().unwrap();
+        let b = "https://example.com/b?c".into_url().unwrap();
+        assert_eq!(sharded.shard_of(&a), sharded.shard_of(&b));
+        sharded.lock_url(&a).unwrap().push(a.clone());
+        assert_eq!(*sharded.lock_url(&b).unwrap(), vec![a]);
+    }
+}
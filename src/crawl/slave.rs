@@ -1,101 +1,176 @@
-use common::href_to_url;
 use error::*;
-use hyper::client::Client;
-use hyper::net::HttpsConnector;
+use graph::LinkGraph;
 use hyper::Url;
-use hyper_native_tls::NativeTlsClient;
-use indexer::Indexer;
-use scrap::scrap_attr;
-use select::document::Document;
-use std::collections::VecDeque;
-use std::io::Read;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::mpsc::Sender;
 use std::thread;
 use std::time::Duration;
 use super::config::CrawlerConfig;
-use super::sync;
+use super::dns::DnsCache;
+use super::fetch::{FetchResponse, Fetcher};
+use super::frontier::FrontierEntry;
+use super::politeness::Politeness;
+use super::revisit::RevisitScheduler;
+use super::process;
+use super::sync::{self, SharedIndexer, SharedQueue};
+
+/// Time waited by an idle slave before looking at the queue again
+const IDLE_MS: u64 = 5;
+
+/// Time waited by an idle slave of a continuous crawl when no page is being crawled
+const REVISIT_POLL_MS: u64 = 100;
 
 #[derive(Debug)]
 pub struct CrawlerSlave {
-    client: Client,
-    indexer: Arc<Mutex<Indexer>>,
-    queue: Arc<Mutex<VecDeque<Url>>>,
+    fetcher: Box<Fetcher>,
+    indexer: SharedIndexer,
+    queue: SharedQueue,
+    graph: Arc<Mutex<LinkGraph>>,
     running: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
     stop: Arc<AtomicBool>,
+    politeness: Arc<Politeness>,
+    revisits: Arc<Mutex<RevisitScheduler>>,
+    next_shard: usize,
 }
 
 impl CrawlerSlave {
-    pub fn new() -> CrawlerSlave {
-        let ssl = NativeTlsClient::new().unwrap();
-        let connector = HttpsConnector::new(ssl);
+    /// Create a slave sharing state with the crawler
+    ///
+    /// `active` counts slaves handling a page.
+    pub fn new_shared(fetcher: Box<Fetcher>,
+                      indexer: SharedIndexer,
+                      queue: SharedQueue,
+                      graph: Arc<Mutex<LinkGraph>>,
+                      running: Arc<AtomicUsize>,
+                      active: Arc<AtomicUsize>,
+                      stop: Arc<AtomicBool>)
+                      -> CrawlerSlave {
         CrawlerSlave {
-            client: Client::with_connector(connector),
-            indexer: Arc::new(Mutex::new(Indexer::new())),
-            queue: Arc::new(Mutex::new(VecDeque::new())),
-            running: Arc::new(AtomicUsize::new(0)),
-            stop: Arc::new(AtomicBool::new(false)),
+            fetcher: fetcher,
+            indexer: indexer,
+            queue: queue,
+            graph: graph,
+            running: running,
+            active: active,
+            stop: stop,
+            politeness: Arc::new(Politeness::new(DnsCache::new())),
+            revisits: Arc::new(Mutex::new(RevisitScheduler::default())),
+            next_shard: 0,
         }
     }
 
-    pub fn new_shared(indexer: Arc<Mutex<Indexer>>,
-                      queue: Arc<Mutex<VecDeque<Url>>>,
-                      running: Arc<AtomicUsize>,
-                      stop: Arc<AtomicBool>)
-                      -> CrawlerSlave {
-        let mut crawler = CrawlerSlave::new();
-        crawler.indexer = indexer;
-        crawler.queue = queue;
-        crawler.running = running;
-        crawler.stop = stop;
-        crawler
+    /// Set the queue shard popped first, to spread slaves over shards
+    pub fn set_first_shard(&mut self, shard: usize) {
+        self.next_shard = shard;
+    }
+
+    /// Space requests to hosts as politeness says, shared with other slaves
+    pub fn set_politeness(&mut self, politeness: Arc<Politeness>) {
+        self.politeness = politeness;
+    }
+
+    /// Schedule revisits of a continuous crawl with revisits, shared with other slaves
+    pub fn set_revisits(&mut self, revisits: Arc<Mutex<RevisitScheduler>>) {
+        self.revisits = revisits;
     }
 
     /// Crawl site from queue, index it and return url and the body.
     pub fn crawl(&mut self) -> Result<(Url, Vec<u8>)> {
-        let url = sync::pop_queue(&self.queue)?;
-        let mut reponse = self.client.get(url.clone()).send()?;
-        sync::lock(&self.indexer)?.add_url(url.clone())?;
-        let mut buf = Vec::new();
-        let body = match reponse.read_to_end(&mut buf) {
-            Ok(_) => buf,
-            Err(e) => bail!(e),
+        self.crawl_entry(None)
+            .map(|(entry, response)| (entry.get_url().clone(), response.into_body()))
+    }
+
+    /// Crawl entry from queue, index it and return entry and the response.
+    ///
+    /// Pages found by the previous crawl, or revisited, are asked only if they
+    /// changed, if config stores their body.
+    fn crawl_entry(&mut self,
+                   config: Option<&CrawlerConfig>)
+                   -> Result<(FrontierEntry, FetchResponse)> {
+        let entry = sync::pop_queue(&self.queue, self.next_shard)?;
+        self.next_shard = self.next_shard.wrapping_add(1);
+        let url = entry.get_url().clone();
+        let known = process::claim_or_revisit(&self.indexer, &self.revisits, &url)?;
+        self.politeness.wait(&url);
+        let previous = match (config, known.as_ref()) {
+            (Some(config), _) if !config.is_stored(&url) => None,
+            (_, Some(known)) if known.has_validators() => Some(known),
+            (config, _) => {
+                config.and_then(|c| c.previous_manifest()).and_then(|m| m.get_validators(&url))
+            }
         };
-        Ok((url, body))
+        let response = match previous {
+            Some(previous) => self.fetcher.get_conditional(&url, previous),
+            None => self.fetcher.get(&url),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = process::record_error(&self.indexer, &url, &e);
+                // Keep trying a page of a continuous crawl, but not too often
+                if config.and_then(|c| c.revisit()).is_some() {
+                    let _ = process::schedule_retry(&self.revisits, &url);
+                }
+                return Err(e);
+            }
+        };
+        process::record_response(&self.indexer, &url, &response, previous)?;
+        Ok((entry, response))
+    }
+
+    /// Download url without indexing it and return the body
+    pub fn fetch(&self, url: &Url) -> Result<Vec<u8>> {
+        let response = self.fetcher.get(url)?;
+        if !response.is_success() {
+            bail!(ErrorKind::BadStatus(response.get_status()));
+        }
+        Ok(response.into_body())
     }
 
     /// Crawl site recursively until queue is empty with a filter
     pub fn crawl_recursive(&mut self, config: CrawlerConfig, tx: Sender<Url>) {
         sync::set_stop(&self.stop, false);
         let sleep = Duration::from_millis(config.sleep_ms());
-        while !sync::is_queue_empty(&self.queue) && !sync::get_stop(&self.stop) {
-            let (v_url, body) = match self.crawl() {
-                Ok(t) => t,
-                Err(_e) => continue,
-            };
-            config.store(&v_url, &body);
-            let body = String::from_utf8_lossy(body.as_slice()).to_string();
-            let doc = Document::from(body.as_str());
-            match tx.send(v_url.clone()) {
-                Ok(_) => {}
-                Err(_) => continue,
+        let continuous = config.revisit().is_some();
+        while !sync::get_stop(&self.stop) {
+            if continuous {
+                let _ = sync::queue_revisits(&self.revisits, &self.queue);
             }
-            let hrefs = scrap_attr(&doc, "href");
-            for href in hrefs {
-                if href.starts_with('#') {
-                    continue;
-                }
-                let url = match href_to_url(&v_url, &href) {
-                    Some(u) => u,
-                    None => continue,
-                };
-                if config.filter(&v_url, &url) {
-                    if let Err(_e) = sync::add_to_queue(&self.indexer, &self.queue, url) {
-                        continue;
+            // Count this slave as active while it holds an entry, so others
+            // wait for the links it may queue instead of stopping
+            sync::add_running(&self.active);
+            let (entry, response) = match self.crawl_entry(Some(&config)) {
+                Ok(t) => t,
+                Err(e) => {
+                    sync::remove_running(&self.active);
+                    if let ErrorKind::QueueEmpty = *e.kind() {
+                        if sync::get_running(&self.active) == 0 &&
+                           sync::is_queue_empty(&self.queue) {
+                            if !continuous {
+                                break;
+                            }
+                            // Only revisits may come now
+                            thread::sleep(Duration::from_millis(REVISIT_POLL_MS));
+                            continue;
+                        }
+                        thread::sleep(Duration::from_millis(IDLE_MS));
                     }
+                    continue;
                 }
+            };
+            process::process_response(&config,
+                                      &self.indexer,
+                                      &self.queue,
+                                      &self.graph,
+                                      &entry,
+                                      response);
+            if continuous {
+                let _ = process::schedule_revisit(&self.indexer, &self.revisits, entry.get_url());
             }
+            let _ = tx.send(entry.get_url().clone());
+            sync::remove_running(&self.active);
             thread::sleep(sleep);
         }
         sync::remove_running(&self.running);
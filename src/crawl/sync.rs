@@ -2,12 +2,23 @@ use error::*;
 use hyper::client::IntoUrl;
 use hyper::Url;
 use indexer::Indexer;
-use std::collections::VecDeque;
+use super::frontier::{Frontier, FrontierEntry, Strategy};
+use super::revisit::RevisitScheduler;
+use super::shard::Sharded;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Indexer shared by slaves, sharded by host
+pub type SharedIndexer = Arc<Sharded<Indexer>>;
+
+/// Queue shared by slaves, sharded by host
+pub type SharedQueue = Arc<Sharded<Box<Frontier>>>;
 
 /// Return a mutex guard of T
-pub fn lock<T>(mutex: &Arc<Mutex<T>>) -> Result<MutexGuard<T>> {
+pub fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<T>> {
     match mutex.lock() {
         Ok(t) => Ok(t),
         Err(e) => bail!(ErrorKind::PoisonError(e.to_string())),
@@ -15,40 +26,129 @@ pub fn lock<T>(mutex: &Arc<Mutex<T>>) -> Result<MutexGuard<T>> {
 }
 
 /// Add an url to the queue
-pub fn add_to_queue<U: IntoUrl>(indexer: &Arc<Mutex<Indexer>>,
-                                queue: &Arc<Mutex<VecDeque<Url>>>,
+pub fn add_to_queue<U: IntoUrl>(indexer: &SharedIndexer,
+                                queue: &SharedQueue,
                                 url: U)
                                 -> Result<()> {
-    let url = url.into_url()?;
-    let mut queue = lock(queue)?;
-    if !queue.contains(&url) && !lock(indexer)?.is_indexed(&url) {
-        queue.push_back(url);
+    add_entry(indexer, queue, FrontierEntry::new(url.into_url()?))
+}
+
+/// Add an entry to the queue if its url was not seen
+///
+/// Indexer and queue shards are never locked together.
+pub fn add_entry(indexer: &SharedIndexer, queue: &SharedQueue, entry: FrontierEntry) -> Result<()> {
+    let seen = indexer.lock_url(entry.get_url())?.is_seen(entry.get_url());
+    if !seen {
+        queue.lock_url(entry.get_url())?.push(entry);
+    }
+    Ok(())
+}
+
+/// Queue again the urls due to be revisited, whether they were seen or not
+pub fn queue_revisits(revisits: &Mutex<RevisitScheduler>, queue: &SharedQueue) -> Result<()> {
+    let due = lock(revisits)?.take_due(Instant::now());
+    for url in due {
+        queue.lock_url(&url)?.push(FrontierEntry::new(url));
     }
     Ok(())
 }
 
 /// Get all item from queue
-pub fn queue_items(queue: &Arc<Mutex<VecDeque<Url>>>) -> Result<VecDeque<Url>> {
-    let queue = lock(queue)?;
-    Ok(queue.clone())
+pub fn queue_items(queue: &SharedQueue) -> Result<VecDeque<Url>> {
+    let mut items = VecDeque::new();
+    for i in 0..queue.len() {
+        items.extend(queue.lock(i)?.urls());
+    }
+    Ok(items)
+}
+
+/// Sort queue by score, highest first. Urls without score keep their order at the end.
+///
+/// Each shard is sorted on its own, slaves popping from shards in turn.
+pub fn order_queue(queue: &SharedQueue, scores: &HashMap<Url, f64>) -> Result<()> {
+    for i in 0..queue.len() {
+        let mut shard = queue.lock(i)?;
+        let mut entries = shard.drain();
+        entries.sort_by(|a, b| {
+            let sa = scores.get(a.get_url()).cloned().unwrap_or(-1.0);
+            let sb = scores.get(b.get_url()).cloned().unwrap_or(-1.0);
+            sb.partial_cmp(&sa).unwrap_or(CmpOrdering::Equal)
+        });
+        for entry in entries {
+            shard.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Replace each queue shard by the frontier created for its index, keeping queued entries
+pub fn set_frontiers<F>(queue: &SharedQueue, mut new_frontier: F) -> Result<()>
+    where F: FnMut(usize) -> Result<Box<Frontier>>
+{
+    for i in 0..queue.len() {
+        let mut frontier = new_frontier(i)?;
+        let mut shard = queue.lock(i)?;
+        for entry in shard.drain() {
+            frontier.push(entry);
+        }
+        *shard = frontier;
+    }
+    Ok(())
+}
+
+/// Replace queue shards by frontiers using strategy, keeping queued entries
+pub fn set_strategy(queue: &SharedQueue, strategy: &Strategy) -> Result<()> {
+    set_frontiers(queue, |_| Ok(strategy.frontier()))
+}
+
+/// Save queued entries so they can be resumed
+pub fn checkpoint_queue(queue: &SharedQueue) -> Result<()> {
+    for i in 0..queue.len() {
+        queue.lock(i)?.checkpoint()?;
+    }
+    Ok(())
 }
 
 /// Check if queue is empty
-pub fn is_queue_empty(queue: &Arc<Mutex<VecDeque<Url>>>) -> bool {
-    let queue = match lock(queue) {
-        Ok(q) => q,
-        Err(_) => return true,
-    };
-    queue.is_empty()
-}
-
-/// Pop an url from queue
-pub fn pop_queue(queue: &Arc<Mutex<VecDeque<Url>>>) -> Result<Url> {
-    let mut queue = lock(queue)?;
-    let url = queue.pop_front();
-    match url {
-        Some(u) => Ok(u),
-        None => bail!(ErrorKind::QueueEmpty),
+pub fn is_queue_empty(queue: &SharedQueue) -> bool {
+    (0..queue.len()).all(|i| match queue.lock(i) {
+        Ok(shard) => shard.is_empty(),
+        Err(_) => true,
+    })
+}
+
+/// Pop an entry from queue, looking at shards from shard `start`
+///
+/// The entry of highest priority among the heads of all shards is popped, so
+/// breadth-first, depth-first and best-first strategies hold across hosts. For
+/// frontiers without priority, the first entry found is popped. Shards are not
+/// locked together, an entry queued meanwhile may be popped after.
+pub fn pop_queue(queue: &SharedQueue, start: usize) -> Result<FrontierEntry> {
+    loop {
+        let mut best: Option<(usize, f64)> = None;
+        for i in start..start + queue.len() {
+            let mut shard = queue.lock(i)?;
+            match shard.head_priority() {
+                Some(p) => {
+                    if best.map(|(_, b)| p > b).unwrap_or(true) {
+                        best = Some((i, p));
+                    }
+                }
+                None => {
+                    if let Some(entry) = shard.pop() {
+                        return Ok(entry);
+                    }
+                }
+            }
+        }
+        let i = match best {
+            Some((i, _)) => i,
+            None => bail!(ErrorKind::QueueEmpty),
+        };
+        // Another slave may have emptied the shard since
+        if let Some(entry) = queue.lock(i)?.pop() {
+            return Ok(entry);
+        }
     }
 }
 
@@ -79,3 +179,56 @@ pub fn get_stop(stop: &Arc<AtomicBool>) -> bool {
 pub fn set_stop(stop_async: &Arc<AtomicBool>, stop: bool) {
     stop_async.store(stop, Ordering::Relaxed);
 }
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use super::{SharedQueue, pop_queue};
+    use super::super::frontier::{FrontierEntry, Strategy, score_known};
+    use super::super::shard::Sharded;
+
+    fn queue(strategy: Strategy, urls: &[(&str, usize)]) -> SharedQueue {
+        let queue: SharedQueue = Arc::new(Sharded::new(8, || strategy.frontier()));
+        for &(url, depth) in urls {
+            let mut entry = FrontierEntry::new(url.into_url().unwrap());
+            entry.set_depth(depth);
+            queue.lock_url(entry.get_url()).unwrap().push(entry);
+        }
+        queue
+    }
+
+    fn pop_all(queue: &SharedQueue) -> Vec<String> {
+        let mut urls = Vec::new();
+        let mut start = 0;
+        while let Ok(entry) = pop_queue(queue, start) {
+            urls.push(entry.get_url().to_string());
+            start += 1;
+        }
+        urls
+    }
+
+    const URLS: &'static [(&'static str, usize)] = &[("http://a.com/deep", 2),
+                                                      ("http://b.com/", 0),
+                                                      ("http://c.com/mid", 1),
+                                                      ("http://d.com/deeper", 3)];
+
+    #[test]
+    fn order_across_shards() {
+        let by_depth = vec!["http://b.com/", "http://c.com/mid", "http://a.com/deep",
+                            "http://d.com/deeper"];
+        assert_eq!(pop_all(&queue(Strategy::BreadthFirst, URLS)), by_depth);
+        let mut deepest = by_depth.clone();
+        deepest.reverse();
+        assert_eq!(pop_all(&queue(Strategy::DepthFirst, URLS)), deepest);
+
+        let mut scores = HashMap::new();
+        scores.insert("http://c.com/mid".into_url().unwrap(), 3.0);
+        scores.insert("http://a.com/deep".into_url().unwrap(), 2.0);
+        scores.insert("http://d.com/deeper".into_url().unwrap(), 1.0);
+        assert_eq!(pop_all(&queue(Strategy::BestFirst(score_known(scores)), URLS)),
+                   vec!["http://c.com/mid", "http://a.com/deep", "http://d.com/deeper",
+                        "http://b.com/"]);
+    }
+}
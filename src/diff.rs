@@ -0,0 +1,490 @@
+use common::{href_to_url, store_file_name};
+use error::*;
+use hyper::Url;
+use manifest::{MANIFEST_FILE, Manifest};
+use scrap::{scrap_links, scrap_text};
+use select::document::Document;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Longest texts compared line by line, beyond it they are shown as replaced
+const MAX_DIFF_CELLS: usize = 4000000;
+
+/// A crawl to compare: its manifest, and the pages it stored if any
+#[derive(Debug)]
+pub struct Crawl {
+    manifest: Manifest,
+    store: Option<PathBuf>,
+}
+
+impl Crawl {
+    /// Open a manifest file, or a store directory holding its manifest
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Crawl> {
+        let path = path.as_ref();
+        let (manifest_path, store) = if path.is_dir() {
+            (path.join(MANIFEST_FILE), Some(path.to_path_buf()))
+        } else {
+            (path.to_path_buf(), None)
+        };
+        let manifest = Manifest::read(BufReader::new(File::open(manifest_path)?))?;
+        Ok(Crawl {
+            manifest: manifest,
+            store: store,
+        })
+    }
+
+    /// Create a crawl from a manifest and the directory its pages are stored in
+    pub fn new(manifest: Manifest, store: Option<PathBuf>) -> Crawl {
+        Crawl {
+            manifest: manifest,
+            store: store,
+        }
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Return the body of url stored by the crawl
+    fn body(&self, url: &Url) -> Option<String> {
+        let path = match self.store {
+            Some(ref dir) => dir.join(store_file_name(url)),
+            None => return None,
+        };
+        let mut body = Vec::new();
+        match File::open(path).and_then(|mut f| f.read_to_end(&mut body)) {
+            Ok(_) => Some(String::from_utf8_lossy(&body).into_owned()),
+            Err(_) => None,
+        }
+    }
+
+    fn is_broken(&self, url: &Url) -> bool {
+        self.manifest.get(url).and_then(|e| e.get_status()).map(|s| s >= 400).unwrap_or(false)
+    }
+
+    /// Return the links of stored pages to pages which answered an error
+    fn broken_links(&self) -> HashSet<(Url, Url)> {
+        let mut broken = HashSet::new();
+        for (url, _) in self.manifest.entries() {
+            let body = match self.body(url) {
+                Some(body) => body,
+                None => continue,
+            };
+            let doc = Document::from(body.as_str());
+            for link in scrap_links(&doc) {
+                if let Some(target) = href_to_url(url, link.get_href()) {
+                    if self.is_broken(&target) {
+                        broken.insert((url.clone(), target));
+                    }
+                }
+            }
+        }
+        broken
+    }
+}
+
+/// A line of a text diff
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Compare two texts line by line
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    if old.len() * new.len() > MAX_DIFF_CELLS {
+        let mut lines: Vec<DiffLine> =
+            old.iter().map(|l| DiffLine::Removed(l.to_string())).collect();
+        lines.extend(new.iter().map(|l| DiffLine::Added(l.to_string())));
+        return lines;
+    }
+    // Length of the longest common subsequence of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lcs = vec![0usize; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                ::std::cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+            };
+        }
+    }
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() &&
+                  (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            lines.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    lines
+}
+
+/// A page whose status changed between two crawls
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusChange {
+    url: Url,
+    old: Option<u16>,
+    new: Option<u16>,
+}
+
+impl StatusChange {
+    /// Return the url of the page
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the status of the page in the old crawl
+    pub fn get_old(&self) -> Option<u16> {
+        self.old
+    }
+
+    /// Return the status of the page in the new crawl
+    pub fn get_new(&self) -> Option<u16> {
+        self.new
+    }
+}
+
+/// A page whose body changed between two crawls.
+///
+/// `text` - Changed lines of the extracted text, if both crawls stored the page
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentChange {
+    url: Url,
+    old_hash: Option<u64>,
+    new_hash: Option<u64>,
+    text: Option<Vec<DiffLine>>,
+}
+
+impl ContentChange {
+    /// Return the url of the page
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the hash of the body in the old crawl
+    pub fn get_old_hash(&self) -> Option<u64> {
+        self.old_hash
+    }
+
+    /// Return the hash of the body in the new crawl
+    pub fn get_new_hash(&self) -> Option<u64> {
+        self.new_hash
+    }
+
+    /// Return the changed lines of the extracted text
+    pub fn get_text(&self) -> Option<&Vec<DiffLine>> {
+        self.text.as_ref()
+    }
+}
+
+/// Differences between two crawls.
+///
+/// `content_changes` - Pages answering without error in both crawls whose body changed
+///
+/// `broken_links` - Links of the new crawl to a page answering an error,
+/// which were not broken in the old crawl, as source and target. Links are
+/// only known for pages stored by the new crawl.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CrawlDiff {
+    added: Vec<Url>,
+    removed: Vec<Url>,
+    status_changes: Vec<StatusChange>,
+    content_changes: Vec<ContentChange>,
+    broken_links: Vec<(Url, Url)>,
+}
+
+impl CrawlDiff {
+    /// Return the pages missing from the old crawl
+    pub fn get_added(&self) -> &Vec<Url> {
+        &self.added
+    }
+
+    /// Return the pages of the old crawl missing from the new crawl
+    pub fn get_removed(&self) -> &Vec<Url> {
+        &self.removed
+    }
+
+    /// Return the pages whose status changed
+    pub fn get_status_changes(&self) -> &Vec<StatusChange> {
+        &self.status_changes
+    }
+
+    /// Return the pages whose body changed
+    pub fn get_content_changes(&self) -> &Vec<ContentChange> {
+        &self.content_changes
+    }
+
+    /// Return the new broken links, as source and target
+    pub fn get_broken_links(&self) -> &Vec<(Url, Url)> {
+        &self.broken_links
+    }
+
+    /// Compare crawl new to crawl old, with a diff of the text of changed pages if text
+    pub fn compare(old: &Crawl, new: &Crawl, text: bool) -> CrawlDiff {
+        let mut diff = CrawlDiff::default();
+        for (url, entry) in new.manifest.entries() {
+            let previous = match old.manifest.get(url) {
+                Some(previous) => previous,
+                None => {
+                    diff.added.push(url.clone());
+                    continue;
+                }
+            };
+            if previous.get_status() != entry.get_status() {
+                diff.status_changes.push(StatusChange {
+                    url: url.clone(),
+                    old: previous.get_status(),
+                    new: entry.get_status(),
+                });
+            }
+            // The body of an error page is not content, its status change is enough
+            let broken = old.is_broken(url) || new.is_broken(url);
+            let changed = previous.get_hash().is_some() && entry.get_hash().is_some() &&
+                          previous.get_hash() != entry.get_hash();
+            if !broken && changed {
+                let text = if text {
+                    match (old.body(url), new.body(url)) {
+                        (Some(a), Some(b)) => {
+                            let a = scrap_text(&Document::from(a.as_str()));
+                            let b = scrap_text(&Document::from(b.as_str()));
+                            Some(diff_lines(&a, &b)
+                                .into_iter()
+                                .filter(|l| match *l {
+                                    DiffLine::Same(_) => false,
+                                    _ => true,
+                                })
+                                .collect())
+                        }
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+                diff.content_changes.push(ContentChange {
+                    url: url.clone(),
+                    old_hash: previous.get_hash(),
+                    new_hash: entry.get_hash(),
+                    text: text,
+                });
+            }
+        }
+        for (url, _) in old.manifest.entries() {
+            if new.manifest.get(url).is_none() {
+                diff.removed.push(url.clone());
+            }
+        }
+        let old_broken = old.broken_links();
+        let mut broken: Vec<(Url, Url)> = new.broken_links()
+            .into_iter()
+            .filter(|&(ref source, ref target)| {
+                // Without stored pages, links of the old crawl are unknown
+                if old.store.is_some() {
+                    !old_broken.contains(&(source.clone(), target.clone()))
+                } else {
+                    !old.is_broken(target)
+                }
+            })
+            .collect();
+        broken.sort_by(|a, b| (a.0.as_str(), a.1.as_str()).cmp(&(b.0.as_str(), b.1.as_str())));
+        diff.broken_links = broken;
+        diff
+    }
+
+    /// Check if the crawls are the same
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.status_changes.is_empty() &&
+        self.content_changes.is_empty() && self.broken_links.is_empty()
+    }
+
+    /// Write a report for humans
+    pub fn write_text<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w,
+                 "{} new, {} removed, {} status changes, {} content changes, {} new broken links",
+                 self.added.len(),
+                 self.removed.len(),
+                 self.status_changes.len(),
+                 self.content_changes.len(),
+                 self.broken_links.len())?;
+        for url in &self.added {
+            writeln!(w, "+ {}", url)?;
+        }
+        for url in &self.removed {
+            writeln!(w, "- {}", url)?;
+        }
+        for change in &self.status_changes {
+            writeln!(w,
+                     "! {} {} -> {}",
+                     change.url,
+                     status_str(change.old),
+                     status_str(change.new))?;
+        }
+        for change in &self.content_changes {
+            writeln!(w, "~ {}", change.url)?;
+            for line in change.text.iter().flat_map(|t| t) {
+                match *line {
+                    DiffLine::Removed(ref l) => writeln!(w, "    - {}", l)?,
+                    DiffLine::Added(ref l) => writeln!(w, "    + {}", l)?,
+                    DiffLine::Same(_) => {}
+                }
+            }
+        }
+        for &(ref source, ref target) in &self.broken_links {
+            writeln!(w, "x {} -> {}", source, target)?;
+        }
+        Ok(())
+    }
+
+    /// Return the report as JSON
+    pub fn to_json(&self) -> Value {
+        let urls = |urls: &[Url]| -> Value {
+            Value::Array(urls.iter().map(|u| Value::String(u.to_string())).collect())
+        };
+        let status = |s: Option<u16>| s.map(|s| json!(s)).unwrap_or(Value::Null);
+        let hash = |h: Option<u64>| h.map(|h| json!(format!("{:016x}", h))).unwrap_or(Value::Null);
+        json!({
+            "added": urls(&self.added),
+            "removed": urls(&self.removed),
+            "status_changes": self.status_changes.iter().map(|c| json!({
+                "url": c.url.to_string(),
+                "old": status(c.old),
+                "new": status(c.new)
+            })).collect::<Vec<_>>(),
+            "content_changes": self.content_changes.iter().map(|c| json!({
+                "url": c.url.to_string(),
+                "old_hash": hash(c.old_hash),
+                "new_hash": hash(c.new_hash),
+                "removed": c.text.as_ref().map(|t| text_lines(t, false)).unwrap_or(Value::Null),
+                "added": c.text.as_ref().map(|t| text_lines(t, true)).unwrap_or(Value::Null)
+            })).collect::<Vec<_>>(),
+            "broken_links": self.broken_links.iter().map(|&(ref s, ref t)| json!({
+                "source": s.to_string(),
+                "target": t.to_string()
+            })).collect::<Vec<_>>()
+        })
+    }
+
+    /// Write the report as indented JSON
+    pub fn write_json<W: Write>(&self, w: &mut W) -> Result<()> {
+        ::serde_json::to_writer_pretty(w, &self.to_json())?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+fn status_str(status: Option<u16>) -> String {
+    status.map(|s| s.to_string()).unwrap_or_else(|| "none".to_string())
+}
+
+/// Return the added or removed lines of a text diff
+fn text_lines(lines: &[DiffLine], added: bool) -> Value {
+    Value::Array(lines.iter()
+        .filter_map(|l| match (l, added) {
+            (&DiffLine::Added(ref l), true) |
+            (&DiffLine::Removed(ref l), false) => Some(Value::String(l.clone())),
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use common::store_file_name;
+    use hyper::client::IntoUrl;
+    use hyper::Url;
+    use manifest::{Manifest, ManifestEntry};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use super::{Crawl, CrawlDiff, DiffLine, diff_lines};
+
+    fn url(path: &str) -> Url {
+        format!("http://example.com/{}", path).into_url().unwrap()
+    }
+
+    fn entry(status: u16, hash: u64) -> ManifestEntry {
+        let mut entry = ManifestEntry::default();
+        entry.set_status(Some(status));
+        entry.set_hash(Some(hash));
+        entry
+    }
+
+    fn store(name: &str, pages: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("unibot-diff-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for &(path, body) in pages {
+            let mut file = File::create(dir.join(store_file_name(&url(path)))).unwrap();
+            file.write_all(body.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn diff_text_lines() {
+        let lines = diff_lines("a\nb\nc", "a\nc\nd");
+        assert_eq!(lines,
+                   vec![DiffLine::Same("a".to_string()),
+                        DiffLine::Removed("b".to_string()),
+                        DiffLine::Same("c".to_string()),
+                        DiffLine::Added("d".to_string())]);
+    }
+
+    #[test]
+    fn compare_crawls() {
+        let mut old = Manifest::new();
+        old.insert(url(""), entry(200, 1));
+        old.insert(url("a"), entry(200, 2));
+        old.insert(url("gone"), entry(200, 3));
+        old.insert(url("b"), entry(200, 4));
+        let mut new = Manifest::new();
+        new.insert(url(""), entry(200, 5));
+        let mut not_modified = entry(200, 2);
+        not_modified.set_not_modified(true);
+        new.insert(url("a"), not_modified);
+        new.insert(url("b"), entry(404, 6));
+        new.insert(url("c"), entry(200, 7));
+        let old_store = store("old", &[("", "<p>Hello</p><p>world</p><a href=\"/a\">a</a>")]);
+        let new_store = store("new",
+                              &[("", "<p>Hello</p><p>there</p><a href=\"/b\">b</a>\
+                                      <script>x</script>")]);
+        let diff = CrawlDiff::compare(&Crawl::new(old, Some(old_store.clone())),
+                                      &Crawl::new(new, Some(new_store.clone())),
+                                      true);
+        assert_eq!(diff.added, vec![url("c")]);
+        assert_eq!(diff.removed, vec![url("gone")]);
+        assert_eq!(diff.status_changes.len(), 1);
+        assert_eq!(diff.status_changes[0].new, Some(404));
+        assert_eq!(diff.content_changes.len(), 1);
+        assert_eq!(diff.content_changes[0].text,
+                   Some(vec![DiffLine::Removed("world".to_string()),
+                             DiffLine::Removed("a".to_string()),
+                             DiffLine::Added("there".to_string()),
+                             DiffLine::Added("b".to_string())]));
+        assert_eq!(diff.broken_links, vec![(url(""), url("b"))]);
+        let json = diff.to_json();
+        assert_eq!(json["content_changes"][0]["added"][1], "b");
+        let mut report = Vec::new();
+        diff.write_text(&mut report).unwrap();
+        assert!(String::from_utf8(report).unwrap().contains("x http://example.com/ -> \
+                                                             http://example.com/b"));
+        let _ = fs::remove_dir_all(old_store);
+        let _ = fs::remove_dir_all(new_store);
+    }
+}
@@ -0,0 +1,134 @@
+use hyper::header::Headers;
+use scrap::scrap_meta;
+use select::document::Document;
+
+/// Name of the crawler in robots directives meant for a single crawler
+pub const USER_AGENT: &'static str = "unibot";
+
+/// Directives with a value after `:`, which is not a crawler name then
+const VALUED: &'static [&'static str] = &["unavailable_after", "max-snippet", "max-image-preview",
+                                          "max-video-preview"];
+
+/// What a page asks crawlers, in `<meta name="robots">` or `X-Robots-Tag`.
+///
+/// `noindex` - Page must not be kept
+///
+/// `nofollow` - Links of the page must not be followed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Directives {
+    noindex: bool,
+    nofollow: bool,
+}
+
+impl Directives {
+    /// Check if the page must not be kept
+    pub fn is_noindex(&self) -> bool {
+        self.noindex
+    }
+
+    /// Check if the links of the page must not be followed
+    pub fn is_nofollow(&self) -> bool {
+        self.nofollow
+    }
+
+    /// Parse a comma separated list of directives, like `noindex, nofollow`
+    pub fn parse(content: &str) -> Directives {
+        let mut directives = Directives::default();
+        for directive in content.split(',') {
+            match directive.trim().to_lowercase().as_str() {
+                "noindex" => directives.noindex = true,
+                "nofollow" => directives.nofollow = true,
+                "none" => {
+                    directives.noindex = true;
+                    directives.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+        directives
+    }
+
+    /// Parse the value of an `X-Robots-Tag` header
+    ///
+    /// A value starting with a crawler name, like `otherbot: noindex`, only
+    /// counts if the name is unibot. The name is a single token before the first
+    /// `:`, so `noindex, unavailable_after: ...` is a list of directives.
+    pub fn parse_header(value: &str) -> Directives {
+        if let Some(i) = value.find(':') {
+            let agent = value[..i].trim().to_lowercase();
+            // A list of directives is not a crawler name
+            let token = !agent.is_empty() &&
+                        !agent.contains(|c: char| c == ',' || c.is_whitespace());
+            if token && !VALUED.contains(&agent.as_str()) {
+                if agent != USER_AGENT {
+                    return Directives::default();
+                }
+                return Directives::parse(&value[i + 1..]);
+            }
+        }
+        Directives::parse(value)
+    }
+
+    /// Return the directives of the `X-Robots-Tag` headers
+    pub fn from_headers(headers: &Headers) -> Directives {
+        let mut directives = Directives::default();
+        if let Some(values) = headers.get_raw("X-Robots-Tag") {
+            for value in values {
+                let value = String::from_utf8_lossy(value);
+                directives = directives.merge(Directives::parse_header(&value));
+            }
+        }
+        directives
+    }
+
+    /// Return the directives of the robots meta tags of document, for all
+    /// crawlers and for unibot
+    pub fn from_document(doc: &Document) -> Directives {
+        let all = scrap_meta(doc, "robots").map(|c| Directives::parse(&c)).unwrap_or_default();
+        let own = scrap_meta(doc, USER_AGENT).map(|c| Directives::parse(&c)).unwrap_or_default();
+        all.merge(own)
+    }
+
+    /// Return the directives asked by self or other
+    pub fn merge(self, other: Directives) -> Directives {
+        Directives {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::header::Headers;
+    use select::document::Document;
+    use super::Directives;
+
+    fn directives(noindex: bool, nofollow: bool) -> Directives {
+        Directives {
+            noindex: noindex,
+            nofollow: nofollow,
+        }
+    }
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(Directives::parse("NoIndex, follow"), directives(true, false));
+        assert_eq!(Directives::parse("none"), directives(true, true));
+        assert_eq!(Directives::parse("all"), directives(false, false));
+        assert_eq!(Directives::parse_header("otherbot: noindex"), directives(false, false));
+        assert_eq!(Directives::parse_header("unibot: nofollow"), directives(false, true));
+        assert_eq!(Directives::parse_header("unavailable_after: 25 Jun 2010 15:00:00 PST"),
+                   directives(false, false));
+        assert_eq!(Directives::parse_header("noindex, unavailable_after: 25 Jun 2010 15:00:00 PST"),
+                   directives(true, false));
+
+        let mut headers = Headers::new();
+        headers.set_raw("X-Robots-Tag",
+                        vec![b"otherbot: nofollow".to_vec(), b"noindex".to_vec()]);
+        assert_eq!(Directives::from_headers(&headers), directives(true, false));
+        let doc = Document::from("<meta name=\"robots\" content=\"noindex\">\
+                                  <meta name=\"unibot\" content=\"nofollow\">");
+        assert_eq!(Directives::from_document(&doc), directives(true, true));
+    }
+}
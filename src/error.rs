@@ -4,6 +4,10 @@ error_chain!{
         Io(::std::io::Error);
         Hyper(::hyper::Error);
         Url(::hyper::error::ParseError);
+        Xml(::xml::reader::Error);
+        Json(::serde_json::Error);
+        Toml(::toml::de::Error);
+        Regex(::regex::Error);
     }
 
     errors {
@@ -19,5 +23,49 @@ error_chain!{
             description("Queue has no item in it")
             display("Queue has no item in it")
         }
+        NoSlaves {
+            description("Crawler has no slave to fetch pages with")
+            display("Crawler has no slave, create slaves first")
+        }
+        BadStatus(status: u16) {
+            description("Server answered with an unexpected status")
+            display("Server answered with status {}", status)
+        }
+        InvalidFalsePositiveRate(rate: f64) {
+            description("Bloom filter false positive rate is invalid")
+            display("Invalid false positive rate {}, expected a rate between 0 and 1", rate)
+        }
+        InvalidFrontier(e: String) {
+            description("Frontier is invalid")
+            display("Invalid frontier: {}", e)
+        }
+        InvalidSitemap(e: String) {
+            description("Sitemap is invalid")
+            display("Invalid sitemap: {}", e)
+        }
+        InvalidResponse(e: String) {
+            description("HTTP response is invalid")
+            display("Invalid HTTP response: {}", e)
+        }
+        InvalidResolve(e: String) {
+            description("resolve override is invalid")
+            display("Invalid resolve override, expected host:port:addr: {}", e)
+        }
+        InvalidSelector(e: String) {
+            description("CSS selector is invalid")
+            display("Invalid CSS selector {}", e)
+        }
+        InvalidRules(e: String) {
+            description("Scraping rules are invalid")
+            display("Invalid scraping rules: {}", e)
+        }
+        InvalidXPath(e: String) {
+            description("XPath expression is invalid")
+            display("Invalid XPath expression {}", e)
+        }
+        CommandFailed(command: String, status: String) {
+            description("Command failed")
+            display("Command `{}` failed: {}", command, status)
+        }
     }
 }
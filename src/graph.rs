@@ -0,0 +1,384 @@
+use common::{escape_csv, escape_xml};
+use error::*;
+use hyper::Url;
+use scrap::LinkKind;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+
+/// Level at which graph nodes are exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    /// One node per page
+    Page,
+    /// One node per host, edges are weighted by the number of links
+    Host,
+}
+
+/// Format of an exported graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    GraphMl,
+    Csv,
+}
+
+impl GraphFormat {
+    /// Return the format from its name (`dot`, `graphml` or `csv`)
+    pub fn from_name(name: &str) -> Option<GraphFormat> {
+        match name.to_lowercase().as_str() {
+            "dot" | "gv" => Some(GraphFormat::Dot),
+            "graphml" => Some(GraphFormat::GraphMl),
+            "csv" => Some(GraphFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// A link from a page to another.
+///
+/// `source` - Id of the page containing the link
+///
+/// `target` - Id of the linked page
+///
+/// `anchor` - Anchor text of the link
+///
+/// `kind` - Kind of element the link comes from
+///
+/// `fragment` - Part of the target page linked, after `#` in the link
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    source: usize,
+    target: usize,
+    anchor: String,
+    kind: LinkKind,
+    fragment: Option<String>,
+}
+
+impl Edge {
+    /// Return the id of the source page
+    pub fn get_source(&self) -> usize {
+        self.source
+    }
+
+    /// Return the id of the target page
+    pub fn get_target(&self) -> usize {
+        self.target
+    }
+
+    /// Return the anchor text
+    pub fn get_anchor(&self) -> &str {
+        &self.anchor
+    }
+
+    /// Return the link kind
+    pub fn get_kind(&self) -> LinkKind {
+        self.kind
+    }
+
+    /// Return the fragment of the link
+    pub fn get_fragment(&self) -> Option<&str> {
+        self.fragment.as_ref().map(|f| f.as_str())
+    }
+}
+
+/// Record all links found while crawling
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    nodes: Vec<Url>,
+    index: HashMap<Url, usize>,
+    edges: Vec<Edge>,
+    sources: HashSet<usize>,
+}
+
+impl LinkGraph {
+    pub fn new() -> LinkGraph {
+        LinkGraph::default()
+    }
+
+    /// Return the id of url, adding it to nodes if needed
+    pub fn add_node(&mut self, mut url: Url) -> usize {
+        url.set_fragment(None);
+        if let Some(id) = self.index.get(&url) {
+            return *id;
+        }
+        let id = self.nodes.len();
+        self.index.insert(url.clone(), id);
+        self.nodes.push(url);
+        id
+    }
+
+    /// Add a link from source to target, keeping the fragment of target on the edge
+    pub fn add_edge(&mut self, source: Url, target: Url, anchor: String, kind: LinkKind) {
+        let fragment = target.fragment().map(|f| f.to_string());
+        let source = self.add_node(source);
+        let target = self.add_node(target);
+        self.sources.insert(source);
+        self.edges.push(Edge {
+            source: source,
+            target: target,
+            anchor: anchor,
+            kind: kind,
+            fragment: fragment,
+        });
+    }
+
+    /// Return the id of url if it is a node of the graph
+    pub fn get_node_id(&self, url: &Url) -> Option<usize> {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        self.index.get(&url).cloned()
+    }
+
+    /// Check if links from url were recorded
+    pub fn has_edges_from(&self, url: &Url) -> bool {
+        self.get_node_id(url).map(|id| self.sources.contains(&id)).unwrap_or(false)
+    }
+
+    /// Return the url of node id
+    pub fn get_node(&self, id: usize) -> &Url {
+        &self.nodes[id]
+    }
+
+    /// Return all nodes
+    pub fn get_nodes(&self) -> &Vec<Url> {
+        &self.nodes
+    }
+
+    /// Return all edges
+    pub fn get_edges(&self) -> &Vec<Edge> {
+        &self.edges
+    }
+
+    /// Return all edges pointing to url
+    pub fn edges_to(&self, url: &Url) -> Vec<&Edge> {
+        match self.get_node_id(url) {
+            Some(id) => self.edges.iter().filter(|e| e.target == id).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return all edges starting from url
+    pub fn edges_from(&self, url: &Url) -> Vec<&Edge> {
+        match self.get_node_id(url) {
+            Some(id) => self.edges.iter().filter(|e| e.source == id).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return hosts and the number of links between each of them
+    pub fn host_edges(&self) -> (Vec<String>, BTreeMap<(usize, usize), usize>) {
+        let mut hosts = Vec::new();
+        let mut host_ids = HashMap::new();
+        let node_hosts: Vec<usize> = self.nodes
+            .iter()
+            .map(|url| {
+                let host = url.host_str().unwrap_or("").to_string();
+                if let Some(id) = host_ids.get(&host) {
+                    return *id;
+                }
+                hosts.push(host.clone());
+                host_ids.insert(host, hosts.len() - 1);
+                hosts.len() - 1
+            })
+            .collect();
+        let mut weights = BTreeMap::new();
+        for edge in &self.edges {
+            let key = (node_hosts[edge.source], node_hosts[edge.target]);
+            *weights.entry(key).or_insert(0) += 1;
+        }
+        (hosts, weights)
+    }
+
+    /// Write graph to w
+    pub fn write<W: Write>(&self,
+                           w: &mut W,
+                           format: GraphFormat,
+                           granularity: Granularity)
+                           -> Result<()> {
+        match (format, granularity) {
+            (GraphFormat::Dot, Granularity::Page) => self.write_dot(w),
+            (GraphFormat::Dot, Granularity::Host) => self.write_host_dot(w),
+            (GraphFormat::GraphMl, Granularity::Page) => self.write_graphml(w),
+            (GraphFormat::GraphMl, Granularity::Host) => self.write_host_graphml(w),
+            (GraphFormat::Csv, Granularity::Page) => self.write_csv(w),
+            (GraphFormat::Csv, Granularity::Host) => self.write_host_csv(w),
+        }
+    }
+
+    fn write_dot<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "digraph links {{")?;
+        for (id, url) in self.nodes.iter().enumerate() {
+            writeln!(w, "  n{} [label=\"{}\"];", id, escape_dot(url.as_str()))?;
+        }
+        for edge in &self.edges {
+            writeln!(w,
+                     "  n{} -> n{} [label=\"{}\", kind=\"{}\"];",
+                     edge.source,
+                     edge.target,
+                     escape_dot(&edge.anchor),
+                     edge.kind)?;
+        }
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    fn write_host_dot<W: Write>(&self, w: &mut W) -> Result<()> {
+        let (hosts, weights) = self.host_edges();
+        writeln!(w, "digraph hosts {{")?;
+        for (id, host) in hosts.iter().enumerate() {
+            writeln!(w, "  h{} [label=\"{}\"];", id, escape_dot(host))?;
+        }
+        for (&(source, target), weight) in &weights {
+            writeln!(w,
+                     "  h{} -> h{} [label=\"{}\", weight={}];",
+                     source,
+                     target,
+                     weight,
+                     weight)?;
+        }
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    fn write_graphml<W: Write>(&self, w: &mut W) -> Result<()> {
+        write_graphml_header(w)?;
+        writeln!(w, r#"  <key id="anchor" for="edge" attr.name="anchor" attr.type="string"/>"#)?;
+        writeln!(w, r#"  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>"#)?;
+        writeln!(w, r#"  <graph id="links" edgedefault="directed">"#)?;
+        for (id, url) in self.nodes.iter().enumerate() {
+            writeln!(w,
+                     r#"    <node id="n{}"><data key="label">{}</data></node>"#,
+                     id,
+                     escape_xml(url.as_str()))?;
+        }
+        for edge in &self.edges {
+            write!(w, r#"    <edge source="n{}" target="n{}">"#, edge.source, edge.target)?;
+            writeln!(w,
+                     r#"<data key="anchor">{}</data><data key="kind">{}</data></edge>"#,
+                     escape_xml(&edge.anchor),
+                     edge.kind)?;
+        }
+        writeln!(w, "  </graph>\n</graphml>")?;
+        Ok(())
+    }
+
+    fn write_host_graphml<W: Write>(&self, w: &mut W) -> Result<()> {
+        let (hosts, weights) = self.host_edges();
+        write_graphml_header(w)?;
+        writeln!(w, r#"  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>"#)?;
+        writeln!(w, r#"  <graph id="hosts" edgedefault="directed">"#)?;
+        for (id, host) in hosts.iter().enumerate() {
+            writeln!(w,
+                     r#"    <node id="h{}"><data key="label">{}</data></node>"#,
+                     id,
+                     escape_xml(host))?;
+        }
+        for (&(source, target), weight) in &weights {
+            writeln!(w,
+                     r#"    <edge source="h{}" target="h{}"><data key="weight">{}</data></edge>"#,
+                     source,
+                     target,
+                     weight)?;
+        }
+        writeln!(w, "  </graph>\n</graphml>")?;
+        Ok(())
+    }
+
+    fn write_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "source,target,anchor,kind")?;
+        for edge in &self.edges {
+            writeln!(w,
+                     "{},{},{},{}",
+                     escape_csv(self.nodes[edge.source].as_str()),
+                     escape_csv(self.nodes[edge.target].as_str()),
+                     escape_csv(&edge.anchor),
+                     edge.kind)?;
+        }
+        Ok(())
+    }
+
+    fn write_host_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        let (hosts, weights) = self.host_edges();
+        writeln!(w, "source,target,weight")?;
+        for (&(source, target), weight) in &weights {
+            writeln!(w,
+                     "{},{},{}",
+                     escape_csv(&hosts[source]),
+                     escape_csv(&hosts[target]),
+                     weight)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_graphml_header<W: Write>(w: &mut W) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(w, r#"  <key id="label" for="node" attr.name="label" attr.type="string"/>"#)?;
+    Ok(())
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use scrap::LinkKind;
+    use super::{GraphFormat, Granularity, LinkGraph};
+
+    fn graph() -> LinkGraph {
+        let mut graph = LinkGraph::new();
+        let links = vec![("http://example.com/", "http://example.com/a", "A"),
+                         ("http://example.com/", "http://example.com/b#top", "B"),
+                         ("http://example.com/a", "http://example.com/b", "\"B\", again"),
+                         ("http://example.com/b", "http://google.com/", "Google")];
+        for (source, target, anchor) in links {
+            graph.add_edge(source.into_url().unwrap(),
+                           target.into_url().unwrap(),
+                           anchor.to_string(),
+                           LinkKind::Anchor);
+        }
+        graph
+    }
+
+    #[test]
+    fn nodes_and_edges() {
+        let graph = graph();
+        assert_eq!(graph.get_nodes().len(), 4);
+        assert_eq!(graph.get_edges().len(), 4);
+        let to_b = graph.edges_to(&"http://example.com/b".into_url().unwrap());
+        assert_eq!(to_b.len(), 2);
+        assert_eq!(graph.get_node(to_b[0].get_source()).as_str(), "http://example.com/");
+        assert!(graph.has_edges_from(&"http://example.com/a#top".into_url().unwrap()));
+        assert!(!graph.has_edges_from(&"http://google.com/".into_url().unwrap()));
+    }
+
+    #[test]
+    fn host_edges() {
+        let (hosts, weights) = graph().host_edges();
+        assert_eq!(hosts, vec!["example.com".to_string(), "google.com".to_string()]);
+        assert_eq!(weights[&(0, 0)], 3);
+        assert_eq!(weights[&(0, 1)], 1);
+    }
+
+    #[test]
+    fn write_csv() {
+        let mut buf = Vec::new();
+        graph().write(&mut buf, GraphFormat::Csv, Granularity::Page).unwrap();
+        let csv = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "source,target,anchor,kind");
+        assert_eq!(lines[3],
+                   "http://example.com/a,http://example.com/b,\"\"\"B\"\", again\",anchor");
+    }
+
+    #[test]
+    fn write_host_dot() {
+        let mut buf = Vec::new();
+        graph().write(&mut buf, GraphFormat::Dot, Granularity::Host).unwrap();
+        let dot = String::from_utf8(buf).unwrap();
+        assert!(dot.contains("h0 -> h1 [label=\"1\", weight=1];"));
+    }
+}
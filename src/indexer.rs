@@ -1,17 +1,51 @@
+use bloom::ScalableBloomFilter;
 use error::*;
 use hyper::client::IntoUrl;
 use hyper::Url;
+use page::PageInfo;
 use site::Site;
+use std::collections::HashMap;
 
 /// Handle all crawled url
-#[derive(Debug, Default)]
+///
+/// With a seen filter, urls are remembered by a bloom filter instead of the
+/// sites url lists, which can also be dropped to bound memory usage.
+#[derive(Debug)]
 pub struct Indexer {
     sites: Vec<Site>,
+    pages: HashMap<Url, PageInfo>,
+    seen: Option<ScalableBloomFilter>,
+    keep_urls: bool,
+}
+
+impl Default for Indexer {
+    fn default() -> Indexer {
+        Indexer::new()
+    }
 }
 
 impl Indexer {
     pub fn new() -> Indexer {
-        Indexer { sites: Vec::new() }
+        Indexer {
+            sites: Vec::new(),
+            pages: HashMap::new(),
+            seen: None,
+            keep_urls: true,
+        }
+    }
+
+    /// Create an indexer remembering urls with a bloom filter
+    ///
+    /// If `keep_urls` is false, sites url lists and page infos are not kept.
+    /// Fails if `fp_rate` is not between 0 and 1 excluded.
+    pub fn with_seen_filter(fp_rate: f64,
+                            initial_capacity: usize,
+                            keep_urls: bool)
+                            -> Result<Indexer> {
+        let mut indexer = Indexer::new();
+        indexer.seen = Some(ScalableBloomFilter::new(initial_capacity, fp_rate)?);
+        indexer.keep_urls = keep_urls;
+        Ok(indexer)
     }
 
     /// Add a url to indexer
@@ -19,6 +53,14 @@ impl Indexer {
     /// If url is not indexed, then it will create a new site with this url
     pub fn add_url<U: IntoUrl>(&mut self, url: U) -> Result<()> {
         let url = url.into_url()?;
+        if let Some(ref mut seen) = self.seen {
+            if !seen.insert(url.as_str().as_bytes()) {
+                bail!(ErrorKind::UrlAlreadyIndexed);
+            }
+        }
+        if !self.keep_urls {
+            return Ok(());
+        }
         for site in &mut self.sites {
             if site.contains_url(&url) {
                 bail!(ErrorKind::UrlAlreadyIndexed);
@@ -33,6 +75,75 @@ impl Indexer {
         Ok(())
     }
 
+    /// Move all urls and page infos of other into indexer, urls of both only once
+    pub fn absorb(&mut self, other: Indexer) {
+        for site in other.sites {
+            match self.sites.iter().position(|s| s.is_same_host(site.get_url())) {
+                Some(i) => {
+                    let urls = Some(site.get_url()).into_iter().chain(site.get_subs_url());
+                    for url in urls {
+                        if !self.sites[i].contains_url(url) {
+                            self.sites[i].add_sub_url(url.clone());
+                        }
+                    }
+                }
+                None => self.sites.push(site),
+            }
+        }
+        self.pages.extend(other.pages);
+        match (self.seen.take(), other.seen) {
+            (Some(mut seen), Some(other_seen)) => {
+                seen.absorb(other_seen);
+                self.seen = Some(seen);
+            }
+            (seen, other_seen) => self.seen = seen.or(other_seen),
+        }
+        self.keep_urls = self.keep_urls && other.keep_urls;
+    }
+
+    /// Set the information gathered while fetching url
+    pub fn set_page_info(&mut self, url: Url, info: PageInfo) {
+        if self.keep_urls {
+            self.pages.insert(url, info);
+        }
+    }
+
+    /// Return the information gathered while fetching url
+    pub fn get_page_info(&self, url: &Url) -> Option<&PageInfo> {
+        self.pages.get(url)
+    }
+
+    /// Return the information gathered while fetching url (mutable)
+    pub fn get_page_info_mut(&mut self, url: &Url) -> Option<&mut PageInfo> {
+        self.pages.get_mut(url)
+    }
+
+    /// Return all urls with the information gathered while fetching them
+    pub fn get_all_page_infos(&self) -> Vec<(&Url, &PageInfo)> {
+        self.pages.iter().collect()
+    }
+
+    /// Return the canonical url of the page of url, url itself if it declared none
+    pub fn get_canonical<'a>(&'a self, url: &'a Url) -> &'a Url {
+        self.pages.get(url).and_then(|info| info.get_canonical()).unwrap_or(url)
+    }
+
+    /// Return the urls of the pages declaring canonical as their canonical, but itself, sorted
+    pub fn get_duplicates(&self, canonical: &Url) -> Vec<&Url> {
+        let mut duplicates: Vec<&Url> = self.pages
+            .iter()
+            .filter(|&(url, info)| url != canonical && info.get_canonical() == Some(canonical))
+            .map(|(url, _)| url)
+            .collect();
+        duplicates.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        duplicates
+    }
+
+    /// Return the languages and urls of the translations declared by the page of url
+    pub fn get_alternates(&self, url: &Url) -> &[(String, Url)] {
+        self.pages.get(url).map(|info| info.get_alternates()).unwrap_or(&[])
+    }
+
     /// Return all sites that indexer provide
     pub fn get_sites(&self) -> &Vec<Site> {
         &self.sites
@@ -62,6 +173,16 @@ impl Indexer {
         vec
     }
 
+    /// Check if url was seen, using the seen filter if any
+    ///
+    /// With a seen filter, an url may be wrongly reported as seen.
+    pub fn is_seen(&self, url: &Url) -> bool {
+        match self.seen {
+            Some(ref seen) => seen.contains(url.as_str().as_bytes()),
+            None => self.is_indexed(url),
+        }
+    }
+
     /// Check if url is indexed
     pub fn is_indexed(&self, url: &Url) -> bool {
         for site in &self.sites {
@@ -76,6 +197,7 @@ impl Indexer {
 #[cfg(test)]
 mod unit_tests {
     use hyper::client::IntoUrl;
+    use page::PageInfo;
     use super::Indexer;
 
     fn add_set_of_url(indexer: &mut Indexer) {
@@ -144,4 +266,66 @@ mod unit_tests {
             assert_eq!(url.as_str(), urls[i]);
         }
     }
+
+    #[test]
+    fn seen_filter() {
+        let mut indexer = Indexer::with_seen_filter(0.01, 10, false).unwrap();
+        add_set_of_url(&mut indexer);
+        assert!(indexer.is_seen(&"http://example.com/hello".into_url().unwrap()));
+        assert!(!indexer.is_seen(&"http://bing.com/".into_url().unwrap()));
+        assert!(indexer.add_url("http://google.com/").is_err());
+        assert_eq!(indexer.get_sites().len(), 0);
+    }
+
+    #[test]
+    fn absorb() {
+        let mut indexer = Indexer::new();
+        indexer.add_url("http://example.com/").unwrap();
+        let mut other = Indexer::new();
+        add_set_of_url(&mut other);
+        indexer.absorb(other);
+        assert_eq!(indexer.get_sites().len(), 2);
+        assert_eq!(indexer.get_sites()[0].get_subs_url().len(), 2);
+        assert!(indexer.is_indexed(&"http://example.com/hello".into_url().unwrap()));
+        assert!(indexer.is_indexed(&"http://google.com/".into_url().unwrap()));
+    }
+
+    #[test]
+    fn page_info() {
+        let mut indexer = Indexer::new();
+        let url = "http://example.com/hello".into_url().unwrap();
+        let mut info = PageInfo::new();
+        info.set_status(Some(200));
+        indexer.set_page_info(url.clone(), info);
+        indexer.get_page_info_mut(&url).unwrap().set_noindex(true);
+        let info = indexer.get_page_info(&url).unwrap();
+        assert_eq!(info.get_status(), Some(200));
+        assert!(info.is_noindex());
+        assert!(indexer.get_page_info(&"http://example.com/".into_url().unwrap()).is_none());
+    }
+
+    #[test]
+    fn canonicals() {
+        let mut indexer = Indexer::new();
+        let canonical = "http://example.com/shoes".into_url().unwrap();
+        let french = "http://example.fr/chaussures".into_url().unwrap();
+        for url in &["http://example.com/shoes",
+                     "http://example.com/shoes?sort=price",
+                     "http://example.com/shoes?page=1"] {
+            let mut info = PageInfo::new();
+            info.set_canonical(Some(canonical.clone()));
+            info.set_alternates(vec![("fr".to_string(), french.clone())]);
+            indexer.set_page_info(url.into_url().unwrap(), info);
+        }
+        let duplicate = "http://example.com/shoes?sort=price".into_url().unwrap();
+        assert_eq!(indexer.get_canonical(&duplicate), &canonical);
+        assert_eq!(indexer.get_canonical(&french), &french);
+        assert_eq!(indexer.get_duplicates(&canonical)
+                       .iter()
+                       .map(|u| u.as_str())
+                       .collect::<Vec<_>>(),
+                   vec!["http://example.com/shoes?page=1", "http://example.com/shoes?sort=price"]);
+        assert_eq!(indexer.get_alternates(&duplicate), &[("fr".to_string(), french)][..]);
+        assert!(indexer.get_alternates(&canonical.join("boots").unwrap()).is_empty());
+    }
 }
@@ -1,12 +1,48 @@
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate futures_cpupool;
 extern crate hyper;
 extern crate hyper_native_tls;
+#[cfg(feature = "async")]
+extern crate native_tls;
+extern crate regex;
 extern crate select;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "async")]
+extern crate tokio_core;
+#[cfg(feature = "async")]
+extern crate tokio_io;
+#[cfg(feature = "async")]
+extern crate tokio_tls;
+extern crate toml;
+extern crate xml;
 
+pub mod bloom;
+pub mod canonical;
+pub mod check;
 mod common;
 pub mod crawl;
+pub mod diff;
+pub mod directives;
 pub mod error;
+pub mod graph;
 pub mod indexer;
+pub mod manifest;
+pub mod metadata;
+pub mod page;
+pub mod rank;
+pub mod rules;
 pub mod scrap;
+pub mod selector;
+pub mod simhash;
 pub mod site;
+pub mod sitemap;
+pub mod structured;
+pub mod tables;
+pub mod watch;
+pub mod xpath;
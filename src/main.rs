@@ -1,17 +1,46 @@
 extern crate clap;
+extern crate hyper;
 extern crate libunibot;
 extern crate term;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use hyper::Url;
+use hyper::client::IntoUrl;
+use libunibot::canonical::CanonicalReport;
 use libunibot::crawl::Crawler;
 use libunibot::crawl::config::CrawlerConfig;
+use libunibot::crawl::dns;
+use libunibot::check::{self, LinkReport};
+use libunibot::crawl::fetch::{self, Fetcher, HyperFetcher};
+use libunibot::crawl::frontier::{self, Strategy};
+use libunibot::crawl::pool::PoolConfig;
+use libunibot::crawl::revisit::RevisitConfig;
+use libunibot::diff::{Crawl, CrawlDiff};
+use libunibot::graph::{GraphFormat, Granularity};
+use libunibot::indexer::Indexer;
+use libunibot::manifest::{MANIFEST_FILE, Manifest, ManifestSummary};
+use libunibot::metadata;
+use libunibot::rank::{self, RankConfig};
+use libunibot::rules::{Rules, Scraper};
+use libunibot::selector::Selector;
+use libunibot::simhash::DuplicateReport;
+use libunibot::structured::ItemSink;
+use libunibot::tables::TableSink;
+use libunibot::sitemap;
+use libunibot::watch::{WatchConfig, Watcher};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::Path;
+use std::process;
 use std::thread;
+use std::time::Duration;
 
 fn main() {
     let app = App::new("unibot")
         .version("dev")
         .author("Bastien Badzioch <notkild@gmail.com")
         .about("Crawl the web")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("sites")
             .help("Sites to crawl")
             .takes_value(true)
@@ -30,37 +59,857 @@ fn main() {
         .arg(Arg::with_name("store")
             .long("store")
             .takes_value(true)
-            .help("Store crawled web page to store location"))
+            .help("Store crawled web page to store location, with the manifest of the crawl"))
+        .arg(Arg::with_name("sitemaps")
+            .long("sitemaps")
+            .help("Seed crawl with urls from sites sitemaps instead of sites"))
+        .arg(Arg::with_name("sitemap-priority")
+            .long("sitemap-priority")
+            .requires("sitemaps")
+            .help("Queue sitemaps urls by priority and last modification"))
+        .arg(Arg::with_name("export-sitemaps")
+            .long("export-sitemaps")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Write sitemaps of crawled sites to directory once crawl is done, one \
+                   directory per scheme, host and port"))
+        .arg(Arg::with_name("graph")
+            .long("graph")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Record links and write link graph to file once crawl is done"))
+        .arg(Arg::with_name("graph-format")
+            .long("graph-format")
+            .takes_value(true)
+            .possible_values(&["dot", "graphml", "csv"])
+            .help("Format of link graph (default: guessed from file extension)"))
+        .arg(Arg::with_name("graph-hosts")
+            .long("graph-hosts")
+            .help("Write link graph between hosts instead of pages"))
+        .arg(Arg::with_name("rank")
+            .long("rank")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write PageRank, HITS and degrees of crawled pages to file once crawl is done"))
+        .arg(Arg::with_name("prior-ranks")
+            .long("prior-ranks")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Order queue with ranks written by a previous crawl"))
+        .arg(Arg::with_name("strategy")
+            .long("strategy")
+            .takes_value(true)
+            .possible_values(&["bfs", "dfs", "best", "host"])
+            .help("Order in which urls are crawled across all hosts, bfs by depth, dfs deepest \
+                   first, best by score (default: bfs). With host, or with --frontier-dir, \
+                   hosts take turns"))
+        .arg(Arg::with_name("keywords")
+            .long("keywords")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Crawl first links whose anchor text contains keywords (best strategy)"))
+        .arg(Arg::with_name("boost")
+            .long("boost")
+            .takes_value(true)
+            .multiple(true)
+            .value_name("PATTERN")
+            .help("Crawl first urls containing pattern (best strategy)"))
+        .arg(Arg::with_name("frontier-dir")
+            .long("frontier-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Spill queued urls to directory, resuming urls spilled by a previous crawl"))
+        .arg(Arg::with_name("frontier-window")
+            .long("frontier-window")
+            .takes_value(true)
+            .requires("frontier-dir")
+            .help("Number of queued urls kept in memory with --frontier-dir (default: 100000)"))
+        .arg(Arg::with_name("seen-filter")
+            .long("seen-filter")
+            .takes_value(true)
+            .value_name("FP_RATE")
+            .help("Remember crawled urls with a bloom filter wrongly skipping urls at this rate"))
+        .arg(Arg::with_name("no-url-lists")
+            .long("no-url-lists")
+            .requires("seen-filter")
+            .help("Do not keep crawled urls of sites, only the bloom filter"))
+        .arg(Arg::with_name("async")
+            .long("async")
+            .help("Crawl with the async engine, needs unibot built with the async feature"))
+        .arg(Arg::with_name("concurrency")
+            .long("concurrency")
+            .takes_value(true)
+            .requires("async")
+            .help("Number of requests in flight with --async (default: 1000)"))
+        .arg(Arg::with_name("max-connections-per-host")
+            .long("max-connections-per-host")
+            .takes_value(true)
+            .help("Number of connections open at once to a host (default: 6)"))
+        .arg(Arg::with_name("idle-timeout")
+            .long("idle-timeout")
+            .takes_value(true)
+            .value_name("SECS")
+            .help("Close connections idle for this long (default: 90)"))
+        .arg(Arg::with_name("manifest")
+            .long("manifest")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("store")
+            .help("Ask pages listed in manifest and stored only if they changed, then write \
+                   the new manifest to it"))
+        .arg(Arg::with_name("continuous")
+            .long("continuous")
+            .help("Keep crawling until interrupted, revisiting pages more often when they change"))
+        .arg(Arg::with_name("revisit-min")
+            .long("revisit-min")
+            .takes_value(true)
+            .value_name("SECS")
+            .requires("continuous")
+            .help("Shortest time between two visits of a page (default: 60)"))
+        .arg(Arg::with_name("revisit-max")
+            .long("revisit-max")
+            .takes_value(true)
+            .value_name("SECS")
+            .requires("continuous")
+            .help("Longest time between two visits of a page (default: 86400)"))
+        .arg(Arg::with_name("revisit-site")
+            .long("revisit-site")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("HOST:MIN:MAX")
+            .requires("continuous")
+            .help("Revisit pages of HOST between MIN and MAX seconds"))
+        .arg(Arg::with_name("host-delay")
+            .long("host-delay")
+            .takes_value(true)
+            .value_name("MS")
+            .help("Wait this long between two requests to a host (default: 0)"))
+        .arg(Arg::with_name("per-ip")
+            .long("per-ip")
+            .help("Apply host delay and connection limits per resolved IP address"))
+        .arg(Arg::with_name("dns-ttl")
+            .long("dns-ttl")
+            .takes_value(true)
+            .value_name("SECS")
+            .help("Keep resolved host names this long (default: 300)"))
+        .arg(Arg::with_name("resolve")
+            .long("resolve")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("HOST:PORT:ADDR")
+            .help("Connect to ADDR for HOST and PORT, like curl"))
+        .arg(Arg::with_name("ignore-robots-directives")
+            .long("ignore-robots-directives")
+            .help("Store noindex pages and follow nofollow links, for audits"))
+        .arg(Arg::with_name("collapse-duplicates")
+            .long("collapse-duplicates")
+            .help("Do not store pages declaring another page as their canonical"))
+        .arg(Arg::with_name("canonicals")
+            .long("canonicals")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write duplicates and canonical conflicts to FILE once crawl is done, \
+                   as JSON if FILE ends with .json"))
+        .arg(Arg::with_name("metadata")
+            .long("metadata")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write the title, description, headings and other metadata of pages to FILE \
+                   as JSON lines once crawl is done"))
+        .arg(Arg::with_name("structured-data")
+            .long("structured-data")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write the JSON-LD, Microdata and RDFa items of each page to FILE as JSON \
+                   lines"))
+        .arg(Arg::with_name("item-type")
+            .long("item-type")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("TYPE")
+            .requires("structured-data")
+            .help("Only write items of this schema.org type, like Product"))
+        .arg(Arg::with_name("rules")
+            .long("rules")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("records")
+            .help("Read scraping rules mapping url patterns to selectors or XPath from FILE, \
+                   in TOML if it ends with .toml and in JSON else"))
+        .arg(Arg::with_name("records")
+            .long("records")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("rules")
+            .help("Write the record extracted by the rules from each page to FILE as JSON lines"))
+        .arg(Arg::with_name("tables")
+            .long("tables")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Write the data tables of each page to DIR as CSV files, listed in \
+                   DIR/index.json once crawl is done"))
+        .arg(Arg::with_name("near-duplicates")
+            .long("near-duplicates")
+            .takes_value(true)
+            .value_name("BITS")
+            .help("Detect pages whose text fingerprints differ by at most BITS bits"))
+        .arg(Arg::with_name("skip-near-duplicates")
+            .long("skip-near-duplicates")
+            .requires("near-duplicates")
+            .help("Do not follow links of near duplicates of pages already crawled"))
+        .arg(Arg::with_name("duplicates-report")
+            .long("duplicates-report")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("near-duplicates")
+            .help("Write the clusters of near duplicates of each site to FILE once crawl is done"))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Compare two crawls")
+            .arg(Arg::with_name("crawl-a")
+                .help("Old crawl, as a store directory or a manifest")
+                .required(true)
+                .value_name("CRAWL-A"))
+            .arg(Arg::with_name("crawl-b")
+                .help("New crawl, as a store directory or a manifest")
+                .required(true)
+                .value_name("CRAWL-B"))
+            .arg(Arg::with_name("text")
+                .long("text")
+                .help("Compare the text of changed pages stored by both crawls"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Write the report as JSON")))
+        .subcommand(SubCommand::with_name("check")
+            .about("Crawl sites and report broken links, exiting with 1 if any")
+            .arg(Arg::with_name("sites")
+                .help("Sites to check")
+                .multiple(true)
+                .required(true)
+                .value_name("SITE"))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of pages fetched at once (default: 4)"))
+            .arg(Arg::with_name("host-delay")
+                .long("host-delay")
+                .takes_value(true)
+                .value_name("MS")
+                .help("Minimum time between two requests to the same host (default: 100)"))
+            .arg(Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECS")
+                .help("Time after which a link not answering is broken (default: 30)"))
+            .arg(Arg::with_name("internal-only")
+                .long("internal-only")
+                .help("Do not check links to other sites"))
+            .arg(Arg::with_name("ignore-anchors")
+                .long("ignore-anchors")
+                .help("Do not check that pages have the anchors linked with #"))
+            .arg(Arg::with_name("junit")
+                .long("junit")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the report as JUnit XML to FILE"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the broken links as JSON to FILE")))
+        .subcommand(SubCommand::with_name("watch")
+            .about("Fetch pages at an interval and alert when they change")
+            .arg(Arg::with_name("urls")
+                .help("Pages to watch")
+                .multiple(true)
+                .value_name("URL"))
+            .arg(Arg::with_name("url-list")
+                .long("url-list")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Also watch the pages of FILE, one url per line"))
+            .arg(Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .value_name("SECS")
+                .help("Time between two checks of the pages (default: 300)"))
+            .arg(Arg::with_name("selector")
+                .long("selector")
+                .takes_value(true)
+                .value_name("CSS")
+                .help("Only compare the elements matching the CSS selector"))
+            .arg(Arg::with_name("state")
+                .long("state")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Directory keeping the last version of the pages (default: unibot-watch)"))
+            .arg(Arg::with_name("exec")
+                .long("exec")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Shell command run when a page changed, with UNIBOT_URL, \
+                       UNIBOT_OLD_HASH, UNIBOT_NEW_HASH and UNIBOT_DIFF set"))
+            .arg(Arg::with_name("once")
+                .long("once")
+                .help("Check the pages once and exit")))
         .get_matches();
 
+    if let Some(matches) = app.subcommand_matches("diff") {
+        diff(matches);
+        return;
+    }
+    if let Some(matches) = app.subcommand_matches("check") {
+        check(matches);
+        return;
+    }
+    if let Some(matches) = app.subcommand_matches("watch") {
+        watch(matches);
+        return;
+    }
+
     let sites = app.values_of("sites").unwrap();
     let site_only = app.is_present("site-only");
     let jobs = app.value_of("jobs").unwrap_or("1").trim().parse::<usize>().unwrap_or(1);
     let store_path = app.value_of("store");
+    let sitemaps = app.is_present("sitemaps");
+    let sitemap_priority = app.is_present("sitemap-priority");
+    let export_sitemaps = app.value_of("export-sitemaps");
+    let graph_path = app.value_of("graph");
+    let graph_format = app.value_of("graph-format")
+        .or_else(|| graph_path.and_then(|p| Path::new(p).extension()).and_then(|e| e.to_str()))
+        .and_then(GraphFormat::from_name)
+        .unwrap_or(GraphFormat::Csv);
+    let rank_path = app.value_of("rank");
+    let prior_ranks = app.value_of("prior-ranks");
+    let granularity = if app.is_present("graph-hosts") {
+        Granularity::Host
+    } else {
+        Granularity::Page
+    };
+
+    let frontier_dir = app.value_of("frontier-dir");
+    let frontier_window = app.value_of("frontier-window")
+        .and_then(|w| w.trim().parse::<usize>().ok())
+        .unwrap_or(100000);
+    let concurrency = app.value_of("concurrency")
+        .and_then(|c| c.trim().parse::<usize>().ok())
+        .unwrap_or(1000);
+    let max_per_host = app.value_of("max-connections-per-host")
+        .and_then(|m| m.trim().parse::<usize>().ok())
+        .unwrap_or(6);
+    let idle_timeout = app.value_of("idle-timeout")
+        .and_then(|t| t.trim().parse::<u64>().ok())
+        .unwrap_or(90);
+    let host_delay = app.value_of("host-delay")
+        .and_then(|d| d.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    let per_ip = app.is_present("per-ip");
+    let metadata_path = app.value_of("metadata");
+    let structured_data = match app.value_of("structured-data") {
+        Some(path) => {
+            let types = app.values_of("item-type")
+                .map(|t| t.map(|t| t.to_string()).collect())
+                .unwrap_or_else(Vec::new);
+            match File::create(path) {
+                Ok(f) => Some(ItemSink::new(Box::new(BufWriter::new(f)), types)),
+                Err(e) => {
+                    println!("Could not create {}: {}", path, e);
+                    process::exit(2);
+                }
+            }
+        }
+        None => None,
+    };
+    let scraper = match (app.value_of("rules"), app.value_of("records")) {
+        (Some(rules), Some(records)) => {
+            let rules = match Rules::open(rules) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    println!("Could not read rules {}: {}", rules, e);
+                    process::exit(2);
+                }
+            };
+            match File::create(records) {
+                Ok(f) => Some(Scraper::new(rules, Box::new(BufWriter::new(f)))),
+                Err(e) => {
+                    println!("Could not create {}: {}", records, e);
+                    process::exit(2);
+                }
+            }
+        }
+        _ => None,
+    };
+    let tables = match app.value_of("tables") {
+        Some(dir) => {
+            match TableSink::new(dir) {
+                Ok(sink) => Some(sink),
+                Err(e) => {
+                    println!("Could not create {}: {}", dir, e);
+                    process::exit(2);
+                }
+            }
+        }
+        None => None,
+    };
+    let near_duplicates = app.value_of("near-duplicates")
+        .and_then(|d| d.trim().parse::<u32>().ok());
+    let dns_ttl = app.value_of("dns-ttl")
+        .and_then(|t| t.trim().parse::<u64>().ok())
+        .unwrap_or(300);
+    let revisit_min = match app.value_of("revisit-min").map(|s| s.trim().parse::<u64>()) {
+        None => 60,
+        Some(Ok(min)) if min > 0 => min,
+        Some(_) => {
+            println!("Invalid --revisit-min, expected a number of seconds above 0");
+            process::exit(2);
+        }
+    };
+    let revisit_max = app.value_of("revisit-max")
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(24 * 3600);
+    let revisit = if app.is_present("continuous") {
+        let mut revisit = RevisitConfig::new()
+            .set_interval(Duration::from_secs(revisit_min), Duration::from_secs(revisit_max));
+        for site in app.values_of("revisit-site").into_iter().flat_map(|s| s) {
+            let fields: Vec<&str> = site.splitn(3, ':').collect();
+            let min = fields.get(1).and_then(|s| s.trim().parse::<u64>().ok());
+            let max = fields.get(2).and_then(|s| s.trim().parse::<u64>().ok());
+            match (min, max) {
+                (Some(min), Some(max)) if min > 0 => {
+                    revisit = revisit.set_site_interval(fields[0],
+                                                        Duration::from_secs(min),
+                                                        Duration::from_secs(max));
+                }
+                _ => println!("Invalid revisit site, expected host:min:max: {}", site),
+            }
+        }
+        Some(revisit)
+    } else {
+        None
+    };
+    let manifest_path = app.value_of("manifest");
+    let previous = manifest_path.and_then(|path| File::open(path).ok())
+        .map(|f| Manifest::read(BufReader::new(f)));
+    let previous = match previous {
+        Some(Ok(manifest)) => Some(manifest),
+        Some(Err(e)) => {
+            println!("Could not read manifest: {}", e);
+            None
+        }
+        None => None,
+    };
+    let seen_filter = match app.value_of("seen-filter") {
+        Some(rate) => {
+            match rate.trim().parse::<f64>() {
+                Ok(r) if r > 0.0 && r < 1.0 => Some(r),
+                _ => {
+                    println!("Invalid --seen-filter {}, expected a rate between 0 and 1", rate);
+                    process::exit(2);
+                }
+            }
+        }
+        None => None,
+    };
+    let keep_urls = !app.is_present("no-url-lists");
+    let strategy = app.value_of("strategy").unwrap_or("bfs");
+    let keywords: Vec<String> = app.values_of("keywords")
+        .map(|k| k.map(|k| k.to_string()).collect())
+        .unwrap_or_else(Vec::new);
+    let boosts: Vec<(String, f64)> = app.values_of("boost")
+        .map(|p| p.map(|p| (p.to_string(), 1.0)).collect())
+        .unwrap_or_else(Vec::new);
 
     let mut crawler = Crawler::new();
     crawler.create_slaves(jobs);
+    crawler.set_pool_config(PoolConfig::new()
+        .set_max_per_host(max_per_host)
+        .set_max_idle_per_host(max_per_host)
+        .set_idle_timeout(Duration::from_secs(idle_timeout))
+        .set_group_by_ip(per_ip));
+    let dns = crawler.dns();
+    dns.set_ttl(Duration::from_secs(dns_ttl), Duration::from_secs(dns_ttl.min(30)));
+    for resolve in app.values_of("resolve").into_iter().flat_map(|r| r) {
+        match dns::parse_override(resolve) {
+            Ok((host, port, addr)) => dns.add_override(&host, port, addr),
+            Err(e) => println!("{}", e),
+        }
+    }
+    if let Some(fp_rate) = seen_filter {
+        crawler.set_indexer(|| Indexer::with_seen_filter(fp_rate, 10000, keep_urls)).unwrap();
+    }
     for site in sites {
-        crawler.add_to_queue(site).unwrap();
+        if sitemaps {
+            match crawler.add_sitemaps(site, sitemap_priority) {
+                Ok(n) => println!("Found {} urls in {} sitemaps", n, site),
+                Err(e) => println!("Could not read sitemaps of {}: {}", site, e),
+            }
+        } else {
+            crawler.add_to_queue(site).unwrap();
+        }
+    }
+    let mut scorers = vec![(frontier::score_depth(), 1.0),
+                           (frontier::score_backlinks(), 1.0),
+                           (frontier::score_keywords(keywords), 2.0),
+                           (frontier::score_patterns(boosts), 1.0)];
+    if let Some(path) = prior_ranks {
+        let scores = File::open(path)
+            .map_err(From::from)
+            .and_then(|f| rank::read_scores(BufReader::new(f)));
+        match scores {
+            Ok(scores) => {
+                crawler.order_queue(&scores).unwrap();
+                // PageRank sums to one, scale it so an average page scores one
+                let weight = scores.len() as f64;
+                scorers.push((frontier::score_known(scores), weight));
+            }
+            Err(e) => println!("Could not read ranks: {}", e),
+        }
     }
-    let receivers = if site_only {
-        crawler.crawl_recursive(&CrawlerConfig::new_site_only()
-                .set_sleep_ms(500)
-                .set_store_path(store_path))
-            .unwrap()
+    let strategy = match strategy {
+        "best" => Strategy::BestFirst(frontier::score_sum(scorers)),
+        s => Strategy::from_name(s).unwrap_or_default(),
+    };
+    let config = if site_only {
+        CrawlerConfig::new_site_only()
     } else {
-        crawler.crawl_recursive(&CrawlerConfig::new().set_sleep_ms(500).set_store_path(store_path))
-            .unwrap()
+        CrawlerConfig::new()
     };
+    let config = config.set_sleep_ms(500)
+        .set_store_path(store_path)
+        .set_record_links(graph_path.is_some() || rank_path.is_some())
+        .set_strategy(strategy)
+        .set_frontier_dir(frontier_dir, frontier_window)
+        .set_host_delay_ms(host_delay)
+        .set_by_ip(per_ip)
+        .set_previous_manifest(previous.clone())
+        .set_revisit(revisit)
+        .set_concurrency(concurrency)
+        .set_obey_directives(!app.is_present("ignore-robots-directives"))
+        .set_collapse_duplicates(app.is_present("collapse-duplicates"))
+        .set_near_duplicates(near_duplicates, app.is_present("skip-near-duplicates"))
+        .set_record_metadata(metadata_path.is_some())
+        .set_structured_data(structured_data)
+        .set_scraper(scraper)
+        .set_tables(tables);
+    #[cfg(feature = "async")]
+    let receivers = if app.is_present("async") {
+        crawler.crawl_async(&config)
+    } else {
+        crawler.crawl_recursive(&config)
+    };
+    #[cfg(not(feature = "async"))]
+    let receivers = {
+        if app.is_present("async") {
+            println!("unibot was built without the async feature, crawling with threads");
+        }
+        crawler.crawl_recursive(&config)
+    };
+    let receivers = receivers.unwrap();
     while crawler.get_running() > 0 {
         for receiver in &receivers {
-            match receiver.try_recv() {
-                Ok(u) => {
-                    println!("Visited {}", u);
-                }
-                Err(_) => continue,
+            for u in receiver.try_iter() {
+                println!("Visited {}", u);
             }
         }
         thread::sleep(::std::time::Duration::from_secs(1));
     }
+    if let Some(e) = crawler.take_failure() {
+        println!("Crawl stopped: {}", e);
+    }
+    if let Err(e) = crawler.checkpoint() {
+        println!("Could not save queue: {}", e);
+    }
+    println!("{}", crawler.pool_stats());
+    println!("{}", crawler.dns_stats());
+    if let Some(store) = store_path {
+        // The store holds the manifest of its crawl, to diff it with another one
+        let manifest = crawler.manifest().unwrap();
+        if let Some(path) = manifest_path {
+            if let Some(ref previous) = previous {
+                println!("{}", ManifestSummary::compare(previous, &manifest));
+            }
+            write_manifest(&manifest, Path::new(path));
+        }
+        write_manifest(&manifest, &Path::new(store).join(MANIFEST_FILE));
+    }
+
+    let canonicals = app.value_of("canonicals");
+    let duplicates_report = app.value_of("duplicates-report");
+    let indexer = if export_sitemaps.is_some() || canonicals.is_some() ||
+                     duplicates_report.is_some() || metadata_path.is_some() {
+        Some(crawler.take_indexer().unwrap())
+    } else {
+        None
+    };
+    if let (Some(dir), Some(indexer)) = (export_sitemaps, indexer.as_ref()) {
+        match sitemap::export(indexer, dir) {
+            Ok(paths) => {
+                for path in paths {
+                    println!("Wrote {}", path.display());
+                }
+            }
+            Err(e) => println!("Could not write sitemaps: {}", e),
+        }
+    }
+
+    if let (Some(path), Some(indexer)) = (canonicals, indexer.as_ref()) {
+        let report = CanonicalReport::new(indexer);
+        let result = File::create(path).map_err(From::from).and_then(|f| {
+            let mut w = BufWriter::new(f);
+            if path.ends_with(".json") {
+                report.write_json(&mut w)
+            } else {
+                report.write_text(&mut w)
+            }
+        });
+        match result {
+            Ok(_) => println!("Wrote {}", path),
+            Err(e) => println!("Could not write canonicals: {}", e),
+        }
+    }
+
+    if let (Some(path), Some(indexer)) = (duplicates_report, indexer.as_ref()) {
+        let report = DuplicateReport::new(indexer, near_duplicates.unwrap_or(0));
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| report.write_text(&mut BufWriter::new(f)));
+        match result {
+            Ok(_) => println!("Wrote {}", path),
+            Err(e) => println!("Could not write near duplicates: {}", e),
+        }
+    }
+
+    if let (Some(path), Some(indexer)) = (metadata_path, indexer.as_ref()) {
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| metadata::write_json_lines(&mut BufWriter::new(f), indexer));
+        match result {
+            Ok(n) => println!("Wrote metadata of {} pages to {}", n, path),
+            Err(e) => println!("Could not write metadata: {}", e),
+        }
+    }
+
+    if let Some(tables) = config.tables() {
+        match tables.write_index() {
+            Ok((path, n)) => println!("Wrote index of {} tables to {}", n, path.display()),
+            Err(e) => println!("Could not write tables index: {}", e),
+        }
+    }
+
+    if let Some(path) = graph_path {
+        let graph = crawler.graph();
+        let graph = graph.lock().unwrap();
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| graph.write(&mut BufWriter::new(f), graph_format, granularity));
+        match result {
+            Ok(_) => println!("Wrote {}", path),
+            Err(e) => println!("Could not write link graph: {}", e),
+        }
+    }
+
+    if let Some(path) = rank_path {
+        let graph = crawler.graph();
+        let graph = graph.lock().unwrap();
+        let scores = rank::rank(&graph, &RankConfig::new());
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| rank::write_scores(&mut BufWriter::new(f), &graph, &scores));
+        match result {
+            Ok(_) => println!("Wrote {}", path),
+            Err(e) => println!("Could not write ranks: {}", e),
+        }
+    }
+}
+
+/// Write manifest to path, saying so if it fails
+fn write_manifest(manifest: &Manifest, path: &Path) {
+    let written = File::create(path)
+        .map_err(From::from)
+        .and_then(|f| manifest.write(&mut BufWriter::new(f)));
+    if let Err(e) = written {
+        println!("Could not write manifest: {}", e);
+    }
+}
+
+/// Print the differences between two crawls
+fn diff(matches: &ArgMatches) {
+    let open = |name: &str| {
+        let path = matches.value_of(name).unwrap();
+        match Crawl::open(path) {
+            Ok(crawl) => crawl,
+            Err(e) => {
+                println!("Could not read crawl {}: {}", path, e);
+                process::exit(2);
+            }
+        }
+    };
+    let (old, new) = (open("crawl-a"), open("crawl-b"));
+    let diff = CrawlDiff::compare(&old, &new, matches.is_present("text"));
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = if matches.is_present("json") {
+        diff.write_json(&mut out)
+    } else {
+        diff.write_text(&mut out)
+    };
+    if let Err(e) = result {
+        println!("Could not write diff: {}", e);
+        process::exit(2);
+    }
+}
+
+/// Check pages at an interval, alerting when they change
+fn watch(matches: &ArgMatches) {
+    let mut urls: Vec<String> =
+        matches.values_of("urls").into_iter().flat_map(|u| u).map(|u| u.to_string()).collect();
+    if let Some(path) = matches.value_of("url-list") {
+        match File::open(path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    let line = line.trim();
+                    if !line.is_empty() && !line.starts_with('#') {
+                        urls.push(line.to_string());
+                    }
+                }
+            }
+            Err(e) => {
+                println!("Could not read {}: {}", path, e);
+                process::exit(2);
+            }
+        }
+    }
+    let urls: Vec<Url> = urls.iter()
+        .filter_map(|u| match u.into_url() {
+            Ok(url) => Some(url),
+            Err(e) => {
+                println!("Invalid url {}: {}", u, e);
+                None
+            }
+        })
+        .collect();
+    if urls.is_empty() {
+        println!("No page to watch");
+        process::exit(2);
+    }
+    let selector = match matches.value_of("selector").map(Selector::parse) {
+        Some(Ok(selector)) => Some(selector),
+        Some(Err(e)) => {
+            println!("{}", e);
+            process::exit(2);
+        }
+        None => None,
+    };
+    let interval = matches.value_of("interval")
+        .and_then(|i| i.trim().parse::<u64>().ok())
+        .unwrap_or(300);
+    let config = WatchConfig::new(matches.value_of("state").unwrap_or("unibot-watch"))
+        .set_selector(selector)
+        .set_command(matches.value_of("exec").map(|c| c.to_string()));
+    let watcher = Watcher::new(Box::new(HyperFetcher::new()), config);
+    loop {
+        for url in &urls {
+            match watcher.check(url) {
+                Ok(Some(change)) => {
+                    println!("Changed {} (diff in {})", url, change.get_diff().display())
+                }
+                Ok(None) => {}
+                Err(e) => println!("Could not check {}: {}", url, e),
+            }
+        }
+        if matches.is_present("once") {
+            break;
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
+
+/// Crawl sites, check every link found and report the broken ones
+fn check(matches: &ArgMatches) {
+    let jobs = matches.value_of("jobs").and_then(|j| j.trim().parse::<usize>().ok()).unwrap_or(4);
+    let host_delay = matches.value_of("host-delay")
+        .and_then(|d| d.trim().parse::<u64>().ok())
+        .unwrap_or(100);
+    let timeout = matches.value_of("timeout")
+        .and_then(|t| t.trim().parse::<u64>().ok())
+        .unwrap_or(30);
+    let mut crawler = Crawler::new();
+    // Crawled and external pages are fetched alike
+    let pool = fetch::new_pool(PoolConfig::new(), crawler.dns());
+    let new_fetcher = || -> Box<Fetcher> {
+        let mut fetcher = HyperFetcher::with_pool(pool.clone());
+        fetcher.set_timeout(Some(Duration::from_secs(timeout)));
+        Box::new(fetcher)
+    };
+    crawler.create_slaves_with(jobs, &new_fetcher);
+    let mut hosts = Vec::new();
+    for site in matches.values_of("sites").unwrap() {
+        match site.into_url() {
+            Ok(url) => {
+                hosts.push(url.host_str().unwrap_or("").to_string());
+                crawler.add_to_queue(url).unwrap();
+            }
+            Err(e) => {
+                println!("Invalid site {}: {}", site, e);
+                process::exit(2);
+            }
+        }
+    }
+    // Pages of other hosts are only checked, not crawled
+    let internal = hosts.clone();
+    let config = CrawlerConfig::new()
+        .set_filter(move |_, url| internal.iter().any(|h| Some(h.as_str()) == url.host_str()))
+        .set_sleep_ms(0)
+        .set_host_delay_ms(host_delay)
+        .set_record_links(true)
+        .set_check_anchors(!matches.is_present("ignore-anchors"));
+    let receivers = crawler.crawl_recursive(&config).unwrap();
+    while crawler.get_running() > 0 {
+        for receiver in &receivers {
+            for _ in receiver.try_iter() {}
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let indexer = crawler.take_indexer().unwrap();
+    let graph = crawler.graph();
+    let graph = graph.lock().unwrap();
+    let mut statuses = check::crawled_statuses(&indexer);
+    let mut targets = check::unchecked_targets(&graph, &statuses);
+    if matches.is_present("internal-only") {
+        targets.retain(|url| hosts.iter().any(|h| Some(h.as_str()) == url.host_str()));
+    }
+    statuses.extend(check::check_urls(targets, jobs, &new_fetcher));
+    let mut report = LinkReport::new(&graph, &statuses);
+    report.add_missing_anchors(&graph, &indexer);
+    let stdout = io::stdout();
+    let _ = report.write_text(&mut stdout.lock());
+    if let Some(path) = matches.value_of("junit") {
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| report.write_junit(&mut BufWriter::new(f)));
+        if let Err(e) = result {
+            println!("Could not write JUnit report: {}", e);
+        }
+    }
+    if let Some(path) = matches.value_of("json") {
+        let result = File::create(path)
+            .map_err(From::from)
+            .and_then(|f| report.write_json(&mut BufWriter::new(f)));
+        if let Err(e) = result {
+            println!("Could not write JSON report: {}", e);
+        }
+    }
+    if !report.is_ok() {
+        process::exit(1);
+    }
 }
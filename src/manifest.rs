@@ -0,0 +1,330 @@
+use error::*;
+use hyper::Url;
+use hyper::client::IntoUrl;
+use indexer::Indexer;
+use page::PageInfo;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+
+/// Name of the manifest of a crawl in its store directory
+pub const MANIFEST_FILE: &'static str = "manifest.tsv";
+
+/// What a crawl learnt about a page, to re-crawl it conditionally.
+///
+/// `status` - HTTP status of the response, the one of the previous crawl if the page
+/// did not change
+///
+/// `not_modified` - If the page answered 304, its body being the one of the previous crawl
+///
+/// `etag` - Value of the `ETag` header, sent back in `If-None-Match`
+///
+/// `last_modified` - Value of the `Last-Modified` header, sent back in `If-Modified-Since`
+///
+/// `hash` - Hash of the body
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ManifestEntry {
+    status: Option<u16>,
+    not_modified: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    hash: Option<u64>,
+}
+
+impl ManifestEntry {
+    /// Create an entry from the information gathered while fetching a page
+    pub fn from_page_info(info: &PageInfo) -> ManifestEntry {
+        ManifestEntry {
+            status: info.get_status(),
+            not_modified: info.is_not_modified(),
+            etag: info.get_etag().map(|s| s.to_string()),
+            last_modified: info.get_last_modified().map(|s| s.to_string()),
+            hash: info.get_hash(),
+        }
+    }
+
+    /// Return the HTTP status of the response
+    pub fn get_status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Set the HTTP status of the response
+    pub fn set_status(&mut self, status: Option<u16>) {
+        self.status = status;
+    }
+
+    /// Check if the page answered 304
+    pub fn is_not_modified(&self) -> bool {
+        self.not_modified
+    }
+
+    /// Set if the page answered 304
+    pub fn set_not_modified(&mut self, not_modified: bool) {
+        self.not_modified = not_modified;
+    }
+
+    /// Return the value of the `ETag` header
+    pub fn get_etag(&self) -> Option<&str> {
+        self.etag.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set the value of the `ETag` header
+    pub fn set_etag(&mut self, etag: Option<String>) {
+        self.etag = etag;
+    }
+
+    /// Return the value of the `Last-Modified` header
+    pub fn get_last_modified(&self) -> Option<&str> {
+        self.last_modified.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set the value of the `Last-Modified` header
+    pub fn set_last_modified(&mut self, last_modified: Option<String>) {
+        self.last_modified = last_modified;
+    }
+
+    /// Return the hash of the body
+    pub fn get_hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Set the hash of the body
+    pub fn set_hash(&mut self, hash: Option<u64>) {
+        self.hash = hash;
+    }
+
+    /// Check if the page can be asked only if it changed
+    pub fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+/// Pages of a crawl, written as tab separated values so the next crawl only
+/// downloads what changed
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    entries: HashMap<Url, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Manifest {
+        Manifest::default()
+    }
+
+    /// Add the pages fetched by indexer
+    pub fn add_indexer(&mut self, indexer: &Indexer) {
+        for (url, info) in indexer.get_all_page_infos() {
+            self.entries.insert(url.clone(), ManifestEntry::from_page_info(info));
+        }
+    }
+
+    pub fn insert(&mut self, url: Url, entry: ManifestEntry) {
+        self.entries.insert(url, entry);
+    }
+
+    pub fn get(&self, url: &Url) -> Option<&ManifestEntry> {
+        self.entries.get(url)
+    }
+
+    /// Return the entry of url if it can be asked only if it changed
+    pub fn get_validators(&self, url: &Url) -> Option<&ManifestEntry> {
+        self.entries.get(url).and_then(|e| if e.has_validators() { Some(e) } else { None })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return all urls with their entry, sorted by url
+    pub fn entries(&self) -> Vec<(&Url, &ManifestEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        entries
+    }
+
+    /// Write manifest, one page per line
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "url\tstatus\tetag\tlast_modified\thash\tnot_modified")?;
+        for (url, entry) in self.entries() {
+            writeln!(w,
+                     "{}\t{}\t{}\t{}\t{}\t{}",
+                     url,
+                     entry.status.map(|s| s.to_string()).unwrap_or_default(),
+                     escape_field(entry.etag.as_ref()),
+                     escape_field(entry.last_modified.as_ref()),
+                     entry.hash.map(|h| format!("{:016x}", h)).unwrap_or_default(),
+                     if entry.not_modified { "1" } else { "" })?;
+        }
+        Ok(())
+    }
+
+    /// Read a manifest written by `write`, skipping invalid lines
+    ///
+    /// Manifests written before `not_modified` was recorded are read too.
+    pub fn read<R: BufRead>(r: R) -> Result<Manifest> {
+        let mut manifest = Manifest::new();
+        for line in r.lines().skip(1) {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 5 && fields.len() != 6 {
+                continue;
+            }
+            let url = match fields[0].into_url() {
+                Ok(url) => url,
+                Err(_) => continue,
+            };
+            manifest.insert(url,
+                            ManifestEntry {
+                                status: fields[1].parse().ok(),
+                                not_modified: fields.get(5) == Some(&"1"),
+                                etag: parse_field(fields[2]),
+                                last_modified: parse_field(fields[3]),
+                                hash: u64::from_str_radix(fields[4], 16).ok(),
+                            });
+        }
+        Ok(manifest)
+    }
+}
+
+/// Keep a header value on one tab separated field
+fn escape_field(value: Option<&String>) -> String {
+    value.map(|v| v.replace(|c| c == '\t' || c == '\n' || c == '\r', " ")).unwrap_or_default()
+}
+
+fn parse_field(field: &str) -> Option<String> {
+    if field.is_empty() {
+        None
+    } else {
+        Some(field.to_string())
+    }
+}
+
+/// Changes between the manifests of two crawls.
+///
+/// `new` - Pages missing from the previous crawl
+///
+/// `changed` - Pages downloaded again with another body
+///
+/// `unchanged` - Pages not modified, or downloaded again with the same body
+///
+/// `missing` - Pages of the previous crawl not crawled again
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ManifestSummary {
+    new: usize,
+    changed: usize,
+    unchanged: usize,
+    missing: usize,
+}
+
+impl ManifestSummary {
+    /// Return the number of pages missing from the previous crawl
+    pub fn get_new(&self) -> usize {
+        self.new
+    }
+
+    /// Return the number of pages downloaded again with another body
+    pub fn get_changed(&self) -> usize {
+        self.changed
+    }
+
+    /// Return the number of pages not modified, or downloaded again with the same body
+    pub fn get_unchanged(&self) -> usize {
+        self.unchanged
+    }
+
+    /// Return the number of pages of the previous crawl not crawled again
+    pub fn get_missing(&self) -> usize {
+        self.missing
+    }
+
+    /// Compare the manifest of a crawl to the one of the previous crawl
+    pub fn compare(previous: &Manifest, current: &Manifest) -> ManifestSummary {
+        let mut summary = ManifestSummary::default();
+        for (url, entry) in &current.entries {
+            match previous.get(url) {
+                None => summary.new += 1,
+                Some(_) if entry.not_modified => summary.unchanged += 1,
+                Some(old) if old.hash.is_some() && old.hash == entry.hash => {
+                    summary.unchanged += 1
+                }
+                Some(_) => summary.changed += 1,
+            }
+        }
+        summary.missing = previous.entries.keys().filter(|u| current.get(u).is_none()).count();
+        summary
+    }
+}
+
+impl fmt::Display for ManifestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} new pages, {} changed, {} unchanged, {} not crawled again",
+               self.new,
+               self.changed,
+               self.unchanged,
+               self.missing)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use std::io::Cursor;
+    use super::{Manifest, ManifestEntry, ManifestSummary};
+
+    fn entry(status: u16, etag: Option<&str>, hash: u64) -> ManifestEntry {
+        ManifestEntry {
+            status: Some(status),
+            not_modified: false,
+            etag: etag.map(|e| e.to_string()),
+            last_modified: None,
+            hash: Some(hash),
+        }
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut manifest = Manifest::new();
+        manifest.insert("http://example.com/".into_url().unwrap(),
+                        entry(200, Some("\"abc\tdef\""), 42));
+        let mut not_modified = ManifestEntry::default();
+        not_modified.not_modified = true;
+        manifest.insert("http://example.com/a".into_url().unwrap(), not_modified.clone());
+        let mut out = Vec::new();
+        manifest.write(&mut out).unwrap();
+        let read = Manifest::read(Cursor::new(out)).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read.get(&"http://example.com/".into_url().unwrap()),
+                   Some(&entry(200, Some("\"abc def\""), 42)));
+        assert_eq!(read.get(&"http://example.com/a".into_url().unwrap()),
+                   Some(&not_modified));
+    }
+
+    #[test]
+    fn summary() {
+        let mut previous = Manifest::new();
+        let mut current = Manifest::new();
+        for (i, url) in ["a", "b", "c", "d"].iter().enumerate() {
+            let url = format!("http://example.com/{}", url).into_url().unwrap();
+            previous.insert(url, entry(200, Some("x"), i as u64));
+        }
+        let mut not_modified = entry(200, Some("x"), 0);
+        not_modified.not_modified = true;
+        current.insert("http://example.com/a".into_url().unwrap(), not_modified);
+        current.insert("http://example.com/b".into_url().unwrap(), entry(200, None, 1));
+        current.insert("http://example.com/c".into_url().unwrap(), entry(200, None, 7));
+        current.insert("http://example.com/e".into_url().unwrap(), entry(200, None, 3));
+        let summary = ManifestSummary::compare(&previous, &current);
+        assert_eq!(summary,
+                   ManifestSummary {
+                       new: 1,
+                       changed: 1,
+                       unchanged: 2,
+                       missing: 1,
+                   });
+    }
+}
@@ -0,0 +1,267 @@
+use common::href_to_url;
+use error::*;
+use hyper::Url;
+use indexer::Indexer;
+use scrap::{LinkKind, scrap_canonical, scrap_links, scrap_meta, scrap_text};
+use select::document::Document;
+use select::predicate::{Element, Name};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// What a page tells about itself.
+///
+/// `headings` - Level and text of the `<h1>` to `<h6>` of the page, in document order
+///
+/// `word_count` - Number of words of the visible text
+///
+/// `internal_links`, `external_links` - Number of links to the same host and to others
+///
+/// `nofollow_links` - Number of links with `rel="nofollow"`
+///
+/// `open_graph`, `twitter` - `og:` and `twitter:` meta tags, by property name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageMetadata {
+    title: Option<String>,
+    description: Option<String>,
+    keywords: Vec<String>,
+    language: Option<String>,
+    canonical: Option<Url>,
+    headings: Vec<(u8, String)>,
+    word_count: usize,
+    internal_links: usize,
+    external_links: usize,
+    nofollow_links: usize,
+    open_graph: BTreeMap<String, String>,
+    twitter: BTreeMap<String, String>,
+}
+
+impl PageMetadata {
+    /// Extract the metadata of the document of the page at url
+    pub fn from_document(url: &Url, doc: &Document) -> PageMetadata {
+        let mut metadata = PageMetadata::default();
+        metadata.title = doc.find(Name("title")).iter().next().map(|t| normalize(&t.text()));
+        metadata.description = scrap_meta(doc, "description").map(|d| normalize(&d));
+        metadata.keywords = scrap_meta(doc, "keywords")
+            .map(|k| k.split(',').map(normalize).filter(|k| !k.is_empty()).collect())
+            .unwrap_or_else(Vec::new);
+        metadata.language = doc.find(Name("html"))
+            .iter()
+            .next()
+            .and_then(|html| html.attr("lang").map(|l| l.trim().to_string()))
+            .or_else(|| meta_http_equiv(doc, "content-language"));
+        metadata.canonical = scrap_canonical(doc).and_then(|href| href_to_url(url, &href));
+        for node in doc.find(Element).iter() {
+            let level = match node.name() {
+                Some("h1") => 1,
+                Some("h2") => 2,
+                Some("h3") => 3,
+                Some("h4") => 4,
+                Some("h5") => 5,
+                Some("h6") => 6,
+                _ => continue,
+            };
+            metadata.headings.push((level, normalize(&node.text())));
+        }
+        // The title is not part of the text of the page
+        let text = match doc.find(Name("body")).iter().next() {
+            Some(body) => scrap_text(&Document::from(body.html().as_str())),
+            None => scrap_text(doc),
+        };
+        metadata.word_count = text.split_whitespace().count();
+        for link in scrap_links(doc) {
+            if link.get_kind() != LinkKind::Anchor {
+                continue;
+            }
+            match href_to_url(url, link.get_href()) {
+                Some(ref target) if target.host_str() == url.host_str() => {
+                    metadata.internal_links += 1
+                }
+                Some(_) => metadata.external_links += 1,
+                None => continue,
+            }
+            if link.get_rel().iter().any(|r| r == "nofollow") {
+                metadata.nofollow_links += 1;
+            }
+        }
+        for node in doc.find(Name("meta")).iter() {
+            let property = match node.attr("property").or_else(|| node.attr("name")) {
+                Some(property) => property.trim().to_lowercase(),
+                None => continue,
+            };
+            let content = match node.attr("content") {
+                Some(content) => content.trim().to_string(),
+                None => continue,
+            };
+            if property.starts_with("og:") {
+                metadata.open_graph.entry(property[3..].to_string()).or_insert(content);
+            } else if property.starts_with("twitter:") {
+                metadata.twitter.entry(property[8..].to_string()).or_insert(content);
+            }
+        }
+        metadata
+    }
+
+    /// Return the text of the `<title>` of the page
+    pub fn get_title(&self) -> Option<&str> {
+        self.title.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the content of the description meta tag
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the keywords of the keywords meta tag
+    pub fn get_keywords(&self) -> &Vec<String> {
+        &self.keywords
+    }
+
+    /// Return the language of the page
+    pub fn get_language(&self) -> Option<&str> {
+        self.language.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the canonical url declared by the page
+    pub fn get_canonical(&self) -> Option<&Url> {
+        self.canonical.as_ref()
+    }
+
+    /// Return the level and text of the headings, in document order
+    pub fn get_headings(&self) -> &Vec<(u8, String)> {
+        &self.headings
+    }
+
+    /// Return the number of words of the visible text
+    pub fn get_word_count(&self) -> usize {
+        self.word_count
+    }
+
+    /// Return the number of links to the same host
+    pub fn get_internal_links(&self) -> usize {
+        self.internal_links
+    }
+
+    /// Return the number of links to other hosts
+    pub fn get_external_links(&self) -> usize {
+        self.external_links
+    }
+
+    /// Return the number of links with `rel="nofollow"`
+    pub fn get_nofollow_links(&self) -> usize {
+        self.nofollow_links
+    }
+
+    /// Return the `og:` meta tags, by property name
+    pub fn get_open_graph(&self) -> &BTreeMap<String, String> {
+        &self.open_graph
+    }
+
+    /// Return the `twitter:` meta tags, by property name
+    pub fn get_twitter(&self) -> &BTreeMap<String, String> {
+        &self.twitter
+    }
+
+    /// Return the metadata of the page at url as JSON
+    pub fn to_json(&self, url: &Url) -> Value {
+        let headings: Vec<Value> = self.headings
+            .iter()
+            .map(|&(level, ref text)| json!({"level": level, "text": text}))
+            .collect();
+        json!({
+            "url": url.to_string(),
+            "title": self.title,
+            "description": self.description,
+            "keywords": self.keywords,
+            "language": self.language,
+            "canonical": self.canonical.as_ref().map(|c| c.to_string()),
+            "headings": headings,
+            "word_count": self.word_count,
+            "internal_links": self.internal_links,
+            "external_links": self.external_links,
+            "nofollow_links": self.nofollow_links,
+            "open_graph": self.open_graph,
+            "twitter": self.twitter
+        })
+    }
+}
+
+/// Collapse the whitespace of text
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Return the content of the meta tag with http-equiv (case insensitive)
+fn meta_http_equiv(doc: &Document, name: &str) -> Option<String> {
+    doc.find(Name("meta"))
+        .iter()
+        .find(|node| node.attr("http-equiv").map(|h| h.to_lowercase() == name).unwrap_or(false))
+        .and_then(|node| node.attr("content").map(|c| c.trim().to_string()))
+}
+
+/// Write the metadata of the pages of indexer as JSON lines, one page per line sorted by url
+pub fn write_json_lines<W: Write>(w: &mut W, indexer: &Indexer) -> Result<usize> {
+    let mut pages: Vec<(&Url, &PageMetadata)> = indexer.get_all_page_infos()
+        .into_iter()
+        .filter_map(|(url, info)| info.get_metadata().map(|m| (url, m)))
+        .collect();
+    pages.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    for &(url, metadata) in &pages {
+        ::serde_json::to_writer(&mut *w, &metadata.to_json(url))?;
+        w.write_all(b"\n")?;
+    }
+    Ok(pages.len())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use indexer::Indexer;
+    use page::PageInfo;
+    use select::document::Document;
+    use serde_json::{self, Value};
+    use super::{PageMetadata, write_json_lines};
+
+    #[test]
+    fn extract_metadata() {
+        let url = "http://example.com/shoes/".into_url().unwrap();
+        let html = "<html lang=\"en\"><head><title> Shoes\n| Shop </title>\
+                    <meta name=\"description\" content=\"All our shoes\">\
+                    <meta name=\"keywords\" content=\"shoes, boots,, sandals\">\
+                    <link rel=\"canonical\" href=\"/shoes\">\
+                    <meta property=\"og:title\" content=\"Shoes\">\
+                    <meta property=\"og:image\" content=\"http://example.com/s.png\">\
+                    <meta name=\"twitter:card\" content=\"summary\"></head>\
+                    <body><h1>Shoes</h1><p>Walk in comfort</p><h2>Boots <em>new</em></h2>\
+                    <a href=\"boots\">Boots</a><a href=\"http://other.com/\" rel=\"nofollow\">x</a>\
+                    <a href=\"mailto:shop@example.com\">mail</a></body></html>";
+        let metadata = PageMetadata::from_document(&url, &Document::from(html));
+        assert_eq!(metadata.title, Some("Shoes | Shop".to_string()));
+        assert_eq!(metadata.description, Some("All our shoes".to_string()));
+        assert_eq!(metadata.keywords, vec!["shoes", "boots", "sandals"]);
+        assert_eq!(metadata.language, Some("en".to_string()));
+        assert_eq!(metadata.canonical, Some("http://example.com/shoes".into_url().unwrap()));
+        assert_eq!(metadata.headings,
+                   vec![(1, "Shoes".to_string()), (2, "Boots new".to_string())]);
+        assert_eq!(metadata.word_count, 9);
+        assert_eq!((metadata.internal_links, metadata.external_links, metadata.nofollow_links),
+                   (1, 1, 1));
+        assert_eq!(metadata.open_graph.get("image").map(|s| s.as_str()),
+                   Some("http://example.com/s.png"));
+        assert_eq!(metadata.twitter.get("card").map(|s| s.as_str()), Some("summary"));
+
+        let mut indexer = Indexer::new();
+        let mut info = PageInfo::new();
+        info.set_metadata(Some(metadata));
+        indexer.set_page_info(url.clone(), info);
+        indexer.set_page_info("http://example.com/".into_url().unwrap(), PageInfo::new());
+        let mut out = Vec::new();
+        assert_eq!(write_json_lines(&mut out, &indexer).unwrap(), 1);
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        let line: Value = serde_json::from_str(out.trim()).unwrap();
+        assert_eq!(line["url"], json!("http://example.com/shoes/"));
+        assert_eq!(line["headings"][1]["level"], json!(2));
+        assert_eq!(line["open_graph"]["title"], json!("Shoes"));
+    }
+}
@@ -0,0 +1,207 @@
+use hyper::Url;
+use metadata::PageMetadata;
+use std::collections::HashSet;
+
+/// Information gathered while fetching a page.
+///
+/// `status` - HTTP status of the response, the one of the previous crawl if not modified
+///
+/// `not_modified` - If page answered 304, its body being the one of the previous crawl
+///
+/// `last_modified` - Value of the `Last-Modified` header
+///
+/// `etag` - Value of the `ETag` header
+///
+/// `hash` - Hash of the body
+///
+/// `simhash` - SimHash fingerprint of the text, to find near duplicates
+///
+/// `noindex` - If page asked not to be indexed
+///
+/// `nofollow` - If page asked for its links not to be followed
+///
+/// `canonical` - Canonical url declared by the page
+///
+/// `alternates` - Languages and urls of the translations declared by the page
+///
+/// `redirect` - Url the page redirected to
+///
+/// `error` - Why the page could not be fetched
+///
+/// `anchors` - Ids and anchor names of the page, if they were recorded
+///
+/// `metadata` - What the page tells about itself, if it was recorded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PageInfo {
+    status: Option<u16>,
+    not_modified: bool,
+    last_modified: Option<String>,
+    etag: Option<String>,
+    hash: Option<u64>,
+    simhash: Option<u64>,
+    noindex: bool,
+    nofollow: bool,
+    canonical: Option<Url>,
+    alternates: Vec<(String, Url)>,
+    redirect: Option<Url>,
+    error: Option<String>,
+    anchors: Option<HashSet<String>>,
+    metadata: Option<PageMetadata>,
+}
+
+impl PageInfo {
+    /// Create an empty page info
+    pub fn new() -> PageInfo {
+        PageInfo::default()
+    }
+
+    /// Return the HTTP status
+    pub fn get_status(&self) -> Option<u16> {
+        self.status
+    }
+
+    /// Set the HTTP status
+    pub fn set_status(&mut self, status: Option<u16>) {
+        self.status = status;
+    }
+
+    /// Check if page did not change since the previous crawl
+    pub fn is_not_modified(&self) -> bool {
+        self.not_modified
+    }
+
+    /// Set if page did not change since the previous crawl
+    pub fn set_not_modified(&mut self, not_modified: bool) {
+        self.not_modified = not_modified;
+    }
+
+    /// Return the `Last-Modified` header value
+    pub fn get_last_modified(&self) -> Option<&str> {
+        self.last_modified.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set the `Last-Modified` header value
+    pub fn set_last_modified(&mut self, last_modified: Option<String>) {
+        self.last_modified = last_modified;
+    }
+
+    /// Return the `ETag` header value
+    pub fn get_etag(&self) -> Option<&str> {
+        self.etag.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set the `ETag` header value
+    pub fn set_etag(&mut self, etag: Option<String>) {
+        self.etag = etag;
+    }
+
+    /// Return the hash of the body
+    pub fn get_hash(&self) -> Option<u64> {
+        self.hash
+    }
+
+    /// Set the hash of the body
+    pub fn set_hash(&mut self, hash: Option<u64>) {
+        self.hash = hash;
+    }
+
+    /// Return the SimHash fingerprint of the text
+    pub fn get_simhash(&self) -> Option<u64> {
+        self.simhash
+    }
+
+    /// Set the SimHash fingerprint of the text
+    pub fn set_simhash(&mut self, simhash: Option<u64>) {
+        self.simhash = simhash;
+    }
+
+    /// Check if page asked not to be indexed
+    pub fn is_noindex(&self) -> bool {
+        self.noindex
+    }
+
+    /// Set if page asked not to be indexed
+    pub fn set_noindex(&mut self, noindex: bool) {
+        self.noindex = noindex;
+    }
+
+    /// Check if page asked for its links not to be followed
+    pub fn is_nofollow(&self) -> bool {
+        self.nofollow
+    }
+
+    /// Set if page asked for its links not to be followed
+    pub fn set_nofollow(&mut self, nofollow: bool) {
+        self.nofollow = nofollow;
+    }
+
+    /// Return the canonical url
+    pub fn get_canonical(&self) -> Option<&Url> {
+        self.canonical.as_ref()
+    }
+
+    /// Set the canonical url
+    pub fn set_canonical(&mut self, canonical: Option<Url>) {
+        self.canonical = canonical;
+    }
+
+    /// Return the languages and urls of the translations of the page
+    pub fn get_alternates(&self) -> &[(String, Url)] {
+        &self.alternates
+    }
+
+    /// Set the languages and urls of the translations of the page
+    pub fn set_alternates(&mut self, alternates: Vec<(String, Url)>) {
+        self.alternates = alternates;
+    }
+
+    /// Return the url the page redirected to
+    pub fn get_redirect(&self) -> Option<&Url> {
+        self.redirect.as_ref()
+    }
+
+    /// Set the url the page redirected to
+    pub fn set_redirect(&mut self, redirect: Option<Url>) {
+        self.redirect = redirect;
+    }
+
+    /// Return why the page could not be fetched
+    pub fn get_error(&self) -> Option<&str> {
+        self.error.as_ref().map(|s| s.as_str())
+    }
+
+    /// Set why the page could not be fetched
+    pub fn set_error(&mut self, error: Option<String>) {
+        self.error = error;
+    }
+
+    /// Return the ids and anchor names of the page
+    pub fn get_anchors(&self) -> Option<&HashSet<String>> {
+        self.anchors.as_ref()
+    }
+
+    /// Set the ids and anchor names of the page
+    pub fn set_anchors(&mut self, anchors: Option<HashSet<String>>) {
+        self.anchors = anchors;
+    }
+
+    /// Return the metadata of the page
+    pub fn get_metadata(&self) -> Option<&PageMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Set the metadata of the page
+    pub fn set_metadata(&mut self, metadata: Option<PageMetadata>) {
+        self.metadata = metadata;
+    }
+
+    /// Check if page is canonical for url
+    ///
+    /// A page without canonical declaration is its own canonical.
+    pub fn is_canonical(&self, url: &Url) -> bool {
+        match self.canonical {
+            Some(ref c) => c == url,
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use common::escape_csv;
+use error::*;
+use graph::LinkGraph;
+use hyper::Url;
+use hyper::client::IntoUrl;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, Write};
+
+/// Settings of graph analysis
+#[derive(Debug, Clone)]
+pub struct RankConfig {
+    damping: f64,
+    epsilon: f64,
+    max_iterations: usize,
+}
+
+impl RankConfig {
+    pub fn new() -> RankConfig {
+        RankConfig {
+            damping: 0.85,
+            epsilon: 1e-6,
+            max_iterations: 100,
+        }
+    }
+
+    pub fn damping(&self) -> f64 {
+        self.damping
+    }
+
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    pub fn max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+
+    pub fn set_damping(mut self, damping: f64) -> RankConfig {
+        self.damping = damping;
+        self
+    }
+
+    pub fn set_epsilon(mut self, epsilon: f64) -> RankConfig {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn set_max_iterations(mut self, max_iterations: usize) -> RankConfig {
+        self.max_iterations = max_iterations;
+        self
+    }
+}
+
+impl Default for RankConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Importance scores of a page
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageScore {
+    pagerank: f64,
+    hub: f64,
+    authority: f64,
+    in_degree: usize,
+    out_degree: usize,
+}
+
+impl PageScore {
+    /// Return the PageRank of the page
+    pub fn get_pagerank(&self) -> f64 {
+        self.pagerank
+    }
+
+    /// Return the HITS hub score of the page
+    pub fn get_hub(&self) -> f64 {
+        self.hub
+    }
+
+    /// Return the HITS authority score of the page
+    pub fn get_authority(&self) -> f64 {
+        self.authority
+    }
+
+    /// Return the number of distinct pages linking to the page
+    pub fn get_in_degree(&self) -> usize {
+        self.in_degree
+    }
+
+    /// Return the number of distinct pages the page links to
+    pub fn get_out_degree(&self) -> usize {
+        self.out_degree
+    }
+}
+
+/// Return distinct links of graph as adjacency lists, ignoring self links
+fn adjacency(graph: &LinkGraph) -> Vec<Vec<usize>> {
+    let mut seen = HashSet::new();
+    let mut out = vec![Vec::new(); graph.get_nodes().len()];
+    for edge in graph.get_edges() {
+        let (source, target) = (edge.get_source(), edge.get_target());
+        if source != target && seen.insert((source, target)) {
+            out[source].push(target);
+        }
+    }
+    out
+}
+
+/// Return in-degree and out-degree of each node, counting distinct links
+pub fn degrees(graph: &LinkGraph) -> (Vec<usize>, Vec<usize>) {
+    let out = adjacency(graph);
+    let mut in_degrees = vec![0; out.len()];
+    for targets in &out {
+        for &target in targets {
+            in_degrees[target] += 1;
+        }
+    }
+    (in_degrees, out.iter().map(|t| t.len()).collect())
+}
+
+/// Compute PageRank of each node.
+///
+/// Rank of nodes without outlinks is spread over all nodes.
+pub fn pagerank(graph: &LinkGraph, config: &RankConfig) -> Vec<f64> {
+    let out = adjacency(graph);
+    let n = out.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let d = config.damping;
+    let mut ranks = vec![1.0 / n as f64; n];
+    for _ in 0..config.max_iterations {
+        let dangling: f64 = out.iter()
+            .zip(&ranks)
+            .filter(|&(targets, _)| targets.is_empty())
+            .map(|(_, r)| *r)
+            .sum();
+        let base = (1.0 - d) / n as f64 + d * dangling / n as f64;
+        let mut next = vec![base; n];
+        for (source, targets) in out.iter().enumerate() {
+            let share = d * ranks[source] / targets.len() as f64;
+            for &target in targets {
+                next[target] += share;
+            }
+        }
+        let delta: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+        ranks = next;
+        if delta < config.epsilon {
+            break;
+        }
+    }
+    ranks
+}
+
+/// Compute HITS hub and authority scores of each node
+pub fn hits(graph: &LinkGraph, config: &RankConfig) -> (Vec<f64>, Vec<f64>) {
+    let out = adjacency(graph);
+    let n = out.len();
+    let mut hubs = vec![1.0; n];
+    let mut authorities = vec![1.0; n];
+    for _ in 0..config.max_iterations {
+        let mut next_auth = vec![0.0; n];
+        for (source, targets) in out.iter().enumerate() {
+            for &target in targets {
+                next_auth[target] += hubs[source];
+            }
+        }
+        normalize(&mut next_auth);
+        let mut next_hubs: Vec<f64> = out.iter()
+            .map(|targets| targets.iter().map(|&t| next_auth[t]).sum())
+            .collect();
+        normalize(&mut next_hubs);
+        let delta: f64 = next_hubs.iter()
+            .zip(&hubs)
+            .chain(next_auth.iter().zip(&authorities))
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        hubs = next_hubs;
+        authorities = next_auth;
+        if delta < config.epsilon {
+            break;
+        }
+    }
+    (hubs, authorities)
+}
+
+fn normalize(scores: &mut Vec<f64>) {
+    let norm = scores.iter().map(|s| s * s).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for score in scores.iter_mut() {
+            *score /= norm;
+        }
+    }
+}
+
+/// Compute all scores of each node
+pub fn rank(graph: &LinkGraph, config: &RankConfig) -> Vec<PageScore> {
+    let ranks = pagerank(graph, config);
+    let (hubs, authorities) = hits(graph, config);
+    let (in_degrees, out_degrees) = degrees(graph);
+    (0..ranks.len())
+        .map(|i| {
+            PageScore {
+                pagerank: ranks[i],
+                hub: hubs[i],
+                authority: authorities[i],
+                in_degree: in_degrees[i],
+                out_degree: out_degrees[i],
+            }
+        })
+        .collect()
+}
+
+/// Write scores as csv, highest PageRank first
+pub fn write_scores<W: Write>(w: &mut W, graph: &LinkGraph, scores: &[PageScore]) -> Result<()> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|a, b| {
+        scores[*b].pagerank.partial_cmp(&scores[*a].pagerank).unwrap_or(Ordering::Equal)
+    });
+    writeln!(w, "url,pagerank,in_degree,out_degree,hub,authority")?;
+    for id in order {
+        let score = &scores[id];
+        writeln!(w,
+                 "{},{},{},{},{},{}",
+                 escape_csv(graph.get_node(id).as_str()),
+                 score.pagerank,
+                 score.in_degree,
+                 score.out_degree,
+                 score.hub,
+                 score.authority)?;
+    }
+    Ok(())
+}
+
+/// Read PageRank of each url from a csv written by `write_scores`
+pub fn read_scores<R: BufRead>(r: R) -> Result<HashMap<Url, f64>> {
+    let mut scores = HashMap::new();
+    for line in r.lines().skip(1) {
+        let line = line?;
+        // Urls containing a comma are quoted, the score is always after the last one.
+        let fields: Vec<&str> = line.rsplitn(6, ',').collect();
+        if fields.len() != 6 {
+            continue;
+        }
+        let url = fields[5].trim_matches('"').replace("\"\"", "\"");
+        if let (Ok(url), Ok(score)) = (url.into_url(), fields[4].parse::<f64>()) {
+            scores.insert(url, score);
+        }
+    }
+    Ok(scores)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use graph::LinkGraph;
+    use hyper::client::IntoUrl;
+    use scrap::LinkKind;
+    use super::{RankConfig, degrees, hits, pagerank, rank, read_scores, write_scores};
+
+    fn graph(links: &[(&str, &str)]) -> LinkGraph {
+        let mut graph = LinkGraph::new();
+        for &(source, target) in links {
+            graph.add_edge(format!("http://example.com/{}", source).into_url().unwrap(),
+                           format!("http://example.com/{}", target).into_url().unwrap(),
+                           String::new(),
+                           LinkKind::Anchor);
+        }
+        graph
+    }
+
+    #[test]
+    fn pagerank_sums_to_one() {
+        // c is dangling
+        let graph = graph(&[("a", "b"), ("b", "a"), ("a", "c"), ("b", "c"), ("a", "c")]);
+        let ranks = pagerank(&graph, &RankConfig::new());
+        let sum: f64 = ranks.iter().sum();
+        assert!((sum - 1.0).abs() < 1e-6);
+        assert!(ranks[2] > ranks[0]);
+        assert!((ranks[0] - ranks[1]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn degrees_ignore_duplicates() {
+        let graph = graph(&[("a", "b"), ("a", "b"), ("a", "a"), ("c", "b")]);
+        let (in_degrees, out_degrees) = degrees(&graph);
+        assert_eq!(in_degrees, vec![0, 2, 0]);
+        assert_eq!(out_degrees, vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn hits_hub_and_authority() {
+        let graph = graph(&[("hub", "x"), ("hub", "y"), ("other", "x")]);
+        let (hubs, authorities) = hits(&graph, &RankConfig::new());
+        assert!(hubs[0] > hubs[2]);
+        assert!(authorities[1] > authorities[3]);
+        assert_eq!(hubs[1], 0.0);
+    }
+
+    #[test]
+    fn write_and_read_scores() {
+        let graph = graph(&[("a", "b"), ("b", "a,b")]);
+        let scores = rank(&graph, &RankConfig::new());
+        let mut buf = Vec::new();
+        write_scores(&mut buf, &graph, &scores).unwrap();
+        let read = read_scores(buf.as_slice()).unwrap();
+        assert_eq!(read.len(), 3);
+        let url = "http://example.com/a,b".into_url().unwrap();
+        assert!((read[&url] - scores[2].pagerank).abs() < 1e-9);
+    }
+}
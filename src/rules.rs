@@ -0,0 +1,445 @@
+use common::JsonLines;
+use error::*;
+use hyper::Url;
+use regex::Regex;
+use select::document::Document;
+use selector::Selector;
+use serde_json::{self, Map, Value};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use toml;
+use xpath::XPath;
+
+/// Query selecting the elements of a field
+#[derive(Debug, Clone)]
+pub enum Query {
+    Css(Selector),
+    /// XPath expression, whose value is taken as is if it is not a node-set
+    XPath(XPath),
+}
+
+/// How to extract a field from the elements matching a selector or an XPath expression.
+///
+/// `attr` - Attribute to take the value of, the text of the element if none
+///
+/// `multiple` - Take every matching element as a list, the first one else
+///
+/// `trim` - Remove the whitespace around values, on by default
+///
+/// `regex` - Keep the first capture group of the first match, or the whole match
+/// if the regex has no group. Values not matching are dropped.
+///
+/// `replace` - Replace every match of regex with this text instead
+#[derive(Debug, Clone)]
+pub struct Field {
+    name: String,
+    query: Query,
+    attr: Option<String>,
+    multiple: bool,
+    trim: bool,
+    regex: Option<Regex>,
+    replace: Option<String>,
+}
+
+impl Field {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Return the raw values of the elements matching the query in doc
+    fn raw_values(&self, doc: &Document) -> Vec<String> {
+        let nodes = match (&self.query, &self.attr) {
+            (&Query::Css(ref selector), _) => selector.select(doc),
+            (&Query::XPath(ref xpath), &Some(_)) => {
+                xpath.select(doc).unwrap_or_else(|_| Vec::new())
+            }
+            (&Query::XPath(ref xpath), &None) => {
+                return xpath.strings(doc).unwrap_or_else(|_| Vec::new())
+            }
+        };
+        match self.attr {
+            Some(ref attr) => {
+                nodes.iter().filter_map(|node| node.attr(attr).map(|v| v.to_string())).collect()
+            }
+            None => nodes.iter().map(|node| node.text()).collect(),
+        }
+    }
+
+    /// Return the value of the field in doc, null if no element gives one
+    pub fn extract(&self, doc: &Document) -> Value {
+        let mut values = Vec::new();
+        for value in self.raw_values(doc) {
+            if let Some(value) = self.process(value) {
+                values.push(Value::String(value));
+                if !self.multiple {
+                    break;
+                }
+            }
+        }
+        if self.multiple {
+            Value::Array(values)
+        } else {
+            values.into_iter().next().unwrap_or(Value::Null)
+        }
+    }
+
+    /// Trim value and apply the regex, return None if it does not match
+    fn process(&self, value: String) -> Option<String> {
+        let value = if self.trim {
+            value.trim().to_string()
+        } else {
+            value
+        };
+        let regex = match self.regex {
+            Some(ref regex) => regex,
+            None => return Some(value),
+        };
+        if let Some(ref replace) = self.replace {
+            return Some(regex.replace_all(&value, replace.as_str()).into_owned());
+        }
+        let captures = match regex.captures(&value) {
+            Some(captures) => captures,
+            None => return None,
+        };
+        captures.get(1).or_else(|| captures.get(0)).map(|m| {
+            if self.trim {
+                m.as_str().trim().to_string()
+            } else {
+                m.as_str().to_string()
+            }
+        })
+    }
+}
+
+/// Fields to extract from the pages whose url matches a pattern
+#[derive(Debug, Clone)]
+pub struct Rule {
+    name: String,
+    url: Regex,
+    fields: Vec<Field>,
+}
+
+impl Rule {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Check if the rule applies to url
+    pub fn matches(&self, url: &Url) -> bool {
+        self.url.is_match(url.as_str())
+    }
+
+    /// Return the record of the page at url, with its url and fields
+    pub fn extract(&self, url: &Url, doc: &Document) -> Value {
+        let mut record = Map::new();
+        record.insert("url".to_string(), Value::String(url.to_string()));
+        for field in &self.fields {
+            record.insert(field.name.clone(), field.extract(doc));
+        }
+        Value::Object(record)
+    }
+}
+
+/// Scraping rules, the first rule matching the url of a page applies.
+///
+/// Rules are read from JSON like:
+///
+/// ```text
+/// {"rules": [{
+///     "name": "product",
+///     "url": "^https://shop\\.example\\.com/p/",
+///     "fields": {
+///         "title": {"selector": "h1"},
+///         "brand": {"xpath": "//dt[. = 'Brand']/following-sibling::dd[1]"},
+///         "price": {"selector": ".price", "regex": "[0-9]+(?:\\.[0-9]+)?"},
+///         "sku": {"selector": "[itemprop=sku]", "attr": "content"},
+///         "images": {"selector": ".gallery img", "attr": "src", "multiple": true}
+///     }
+/// }]}
+/// ```
+///
+/// or the same in TOML, with a `[[rules]]` table per rule.
+#[derive(Debug, Clone)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parse rules from JSON
+    pub fn from_json(json: &str) -> Result<Rules> {
+        Rules::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Parse rules from TOML
+    pub fn from_toml(text: &str) -> Result<Rules> {
+        Rules::from_value(toml_to_json(text.parse::<toml::Value>()?))
+    }
+
+    fn from_value(value: Value) -> Result<Rules> {
+        let rules = match value.get("rules") {
+            Some(&Value::Array(ref rules)) => rules,
+            _ => return Err(invalid("expected a \"rules\" list")),
+        };
+        let mut parsed = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            let name = rule.get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| format!("rule {}", i + 1));
+            parsed.push(parse_rule(rule, name)?);
+        }
+        Ok(Rules { rules: parsed })
+    }
+
+    /// Read rules from a file, in TOML if its extension is `.toml` and in JSON else
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Rules> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Rules::from_toml(&text),
+            _ => Rules::from_json(&text),
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Return the rule applying to url
+    pub fn rule_for(&self, url: &Url) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.matches(url))
+    }
+
+    /// Return the record of the page at url, if a rule applies to it
+    pub fn extract(&self, url: &Url, doc: &Document) -> Option<Value> {
+        self.rule_for(url).map(|rule| rule.extract(url, doc))
+    }
+}
+
+/// Convert a TOML value to JSON, dates becoming strings
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(values) => Value::Array(values.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => {
+            Value::Object(table.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect())
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    ErrorKind::InvalidRules(reason.to_string()).into()
+}
+
+fn parse_rule(rule: &Value, name: String) -> Result<Rule> {
+    let url = match rule.get("url").and_then(|u| u.as_str()) {
+        Some(url) => Regex::new(url)?,
+        None => return Err(invalid(&format!("{}: expected a \"url\" pattern", name))),
+    };
+    let fields = match rule.get("fields") {
+        Some(&Value::Object(ref fields)) => fields,
+        _ => return Err(invalid(&format!("{}: expected \"fields\"", name))),
+    };
+    let mut parsed = Vec::new();
+    for (field_name, field) in fields {
+        if field_name == "url" {
+            return Err(invalid(&format!("{}: \"url\" is the url of the page", name)));
+        }
+        let context = format!("{}.{}", name, field_name);
+        parsed.push(parse_field(field, field_name.clone(), &context)?);
+    }
+    Ok(Rule {
+        name: name,
+        url: url,
+        fields: parsed,
+    })
+}
+
+fn parse_field(field: &Value, name: String, context: &str) -> Result<Field> {
+    let string = |key: &str| -> Result<Option<String>> {
+        match field.get(key) {
+            None | Some(&Value::Null) => Ok(None),
+            Some(&Value::String(ref s)) => Ok(Some(s.clone())),
+            Some(_) => Err(invalid(&format!("{}: \"{}\" must be a string", context, key))),
+        }
+    };
+    let boolean = |key: &str, default: bool| -> Result<bool> {
+        match field.get(key) {
+            None | Some(&Value::Null) => Ok(default),
+            Some(&Value::Bool(b)) => Ok(b),
+            Some(_) => Err(invalid(&format!("{}: \"{}\" must be a boolean", context, key))),
+        }
+    };
+    let query = match (string("selector")?, string("xpath")?) {
+        (Some(selector), None) => Query::Css(Selector::parse(&selector)?),
+        (None, Some(xpath)) => Query::XPath(XPath::parse(&xpath)?),
+        _ => {
+            return Err(invalid(&format!("{}: expected a \"selector\" or an \"xpath\"",
+                                        context)))
+        }
+    };
+    let regex = match string("regex")? {
+        Some(regex) => Some(Regex::new(&regex)?),
+        None => None,
+    };
+    let replace = string("replace")?;
+    if replace.is_some() && regex.is_none() {
+        return Err(invalid(&format!("{}: \"replace\" needs a \"regex\"", context)));
+    }
+    Ok(Field {
+        name: name,
+        query: query,
+        attr: string("attr")?,
+        multiple: boolean("multiple", false)?,
+        trim: boolean("trim", true)?,
+        regex: regex,
+        replace: replace,
+    })
+}
+
+/// Output stream of the records of pages a rule applies to, as JSON lines
+pub struct Scraper {
+    rules: Rules,
+    writer: JsonLines,
+}
+
+impl Scraper {
+    pub fn new(rules: Rules, writer: Box<Write + Send>) -> Scraper {
+        Scraper {
+            rules: rules,
+            writer: JsonLines::new(writer),
+        }
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Write the record of the page at url, return false if no rule applies to it
+    pub fn write_page(&self, url: &Url, doc: &Document) -> Result<bool> {
+        match self.rules.extract(url, doc) {
+            Some(record) => {
+                self.writer.write(&record)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use select::document::Document;
+    use super::Rules;
+
+    const RULES: &'static str = r#"{"rules": [
+        {
+            "name": "product",
+            "url": "^http://shop\\.example\\.com/p/",
+            "fields": {
+                "title": {"selector": "h1"},
+                "price": {"selector": ".price", "regex": "[0-9]+(?:\\.[0-9]+)?"},
+                "currency": {"selector": ".price", "regex": "([A-Z]{3})"},
+                "sku": {"selector": "meta[itemprop=sku]", "attr": "content"},
+                "images": {"selector": ".gallery img", "attr": "src", "multiple": true},
+                "tags": {"selector": ".tag", "multiple": true, "regex": "^#", "replace": ""},
+                "rating": {"selector": ".rating"},
+                "brand": {"xpath": "//dt[. = 'Brand']/following-sibling::dd[1]"},
+                "thumbnail": {"xpath": "//div[@class = 'gallery']/img[2]/@src"},
+                "photos": {"xpath": "count(//img[@src])"}
+            }
+        },
+        {"url": ".", "fields": {"title": {"selector": "title"}}}
+    ]}"#;
+
+    #[test]
+    fn extract_records() {
+        let rules = Rules::from_json(RULES).unwrap();
+        let html = "<title>Boots</title><h1>\n  Leather boots </h1>\
+                    <span class=\"price\">59.90 EUR</span>\
+                    <meta itemprop=\"sku\" content=\"B-42\">\
+                    <div class=\"gallery\"><img src=\"/1.png\"><img><img src=\"/2.png\"></div>\
+                    <a class=\"tag\">#leather</a><a class=\"tag\"> #winter</a>\
+                    <dl><dt>Color</dt><dd>Brown</dd><dt>Brand</dt><dd> Acme </dd></dl>";
+        let doc = Document::from(html);
+        let url = "http://shop.example.com/p/boots".into_url().unwrap();
+        assert_eq!(rules.extract(&url, &doc).unwrap(),
+                   json!({
+                       "url": "http://shop.example.com/p/boots",
+                       "title": "Leather boots",
+                       "price": "59.90",
+                       "currency": "EUR",
+                       "sku": "B-42",
+                       "images": ["/1.png", "/2.png"],
+                       "tags": ["leather", "winter"],
+                       "rating": null,
+                       "brand": "Acme",
+                       "thumbnail": null,
+                       "photos": "2"
+                   }));
+        let other = "http://shop.example.com/about".into_url().unwrap();
+        assert_eq!(rules.rule_for(&other).unwrap().name(), "rule 2");
+        assert_eq!(rules.extract(&other, &doc).unwrap()["title"], json!("Boots"));
+    }
+
+    #[test]
+    fn parse_toml() {
+        let rules = Rules::from_toml(r#"
+            [[rules]]
+            name = "product"
+            url = '^http://shop\.example\.com/p/'
+
+            [rules.fields]
+            title = { selector = "h1" }
+            images = { selector = "img", attr = "src", multiple = true }
+            brand = { xpath = "//dd[1]" }
+        "#)
+            .unwrap();
+        let doc = Document::from("<h1>Boots</h1><img src=\"/1.png\"><dl><dd>Acme</dd></dl>");
+        let url = "http://shop.example.com/p/boots".into_url().unwrap();
+        assert_eq!(rules.rule_for(&url).unwrap().name(), "product");
+        assert_eq!(rules.extract(&url, &doc).unwrap(),
+                   json!({
+                       "url": "http://shop.example.com/p/boots",
+                       "title": "Boots",
+                       "images": ["/1.png"],
+                       "brand": "Acme"
+                   }));
+        assert!(Rules::from_toml("rules = 1").is_err());
+        assert!(Rules::from_toml("[[rules]").is_err());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let invalid = [r#"{}"#,
+                       r#"{"rules": [{"fields": {}}]}"#,
+                       r#"{"rules": [{"url": "(", "fields": {}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"a": {"selector": "p >"}}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"a": {"selector": "p",
+                                                                   "multiple": "yes"}}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"a": {"selector": "p",
+                                                                   "replace": ""}}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"url": {"selector": "p"}}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"a": {"xpath": "//p["}}}]}"#,
+                       r#"{"rules": [{"url": ".", "fields": {"a": {"selector": "p",
+                                                                   "xpath": "//p"}}}]}"#];
+        for rules in &invalid {
+            assert!(Rules::from_json(rules).is_err(), "{}", rules);
+        }
+    }
+}
@@ -1,5 +1,87 @@
 use select::document::Document;
-use select::predicate::Attr;
+use select::predicate::{Attr, Name, Or, Text};
+use std::fmt;
+
+/// Kind of element a link comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LinkKind {
+    /// `<a>` or `<area>`
+    Anchor,
+    /// `<link>`
+    Link,
+    /// `<img>`
+    Image,
+    /// `<script>`
+    Script,
+    /// `<iframe>` or `<frame>`
+    Frame,
+}
+
+impl LinkKind {
+    /// Check if link is followed while crawling
+    pub fn is_followed(&self) -> bool {
+        match *self {
+            LinkKind::Anchor | LinkKind::Link => true,
+            _ => false,
+        }
+    }
+
+    /// Return the name of the kind
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LinkKind::Anchor => "anchor",
+            LinkKind::Link => "link",
+            LinkKind::Image => "image",
+            LinkKind::Script => "script",
+            LinkKind::Frame => "frame",
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A link found in a document.
+///
+/// `href` - Raw value of the href (or src) attribute
+///
+/// `text` - Anchor text (or alt text for images)
+///
+/// `kind` - Kind of element
+///
+/// `rel` - Values of the rel attribute
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    href: String,
+    text: String,
+    kind: LinkKind,
+    rel: Vec<String>,
+}
+
+impl Link {
+    /// Return the raw value of the href attribute
+    pub fn get_href(&self) -> &str {
+        &self.href
+    }
+
+    /// Return the anchor text
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Return the kind of element
+    pub fn get_kind(&self) -> LinkKind {
+        self.kind
+    }
+
+    /// Return the values of the rel attribute
+    pub fn get_rel(&self) -> &Vec<String> {
+        &self.rel
+    }
+}
 
 pub fn scrap_attr(doc: &Document, attr: &str) -> Vec<String> {
     let mut attrs = Vec::new();
@@ -13,3 +95,148 @@ pub fn scrap_attr(doc: &Document, attr: &str) -> Vec<String> {
     }
     attrs
 }
+
+/// Return all links of document, in document order
+pub fn scrap_links(doc: &Document) -> Vec<Link> {
+    let mut links = Vec::new();
+    for node in doc.find(Or(Attr("href", ()), Attr("src", ()))).iter() {
+        let (attr, kind) = match node.name() {
+            Some("a") | Some("area") => ("href", LinkKind::Anchor),
+            Some("link") => ("href", LinkKind::Link),
+            Some("img") => ("src", LinkKind::Image),
+            Some("script") => ("src", LinkKind::Script),
+            Some("iframe") | Some("frame") => ("src", LinkKind::Frame),
+            _ => continue,
+        };
+        let href = match node.attr(attr) {
+            Some(h) => h.trim().to_string(),
+            None => continue,
+        };
+        let text = match kind {
+            LinkKind::Anchor => node.text(),
+            LinkKind::Image => node.attr("alt").unwrap_or("").to_string(),
+            _ => node.attr("title").unwrap_or("").to_string(),
+        };
+        let rel = node.attr("rel")
+            .map(|r| r.split_whitespace().map(|r| r.to_lowercase()).collect())
+            .unwrap_or_else(Vec::new);
+        links.push(Link {
+            href: href,
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            kind: kind,
+            rel: rel,
+        });
+    }
+    links
+}
+
+/// Return the content of the meta tag with name (case insensitive)
+pub fn scrap_meta(doc: &Document, name: &str) -> Option<String> {
+    for node in doc.find(Name("meta")).iter() {
+        match node.attr("name") {
+            Some(n) if n.to_lowercase() == name => {}
+            _ => continue,
+        }
+        if let Some(content) = node.attr("content") {
+            return Some(content.to_string());
+        }
+    }
+    None
+}
+
+/// Return the href of the canonical link
+pub fn scrap_canonical(doc: &Document) -> Option<String> {
+    for node in doc.find(Name("link")).iter() {
+        let is_canonical = node.attr("rel")
+            .map(|r| r.split_whitespace().any(|r| r.to_lowercase() == "canonical"))
+            .unwrap_or(false);
+        if is_canonical {
+            if let Some(href) = node.attr("href") {
+                return Some(href.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Return the languages and hrefs of the alternate links with hreflang
+pub fn scrap_alternates(doc: &Document) -> Vec<(String, String)> {
+    let mut alternates = Vec::new();
+    for node in doc.find(Name("link")).iter() {
+        let is_alternate = node.attr("rel")
+            .map(|r| r.split_whitespace().any(|r| r.to_lowercase() == "alternate"))
+            .unwrap_or(false);
+        if !is_alternate {
+            continue;
+        }
+        if let (Some(lang), Some(href)) = (node.attr("hreflang"), node.attr("href")) {
+            alternates.push((lang.to_string(), href.to_string()));
+        }
+    }
+    alternates
+}
+
+/// Return the fragments a link can point to in document, ids and names of `<a>`
+pub fn scrap_anchors(doc: &Document) -> Vec<String> {
+    let mut anchors = Vec::new();
+    for node in doc.find(Attr("id", ())).iter() {
+        if let Some(id) = node.attr("id") {
+            anchors.push(id.to_string());
+        }
+    }
+    for node in doc.find(Name("a")).iter() {
+        if let Some(name) = node.attr("name") {
+            anchors.push(name.to_string());
+        }
+    }
+    anchors
+}
+
+/// Elements whose text is not shown
+const HIDDEN: &'static [&'static str] = &["script", "style", "noscript", "template"];
+
+/// Elements starting a new line of text
+const BLOCKS: &'static [&'static str] = &["address", "article", "aside", "blockquote", "body",
+                                          "dd", "div", "dl", "dt", "fieldset", "figcaption",
+                                          "figure", "footer", "form", "h1", "h2", "h3", "h4",
+                                          "h5", "h6", "header", "hr", "li", "main", "nav", "ol",
+                                          "p", "pre", "section", "table", "td", "th", "title",
+                                          "tr", "ul"];
+
+/// Return the visible text of document, one line per block element
+pub fn scrap_text(doc: &Document) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut last_block = None;
+    for node in doc.find(Text).iter() {
+        let text = node.as_text().unwrap_or("").split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.is_empty() {
+            continue;
+        }
+        let mut block = None;
+        let mut hidden = false;
+        let mut parent = node.parent();
+        while let Some(p) = parent {
+            match p.name() {
+                Some(name) if HIDDEN.contains(&name) => {
+                    hidden = true;
+                    break;
+                }
+                Some(name) if block.is_none() && BLOCKS.contains(&name) => block = Some(p.index()),
+                _ => {}
+            }
+            parent = p.parent();
+        }
+        if hidden {
+            continue;
+        }
+        match lines.last_mut() {
+            Some(line) if block == last_block => {
+                line.push(' ');
+                line.push_str(&text);
+            }
+            _ => lines.push(text),
+        }
+        last_block = block;
+    }
+    lines.join("\n")
+}
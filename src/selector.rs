@@ -0,0 +1,360 @@
+use error::*;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::Element;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// How a compound selector relates to the previous one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    /// `a b`
+    Descendant,
+    /// `a > b`
+    Child,
+}
+
+/// How an attribute selector compares the value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttrOp {
+    /// `[a]`
+    Exists,
+    /// `[a=v]`
+    Equals,
+    /// `[a~=v]`
+    Word,
+    /// `[a^=v]`
+    Prefix,
+    /// `[a$=v]`
+    Suffix,
+    /// `[a*=v]`
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AttrSelector {
+    name: String,
+    op: AttrOp,
+    value: String,
+}
+
+impl AttrSelector {
+    fn matches(&self, value: &str) -> bool {
+        match self.op {
+            AttrOp::Exists => true,
+            AttrOp::Equals => value == self.value,
+            AttrOp::Word => value.split_whitespace().any(|w| w == self.value),
+            AttrOp::Prefix => !self.value.is_empty() && value.starts_with(&self.value),
+            AttrOp::Suffix => !self.value.is_empty() && value.ends_with(&self.value),
+            AttrOp::Contains => !self.value.is_empty() && value.contains(&self.value),
+        }
+    }
+}
+
+/// Selectors of a single element, like `div#main.content[lang]`
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Compound {
+    universal: bool,
+    name: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attrs: Vec<AttrSelector>,
+}
+
+impl Compound {
+    fn is_empty(&self) -> bool {
+        !self.universal && self.name.is_none() && self.id.is_none() &&
+        self.classes.is_empty() && self.attrs.is_empty()
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        let name = match node.name() {
+            Some(name) => name,
+            None => return false,
+        };
+        if let Some(ref n) = self.name {
+            if *n != name.to_lowercase() {
+                return false;
+            }
+        }
+        if let Some(ref id) = self.id {
+            if node.attr("id") != Some(id.as_str()) {
+                return false;
+            }
+        }
+        if !self.classes.is_empty() {
+            let classes: Vec<&str> = node.attr("class").unwrap_or("").split_whitespace().collect();
+            if !self.classes.iter().all(|c| classes.contains(&c.as_str())) {
+                return false;
+            }
+        }
+        self.attrs.iter().all(|a| node.attr(&a.name).map(|v| a.matches(v)).unwrap_or(false))
+    }
+}
+
+/// A CSS selector.
+///
+/// Supports type (`p`), universal (`*`), id (`#id`), class (`.class`) and
+/// attribute (`[a]`, `[a=v]`, `[a~=v]`, `[a^=v]`, `[a$=v]`, `[a*=v]`)
+/// selectors, combined by descendant (`a b`) and child (`a > b`)
+/// combinators, in comma separated groups.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    source: String,
+    groups: Vec<Vec<(Combinator, Compound)>>,
+}
+
+impl Selector {
+    /// Parse a selector
+    pub fn parse(source: &str) -> Result<Selector> {
+        let mut parser = Parser { chars: source.chars().peekable() };
+        let mut groups = Vec::new();
+        loop {
+            groups.push(parser.group().map_err(|e| invalid(source, &e))?);
+            match parser.chars.next() {
+                Some(',') => continue,
+                None => break,
+                Some(c) => return Err(invalid(source, &format!("unexpected '{}'", c))),
+            }
+        }
+        Ok(Selector {
+            source: source.to_string(),
+            groups: groups,
+        })
+    }
+
+    /// Return the source of the selector
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Check if node matches the selector
+    pub fn matches(&self, node: &Node) -> bool {
+        self.groups.iter().any(|g| matches_from(g, g.len() - 1, node))
+    }
+
+    /// Return the elements of doc matching the selector, in document order
+    pub fn select<'a>(&self, doc: &'a Document) -> Vec<Node<'a>> {
+        doc.find(Element).iter().filter(|n| self.matches(n)).collect()
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Selector> {
+        Selector::parse(s)
+    }
+}
+
+fn invalid(source: &str, reason: &str) -> Error {
+    ErrorKind::InvalidSelector(format!("{}: {}", source, reason)).into()
+}
+
+/// Check if node matches the compound selectors of group up to i, from right to left
+fn matches_from(group: &[(Combinator, Compound)], i: usize, node: &Node) -> bool {
+    if !group[i].1.matches(node) {
+        return false;
+    }
+    if i == 0 {
+        return true;
+    }
+    let mut parent = node.parent();
+    match group[i].0 {
+        Combinator::Child => parent.map(|p| matches_from(group, i - 1, &p)).unwrap_or(false),
+        Combinator::Descendant => {
+            while let Some(p) = parent {
+                if matches_from(group, i - 1, &p) {
+                    return true;
+                }
+                parent = p.parent();
+            }
+            false
+        }
+    }
+}
+
+/// Result of parsing, failing with the reason
+type ParseResult<T> = ::std::result::Result<T, String>;
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) -> bool {
+        let mut skipped = false;
+        while self.chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.chars.next();
+            skipped = true;
+        }
+        skipped
+    }
+
+    fn ident(&mut self) -> ParseResult<String> {
+        let mut ident = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                ident.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        if ident.is_empty() {
+            Err(match self.chars.peek() {
+                Some(c) => format!("expected a name, found '{}'", c),
+                None => "expected a name".to_string(),
+            })
+        } else {
+            Ok(ident)
+        }
+    }
+
+    /// Parse compound selectors up to a comma or the end
+    fn group(&mut self) -> ParseResult<Vec<(Combinator, Compound)>> {
+        let mut group = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        self.skip_whitespace();
+        loop {
+            let compound = self.compound()?;
+            if compound.is_empty() {
+                return Err(match self.chars.peek() {
+                    Some(c) => format!("unexpected '{}'", c),
+                    None => "expected a selector".to_string(),
+                });
+            }
+            group.push((combinator, compound));
+            let spaced = self.skip_whitespace();
+            combinator = match self.chars.peek() {
+                None | Some(&',') => return Ok(group),
+                Some(&'>') => {
+                    self.chars.next();
+                    self.skip_whitespace();
+                    Combinator::Child
+                }
+                Some(_) if spaced => Combinator::Descendant,
+                Some(&c) => return Err(format!("unexpected '{}'", c)),
+            };
+        }
+    }
+
+    fn compound(&mut self) -> ParseResult<Compound> {
+        let mut compound = Compound::default();
+        match self.chars.peek() {
+            Some(&'*') => {
+                self.chars.next();
+                compound.universal = true;
+            }
+            Some(&c) if c.is_alphanumeric() || c == '-' || c == '_' => {
+                compound.name = Some(self.ident()?.to_lowercase());
+            }
+            _ => {}
+        }
+        loop {
+            match self.chars.peek() {
+                Some(&'#') => {
+                    self.chars.next();
+                    compound.id = Some(self.ident()?);
+                }
+                Some(&'.') => {
+                    self.chars.next();
+                    compound.classes.push(self.ident()?);
+                }
+                Some(&'[') => {
+                    self.chars.next();
+                    compound.attrs.push(self.attr()?);
+                }
+                _ => return Ok(compound),
+            }
+        }
+    }
+
+    fn attr(&mut self) -> ParseResult<AttrSelector> {
+        self.skip_whitespace();
+        let name = self.ident()?.to_lowercase();
+        self.skip_whitespace();
+        let op = match self.chars.next() {
+            Some(']') => {
+                return Ok(AttrSelector {
+                    name: name,
+                    op: AttrOp::Exists,
+                    value: String::new(),
+                })
+            }
+            Some('=') => AttrOp::Equals,
+            Some(c) if "~^$*".contains(c) => {
+                if self.chars.next() != Some('=') {
+                    return Err(format!("expected '=' after '{}'", c));
+                }
+                match c {
+                    '~' => AttrOp::Word,
+                    '^' => AttrOp::Prefix,
+                    '$' => AttrOp::Suffix,
+                    _ => AttrOp::Contains,
+                }
+            }
+            Some(c) => return Err(format!("unexpected '{}' in attribute selector", c)),
+            None => return Err("unclosed attribute selector".to_string()),
+        };
+        self.skip_whitespace();
+        let value = match self.chars.peek().cloned() {
+            Some(quote) if quote == '"' || quote == '\'' => {
+                self.chars.next();
+                let mut value = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some(c) if c == quote => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unclosed string".to_string()),
+                    }
+                }
+                value
+            }
+            _ => self.ident()?,
+        };
+        self.skip_whitespace();
+        if self.chars.next() != Some(']') {
+            return Err("unclosed attribute selector".to_string());
+        }
+        Ok(AttrSelector {
+            name: name,
+            op: op,
+            value: value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use select::document::Document;
+    use super::Selector;
+
+    fn select(selector: &str, html: &str) -> Vec<String> {
+        let doc = Document::from(html);
+        Selector::parse(selector).unwrap().select(&doc).iter().map(|n| n.text()).collect()
+    }
+
+    #[test]
+    fn select_elements() {
+        let html = "<div id=\"main\" class=\"content wide\"><p>a</p>\
+                    <section><p lang=\"en-GB\">b</p></section></div>\
+                    <p class=\"wide\">c</p><a href=\"https://x\">d</a>";
+        assert_eq!(select("p", html), vec!["a", "b", "c"]);
+        assert_eq!(select("#main p", html), vec!["a", "b"]);
+        assert_eq!(select("div.content > p", html), vec!["a"]);
+        assert_eq!(select(".wide", html).len(), 2);
+        assert_eq!(select("p.wide, a", html), vec!["c", "d"]);
+        assert_eq!(select("[lang^=en]", html), vec!["b"]);
+        assert_eq!(select("a[href*='://']", html), vec!["d"]);
+        assert_eq!(select("div > * > p", html), vec!["b"]);
+        assert_eq!(select("DIV#main.content.wide section", html), vec!["b"]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for selector in &["", "p,", "p >", "[lang", "a[href=\"x]", "p:first-child", ". p"] {
+            assert!(Selector::parse(selector).is_err(), "{}", selector);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use common::hash_bytes;
+use error::*;
+use hyper::Url;
+use indexer::Indexer;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Number of consecutive words hashed together
+const SHINGLE_WORDS: usize = 3;
+
+/// Return the SimHash fingerprint of text, over its lowercase word shingles.
+///
+/// Texts differing by a few words have fingerprints differing by a few bits. A text
+/// shorter than one shingle has none, all such texts would be near duplicates.
+pub fn simhash(text: &str) -> Option<u64> {
+    let words: Vec<String> = text.split_whitespace().map(|w| w.to_lowercase()).collect();
+    if words.len() < SHINGLE_WORDS {
+        return None;
+    }
+    let mut weights = [0i32; 64];
+    for shingle in words.windows(SHINGLE_WORDS) {
+        let hash = mix(hash_bytes(shingle.join(" ").as_bytes()));
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+    let mut fingerprint = 0;
+    for (bit, weight) in weights.iter().enumerate() {
+        if *weight > 0 {
+            fingerprint |= 1 << bit;
+        }
+    }
+    Some(fingerprint)
+}
+
+/// Spread the bits of an FNV hash, whose high bits depend little on the last bytes
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Return the number of bits differing between two fingerprints
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Fingerprints of seen pages, finding those within a Hamming distance of a fingerprint.
+///
+/// Fingerprints are split in `distance + 1` blocks, two fingerprints within
+/// distance share at least one block, so only fingerprints sharing a block are compared.
+#[derive(Debug, Clone)]
+pub struct SimHashIndex {
+    distance: u32,
+    blocks: Vec<(u32, u32)>,
+    tables: Vec<HashMap<u64, Vec<usize>>>,
+    pages: Vec<(Url, u64)>,
+}
+
+impl SimHashIndex {
+    pub fn new(distance: u32) -> SimHashIndex {
+        let count = if distance >= 63 { 64 } else { distance + 1 };
+        let blocks = (0..count).map(|i| (i * 64 / count, (i + 1) * 64 / count)).collect();
+        SimHashIndex {
+            distance: distance,
+            blocks: blocks,
+            tables: (0..count).map(|_| HashMap::new()).collect(),
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn distance(&self) -> u32 {
+        self.distance
+    }
+
+    fn block(fingerprint: u64, block: (u32, u32)) -> u64 {
+        let (start, end) = block;
+        let mask = if end - start == 64 { !0 } else { (1 << (end - start)) - 1 };
+        (fingerprint >> start) & mask
+    }
+
+    /// Return the first page added whose fingerprint is within distance of fingerprint
+    pub fn find(&self, fingerprint: u64) -> Option<&Url> {
+        let mut found: Option<usize> = None;
+        for (block, table) in self.blocks.iter().zip(&self.tables) {
+            let candidates = match table.get(&SimHashIndex::block(fingerprint, *block)) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+            for &i in candidates {
+                if hamming_distance(self.pages[i].1, fingerprint) <= self.distance &&
+                   found.map(|f| i < f).unwrap_or(true) {
+                    found = Some(i);
+                }
+            }
+        }
+        found.map(|i| &self.pages[i].0)
+    }
+
+    /// Add the fingerprint of the page of url
+    pub fn insert(&mut self, url: Url, fingerprint: u64) {
+        let i = self.pages.len();
+        for (block, table) in self.blocks.iter().zip(&mut self.tables) {
+            table.entry(SimHashIndex::block(fingerprint, *block)).or_insert_with(Vec::new).push(i);
+        }
+        self.pages.push((url, fingerprint));
+    }
+
+    /// Return the indexes of the pages within distance of the page at i, but i
+    fn neighbours(&self, i: usize) -> Vec<usize> {
+        let fingerprint = self.pages[i].1;
+        let mut neighbours = Vec::new();
+        for (block, table) in self.blocks.iter().zip(&self.tables) {
+            if let Some(candidates) = table.get(&SimHashIndex::block(fingerprint, *block)) {
+                neighbours.extend(candidates.iter().cloned().filter(|&j| {
+                    j != i && hamming_distance(self.pages[j].1, fingerprint) <= self.distance
+                }));
+            }
+        }
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+    }
+
+    /// Return the groups of pages linked by near-duplicate pairs, with more than one page
+    ///
+    /// Urls are sorted in groups, and groups by their first url.
+    pub fn clusters(&self) -> Vec<Vec<Url>> {
+        let mut cluster_of: Vec<Option<usize>> = vec![None; self.pages.len()];
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        for start in 0..self.pages.len() {
+            if cluster_of[start].is_some() {
+                continue;
+            }
+            let id = clusters.len();
+            let mut cluster = vec![start];
+            cluster_of[start] = Some(id);
+            let mut next = 0;
+            while next < cluster.len() {
+                for j in self.neighbours(cluster[next]) {
+                    if cluster_of[j].is_none() {
+                        cluster_of[j] = Some(id);
+                        cluster.push(j);
+                    }
+                }
+                next += 1;
+            }
+            clusters.push(cluster);
+        }
+        let mut clusters: Vec<Vec<Url>> = clusters.into_iter()
+            .filter(|c| c.len() > 1)
+            .map(|c| {
+                let mut urls: Vec<Url> = c.into_iter().map(|i| self.pages[i].0.clone()).collect();
+                urls.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+                urls
+            })
+            .collect();
+        clusters.sort_by(|a, b| a[0].as_str().cmp(b[0].as_str()));
+        clusters
+    }
+}
+
+/// Clusters of near-duplicate pages of each site of a crawl
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateReport {
+    sites: Vec<(Url, Vec<Vec<Url>>)>,
+}
+
+impl DuplicateReport {
+    /// Return the sites with their clusters of near-duplicate pages
+    pub fn get_sites(&self) -> &Vec<(Url, Vec<Vec<Url>>)> {
+        &self.sites
+    }
+
+    /// Cluster the pages of each site of indexer whose fingerprints are within distance
+    pub fn new(indexer: &Indexer, distance: u32) -> DuplicateReport {
+        let mut sites = Vec::new();
+        for site in indexer.get_sites() {
+            let mut pages: Vec<(&Url, u64)> = indexer.get_all_page_infos()
+                .into_iter()
+                .filter(|&(url, _)| site.is_same_host(url))
+                .filter_map(|(url, info)| info.get_simhash().map(|h| (url, h)))
+                .collect();
+            pages.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+            let mut index = SimHashIndex::new(distance);
+            for (url, fingerprint) in pages {
+                index.insert(url.clone(), fingerprint);
+            }
+            let clusters = index.clusters();
+            if !clusters.is_empty() {
+                sites.push((site.get_url().clone(), clusters));
+            }
+        }
+        sites.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        DuplicateReport { sites: sites }
+    }
+
+    /// Write the report as text, one line per page
+    pub fn write_text<W: Write>(&self, w: &mut W) -> Result<()> {
+        let mut count = 0;
+        for &(ref site, ref clusters) in &self.sites {
+            writeln!(w, "Site {}", site)?;
+            for (i, cluster) in clusters.iter().enumerate() {
+                writeln!(w, "    Cluster {}", i + 1)?;
+                for url in cluster {
+                    writeln!(w, "        {}", url)?;
+                }
+            }
+            count += clusters.len();
+        }
+        writeln!(w, "{} sites, {} clusters of near duplicates", self.sites.len(), count)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::Url;
+    use hyper::client::IntoUrl;
+    use indexer::Indexer;
+    use page::PageInfo;
+    use super::{DuplicateReport, SimHashIndex, hamming_distance, simhash};
+
+    const TEXT: &'static str = "The quick brown fox jumps over the lazy dog while the farmer \
+                                sleeps under the old oak tree near the river bank and the \
+                                children play football in the green field behind the barn";
+
+    fn url(path: &str) -> Url {
+        format!("http://example.com/{}", path).into_url().unwrap()
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let stamped = format!("{} Updated at 10:42", TEXT);
+        let other = "Prices of our shoes and boots, with free delivery for orders over fifty \
+                     euros and returns accepted within thirty days of the purchase date";
+        let fingerprint = simhash(TEXT).unwrap();
+        let stamped = simhash(&stamped).unwrap();
+        let other = simhash(other).unwrap();
+        assert_eq!(simhash(&TEXT.to_uppercase()), Some(fingerprint));
+        assert!(hamming_distance(fingerprint, stamped) <= 12);
+        assert!(hamming_distance(fingerprint, other) > 12);
+        assert_eq!(simhash(""), None);
+        assert_eq!(simhash("Two words"), None);
+
+        let mut index = SimHashIndex::new(12);
+        index.insert(url("a"), fingerprint);
+        assert_eq!(index.find(stamped), Some(&url("a")));
+        assert_eq!(index.find(other), None);
+    }
+
+    #[test]
+    fn report_clusters() {
+        let mut indexer = Indexer::new();
+        let pages = vec![("a", TEXT.to_string()),
+                         ("b", format!("{} Updated at 10:42", TEXT)),
+                         ("c", "Nothing in common with the other pages of this site".to_string())];
+        for (path, text) in pages {
+            indexer.add_url(url(path)).unwrap();
+            let mut info = PageInfo::new();
+            info.set_simhash(simhash(&text));
+            indexer.set_page_info(url(path), info);
+        }
+        let report = DuplicateReport::new(&indexer, 12);
+        assert_eq!(report.sites, vec![(url(""), vec![vec![url("a"), url("b")]])]);
+    }
+}
@@ -0,0 +1,480 @@
+use common::{escape_xml, http_date_to_w3c};
+use error::*;
+use flate2::read::GzDecoder;
+use hyper::Url;
+use hyper::client::IntoUrl;
+use indexer::Indexer;
+use site::Site;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use xml::reader::{EventReader, XmlEvent};
+
+/// Maximum number of nested sitemap index to follow
+const MAX_DEPTH: usize = 4;
+
+/// Maximum number of urls in a sitemap file
+pub const MAX_URLS: usize = 50000;
+
+const XMLNS: &'static str = "http://www.sitemaps.org/schemas/sitemap/0.9";
+
+/// An url listed in a sitemap.
+///
+/// `loc` - Location of the page (or of the sitemap for an index)
+///
+/// `lastmod` - Last modification date, as written in the sitemap
+///
+/// `priority` - Priority of the url, between 0.0 and 1.0
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    loc: Url,
+    lastmod: Option<String>,
+    priority: Option<f32>,
+}
+
+impl SitemapEntry {
+    /// Create a new entry
+    pub fn new<U: IntoUrl>(loc: U) -> Result<SitemapEntry> {
+        Ok(SitemapEntry {
+            loc: loc.into_url()?,
+            lastmod: None,
+            priority: None,
+        })
+    }
+
+    /// Return the location
+    pub fn get_loc(&self) -> &Url {
+        &self.loc
+    }
+
+    /// Return the last modification date
+    pub fn get_lastmod(&self) -> Option<&str> {
+        self.lastmod.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the priority
+    pub fn get_priority(&self) -> Option<f32> {
+        self.priority
+    }
+}
+
+/// A parsed sitemap file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sitemap {
+    /// A `<sitemapindex>` listing other sitemaps
+    Index(Vec<SitemapEntry>),
+    /// A `<urlset>` listing pages
+    UrlSet(Vec<SitemapEntry>),
+}
+
+/// Parse a sitemap, gzipped or not
+pub fn parse_sitemap(body: &[u8]) -> Result<Sitemap> {
+    if body.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = GzDecoder::new(body)?;
+        let mut buf = Vec::new();
+        decoder.read_to_end(&mut buf)?;
+        return parse_sitemap_xml(buf.as_slice());
+    }
+    parse_sitemap_xml(body)
+}
+
+fn parse_sitemap_xml(body: &[u8]) -> Result<Sitemap> {
+    let mut is_index = None;
+    let mut entries = Vec::new();
+    let mut entry: Option<(Option<String>, Option<String>, Option<String>)> = None;
+    let mut text = String::new();
+
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                match name.local_name.as_str() {
+                    "urlset" if is_index.is_none() => is_index = Some(false),
+                    "sitemapindex" if is_index.is_none() => is_index = Some(true),
+                    "url" | "sitemap" => entry = Some((None, None, None)),
+                    _ => {}
+                }
+                text.clear();
+            }
+            XmlEvent::Characters(s) |
+            XmlEvent::CData(s) => text.push_str(&s),
+            XmlEvent::EndElement { name } => {
+                let value = text.trim().to_string();
+                text.clear();
+                match (name.local_name.as_str(), entry.as_mut()) {
+                    ("loc", Some(e)) => e.0 = Some(value),
+                    ("lastmod", Some(e)) => e.1 = Some(value),
+                    ("priority", Some(e)) => e.2 = Some(value),
+                    ("url", _) | ("sitemap", _) => {
+                        if let Some((Some(loc), lastmod, priority)) = entry.take() {
+                            if let Ok(mut e) = SitemapEntry::new(loc.as_str()) {
+                                e.lastmod = lastmod;
+                                e.priority = priority.and_then(|p| p.parse().ok());
+                                entries.push(e);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match is_index {
+        Some(true) => Ok(Sitemap::Index(entries)),
+        Some(false) => Ok(Sitemap::UrlSet(entries)),
+        None => bail!(ErrorKind::InvalidSitemap("no urlset or sitemapindex".to_string())),
+    }
+}
+
+/// Return all sitemaps declared with `Sitemap:` lines in a robots.txt
+pub fn parse_robots_sitemaps(robots: &str) -> Vec<Url> {
+    let mut sitemaps = Vec::new();
+    for line in robots.lines() {
+        let line = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        if key.to_lowercase() != "sitemap" {
+            continue;
+        }
+        if let Some(Ok(url)) = parts.next().map(|v| v.trim().into_url()) {
+            sitemaps.push(url);
+        }
+    }
+    sitemaps
+}
+
+/// Return robots.txt url and default sitemap url for site
+pub fn default_locations(site: &Url) -> (Url, Url) {
+    let mut robots = site.clone();
+    robots.set_path("/robots.txt");
+    robots.set_query(None);
+    robots.set_fragment(None);
+    let mut sitemap = robots.clone();
+    sitemap.set_path("/sitemap.xml");
+    (robots, sitemap)
+}
+
+/// Discover all sitemaps of site and return the urls they provide.
+///
+/// Sitemaps are looked up in robots.txt and at `/sitemap.xml`, sitemap
+/// indexes are followed. `fetch` is used to download each file.
+pub fn discover<F>(site: &Url, mut fetch: F) -> Vec<SitemapEntry>
+    where F: FnMut(&Url) -> Result<Vec<u8>>
+{
+    let (robots_url, default_sitemap) = default_locations(site);
+    let mut sitemaps = match fetch(&robots_url) {
+        Ok(body) => parse_robots_sitemaps(&String::from_utf8_lossy(&body)),
+        Err(_) => Vec::new(),
+    };
+    if !sitemaps.contains(&default_sitemap) {
+        sitemaps.push(default_sitemap);
+    }
+
+    let mut visited = HashSet::new();
+    let mut entries = Vec::new();
+    let mut pending: Vec<(Url, usize)> = sitemaps.into_iter().map(|u| (u, 0)).collect();
+    pending.reverse();
+    while let Some((url, depth)) = pending.pop() {
+        if depth > MAX_DEPTH || !visited.insert(url.clone()) {
+            continue;
+        }
+        let body = match fetch(&url) {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+        match parse_sitemap(&body) {
+            Ok(Sitemap::Index(subs)) => {
+                for sub in subs.into_iter().rev() {
+                    pending.push((sub.loc, depth + 1));
+                }
+            }
+            Ok(Sitemap::UrlSet(urls)) => entries.extend(urls),
+            Err(_) => continue,
+        }
+    }
+    entries
+}
+
+/// Sort entries by priority then by last modification date, highest first
+pub fn prioritize(entries: &mut Vec<SitemapEntry>) {
+    entries.sort_by(|a, b| {
+        let pa = a.priority.unwrap_or(0.5);
+        let pb = b.priority.unwrap_or(0.5);
+        match pb.partial_cmp(&pa).unwrap_or(Ordering::Equal) {
+            Ordering::Equal => b.lastmod.cmp(&a.lastmod),
+            o => o,
+        }
+    });
+}
+
+/// Return urls of site that belong to its sitemap with their last modification date
+///
+/// Only pages fetched with a 200 status, that are not noindex and that are
+/// their own canonical are returned.
+pub fn sitemap_urls<'a>(indexer: &'a Indexer, site: &'a Site) -> Vec<(&'a Url, Option<String>)> {
+    let mut urls = Vec::new();
+    let all = Some(site.get_url()).into_iter().chain(site.get_subs_url());
+    for url in all {
+        let info = match indexer.get_page_info(url) {
+            Some(i) => i,
+            None => continue,
+        };
+        if info.get_status() != Some(200) || info.is_noindex() || !info.is_canonical(url) {
+            continue;
+        }
+        urls.push((url, info.get_last_modified().and_then(http_date_to_w3c)));
+    }
+    urls
+}
+
+/// Write a `<urlset>` sitemap
+pub fn write_urlset<W: Write>(w: &mut W, urls: &[(&Url, Option<String>)]) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<urlset xmlns="{}">"#, XMLNS)?;
+    for &(url, ref lastmod) in urls {
+        write!(w, "  <url><loc>{}</loc>", escape_xml(url.as_str()))?;
+        if let Some(ref lastmod) = *lastmod {
+            write!(w, "<lastmod>{}</lastmod>", lastmod)?;
+        }
+        writeln!(w, "</url>")?;
+    }
+    writeln!(w, "</urlset>")?;
+    Ok(())
+}
+
+/// Write a `<sitemapindex>` sitemap
+pub fn write_index<W: Write>(w: &mut W, sitemaps: &[Url]) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(w, r#"<sitemapindex xmlns="{}">"#, XMLNS)?;
+    for url in sitemaps {
+        writeln!(w, "  <sitemap><loc>{}</loc></sitemap>", escape_xml(url.as_str()))?;
+    }
+    writeln!(w, "</sitemapindex>")?;
+    Ok(())
+}
+
+/// Return the name of the directory of the sitemap of the origin of url, like
+/// `https_example.com` or `http_example.com_8080`
+fn origin_dir_name(url: &Url) -> String {
+    let host = url.host_str().unwrap_or("unknown").replace(':', "_");
+    match url.port() {
+        Some(port) => format!("{}_{}_{}", url.scheme(), host, port),
+        None => format!("{}_{}", url.scheme(), host),
+    }
+}
+
+/// Write sitemaps of all indexed sites in dir, one directory per origin.
+///
+/// A sitemap only lists urls of its own scheme, host and port, so urls of a
+/// site are grouped by origin, each one getting a `sitemap.xml`. When an origin
+/// has more than `MAX_URLS` urls, they are split in `sitemap-N.xml` files and
+/// `sitemap.xml` is an index of them. Return the paths of all written files.
+pub fn export<P: AsRef<Path>>(indexer: &Indexer, dir: P) -> Result<Vec<PathBuf>> {
+    let mut origins: BTreeMap<String, (Url, Vec<(&Url, Option<String>)>)> = BTreeMap::new();
+    for site in indexer.get_sites() {
+        for (url, lastmod) in sitemap_urls(indexer, site) {
+            let origin = origins.entry(origin_dir_name(url)).or_insert_with(|| {
+                let mut root = url.clone();
+                root.set_path("/");
+                root.set_query(None);
+                root.set_fragment(None);
+                (root, Vec::new())
+            });
+            origin.1.push((url, lastmod));
+        }
+    }
+    let mut paths = Vec::new();
+    for (name, (root, urls)) in origins {
+        let site_dir = dir.as_ref().join(name);
+        fs::create_dir_all(&site_dir)?;
+
+        let index_path = site_dir.join("sitemap.xml");
+        if urls.len() <= MAX_URLS {
+            write_urlset(&mut BufWriter::new(File::create(&index_path)?), &urls)?;
+            paths.push(index_path);
+            continue;
+        }
+        let mut parts = Vec::new();
+        for (i, chunk) in urls.chunks(MAX_URLS).enumerate() {
+            let name = format!("sitemap-{}.xml", i + 1);
+            let path = site_dir.join(&name);
+            write_urlset(&mut BufWriter::new(File::create(&path)?), chunk)?;
+            parts.push(root.join(&name)?);
+            paths.push(path);
+        }
+        write_index(&mut BufWriter::new(File::create(&index_path)?), &parts)?;
+        paths.push(index_path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use error::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use hyper::Url;
+    use hyper::client::IntoUrl;
+    use indexer::Indexer;
+    use page::PageInfo;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use super::{Sitemap, discover, export, parse_robots_sitemaps, parse_sitemap, prioritize,
+                sitemap_urls, write_urlset};
+
+    const URLSET: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>http://example.com/</loc><priority>0.5</priority></url>
+  <url><loc>http://example.com/new</loc><lastmod>2017-02-01</lastmod></url>
+  <url><loc>http://example.com/top</loc><priority>1.0</priority></url>
+</urlset>"#;
+
+    const INDEX: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>http://example.com/pages.xml</loc></sitemap>
+</sitemapindex>"#;
+
+    #[test]
+    fn parse_urlset() {
+        match parse_sitemap(URLSET.as_bytes()).unwrap() {
+            Sitemap::UrlSet(entries) => {
+                assert_eq!(entries.len(), 3);
+                assert_eq!(entries[1].get_loc().as_str(), "http://example.com/new");
+                assert_eq!(entries[1].get_lastmod(), Some("2017-02-01"));
+                assert_eq!(entries[2].get_priority(), Some(1.0));
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn parse_index() {
+        match parse_sitemap(INDEX.as_bytes()).unwrap() {
+            Sitemap::Index(entries) => {
+                assert_eq!(entries[0].get_loc().as_str(), "http://example.com/pages.xml")
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn parse_gzipped() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+        encoder.write_all(URLSET.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+        match parse_sitemap(&body).unwrap() {
+            Sitemap::UrlSet(entries) => assert_eq!(entries.len(), 3),
+            s => panic!("unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_sitemap(b"<html></html>").is_err());
+    }
+
+    #[test]
+    fn robots_sitemaps() {
+        let robots = "User-agent: *\nDisallow: /private\nSitemap: http://example.com/a.xml\n\
+                      sitemap:http://example.com/b.xml.gz # gzipped\n";
+        let urls = parse_robots_sitemaps(robots);
+        assert_eq!(urls.len(), 2);
+        assert_eq!(urls[1].as_str(), "http://example.com/b.xml.gz");
+    }
+
+    #[test]
+    fn discover_index() {
+        let site = "http://example.com/".into_url().unwrap();
+        let entries = discover(&site, |url: &Url| -> Result<Vec<u8>> {
+            match url.path() {
+                "/robots.txt" => Ok(b"Sitemap: http://example.com/index.xml".to_vec()),
+                "/index.xml" => Ok(INDEX.as_bytes().to_vec()),
+                "/pages.xml" => Ok(URLSET.as_bytes().to_vec()),
+                _ => bail!(ErrorKind::BadStatus(404)),
+            }
+        });
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    fn prioritize_entries() {
+        let mut entries = match parse_sitemap(URLSET.as_bytes()).unwrap() {
+            Sitemap::UrlSet(e) => e,
+            _ => unreachable!(),
+        };
+        prioritize(&mut entries);
+        let locs: Vec<&str> = entries.iter().map(|e| e.get_loc().path()).collect();
+        assert_eq!(locs, vec!["/top", "/new", "/"]);
+    }
+
+    #[test]
+    fn urls_of_crawled_site() {
+        let mut indexer = Indexer::new();
+        let pages = vec![("http://example.com/ok", 200, false, None),
+                         ("http://example.com/missing", 404, false, None),
+                         ("http://example.com/hidden", 200, true, None),
+                         ("http://example.com/copy", 200, false, Some("http://example.com/ok"))];
+        for (url, status, noindex, canonical) in pages {
+            let url = url.into_url().unwrap();
+            let mut info = PageInfo::new();
+            info.set_status(Some(status));
+            info.set_noindex(noindex);
+            info.set_canonical(canonical.map(|c: &str| c.into_url().unwrap()));
+            info.set_last_modified(Some("Wed, 01 Feb 2017 07:28:00 GMT".to_string()));
+            indexer.add_url(url.clone()).unwrap();
+            indexer.set_page_info(url, info);
+        }
+        let site = &indexer.get_sites()[0];
+        let urls = sitemap_urls(&indexer, site);
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].0.as_str(), "http://example.com/ok");
+        assert_eq!(urls[0].1, Some("2017-02-01".to_string()));
+
+        let mut buf = Vec::new();
+        write_urlset(&mut buf, &urls).unwrap();
+        match parse_sitemap(&buf).unwrap() {
+            Sitemap::UrlSet(entries) => {
+                assert_eq!(entries[0].get_loc().as_str(), "http://example.com/ok");
+                assert_eq!(entries[0].get_lastmod(), Some("2017-02-01"));
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+    }
+
+    #[test]
+    fn export_by_origin() {
+        let mut indexer = Indexer::new();
+        for url in &["http://example.com/a", "https://example.com/b", "http://example.com:8080/c"] {
+            let url = url.into_url().unwrap();
+            let mut info = PageInfo::new();
+            info.set_status(Some(200));
+            indexer.add_url(url.clone()).unwrap();
+            indexer.set_page_info(url, info);
+        }
+        let dir = env::temp_dir().join("unibot-sitemap-export");
+        let _ = fs::remove_dir_all(&dir);
+        let mut paths = export(&indexer, &dir).unwrap();
+        paths.sort();
+        assert_eq!(paths,
+                   vec![dir.join("http_example.com/sitemap.xml"),
+                        dir.join("http_example.com_8080/sitemap.xml"),
+                        dir.join("https_example.com/sitemap.xml")]);
+        let mut body = Vec::new();
+        File::open(&paths[1]).unwrap().read_to_end(&mut body).unwrap();
+        match parse_sitemap(&body).unwrap() {
+            Sitemap::UrlSet(entries) => {
+                assert_eq!(entries.len(), 1);
+                assert_eq!(entries[0].get_loc().as_str(), "http://example.com:8080/c");
+            }
+            s => panic!("unexpected {:?}", s),
+        }
+        let _ = fs::remove_dir_all(dir);
+    }
+}
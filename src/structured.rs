@@ -0,0 +1,418 @@
+use common::{JsonLines, href_to_url};
+use error::*;
+use hyper::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::{Attr, Name};
+use serde_json::{self, Map, Value};
+use std::io::Write;
+
+/// Syntax structured data was written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `<script type="application/ld+json">`
+    JsonLd,
+    /// `itemscope`, `itemtype` and `itemprop` attributes
+    Microdata,
+    /// `typeof` and `property` attributes
+    Rdfa,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::JsonLd => "json-ld",
+            Format::Microdata => "microdata",
+            Format::Rdfa => "rdfa",
+        }
+    }
+}
+
+/// An item described by a page, like a schema.org Product.
+///
+/// `data` - JSON-LD like object, with `@type` and the properties of the item
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    format: Format,
+    data: Value,
+}
+
+impl Item {
+    /// Return the syntax the item was described with
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+
+    /// Return the item as a JSON-LD like object
+    pub fn get_data(&self) -> &Value {
+        &self.data
+    }
+
+    /// Return the types of the item, without their vocabulary, like `Product`
+    pub fn types(&self) -> Vec<&str> {
+        let types = match self.data.get("@type") {
+            Some(&Value::String(ref t)) => vec![t.as_str()],
+            Some(&Value::Array(ref ts)) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        types.into_iter().map(short_type).collect()
+    }
+
+    /// Check if item has one of types, any item if types is empty (case insensitive)
+    pub fn has_type(&self, types: &[String]) -> bool {
+        types.is_empty() ||
+        self.types().iter().any(|t| types.iter().any(|w| w.to_lowercase() == t.to_lowercase()))
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "format": self.format.name(),
+            "data": self.data
+        })
+    }
+}
+
+/// Return a type without its vocabulary, `Product` for `http://schema.org/Product`
+fn short_type(t: &str) -> &str {
+    match t.rfind(|c: char| c == '/' || c == ':' || c == '#') {
+        Some(i) => &t[i + 1..],
+        None => t,
+    }
+}
+
+/// Return the items described by document of url in JSON-LD, Microdata and RDFa
+pub fn extract(url: &Url, doc: &Document) -> Vec<Item> {
+    let mut items = extract_json_ld(doc);
+    items.extend(extract_microdata(url, doc));
+    items.extend(extract_rdfa(url, doc));
+    items
+}
+
+/// Return the items of the JSON-LD scripts of document, skipping invalid ones
+///
+/// Arrays and `@graph` lists are split in one item per object.
+pub fn extract_json_ld(doc: &Document) -> Vec<Item> {
+    let mut items = Vec::new();
+    for node in doc.find(Name("script")).iter() {
+        let is_json_ld = node.attr("type")
+            .map(|t| t.trim().to_lowercase() == "application/ld+json")
+            .unwrap_or(false);
+        if !is_json_ld {
+            continue;
+        }
+        match serde_json::from_str::<Value>(&node.text()) {
+            Ok(value) => add_json_ld(&mut items, value, None),
+            Err(_) => continue,
+        }
+    }
+    items
+}
+
+fn add_json_ld(items: &mut Vec<Item>, value: Value, context: Option<&Value>) {
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                add_json_ld(items, value, context);
+            }
+        }
+        Value::Object(mut object) => {
+            if let Some(graph) = object.remove("@graph") {
+                let context = object.get("@context").or(context).cloned();
+                add_json_ld(items, graph, context.as_ref());
+                return;
+            }
+            if let Some(context) = context {
+                if !object.contains_key("@context") {
+                    object.insert("@context".to_string(), context.clone());
+                }
+            }
+            items.push(Item {
+                format: Format::JsonLd,
+                data: Value::Object(object),
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Return the top level Microdata items of document of url
+pub fn extract_microdata(url: &Url, doc: &Document) -> Vec<Item> {
+    doc.find(Attr("itemscope", ()))
+        .iter()
+        .filter(|node| node.attr("itemprop").is_none())
+        .map(|node| {
+            Item {
+                format: Format::Microdata,
+                data: microdata_item(url, &node),
+            }
+        })
+        .collect()
+}
+
+fn microdata_item(url: &Url, item: &Node) -> Value {
+    let mut object = Map::new();
+    if let Some(types) = item.attr("itemtype") {
+        let types: Vec<&str> = types.split_whitespace().collect();
+        if let Some(context) = types.first().and_then(|t| vocabulary(t)) {
+            object.insert("@context".to_string(), Value::String(context.to_string()));
+        }
+        object.insert("@type".to_string(), one_or_many(types.iter().map(|t| short_type(t))));
+    }
+    if let Some(id) = item.attr("itemid") {
+        object.insert("@id".to_string(), Value::String(id.to_string()));
+    }
+    for prop in item.find(Attr("itemprop", ())).iter() {
+        if !is_in_scope(&prop, item, "itemscope") {
+            continue;
+        }
+        let value = if prop.attr("itemscope").is_some() {
+            microdata_item(url, &prop)
+        } else {
+            Value::String(property_value(url, &prop, &["content"]))
+        };
+        for name in prop.attr("itemprop").unwrap_or("").split_whitespace() {
+            add_property(&mut object, name, value.clone());
+        }
+    }
+    Value::Object(object)
+}
+
+/// Return the top level RDFa resources of document of url with a type
+pub fn extract_rdfa(url: &Url, doc: &Document) -> Vec<Item> {
+    doc.find(Attr("typeof", ()))
+        .iter()
+        .filter(|node| node.attr("property").is_none())
+        .map(|node| {
+            Item {
+                format: Format::Rdfa,
+                data: rdfa_item(url, &node),
+            }
+        })
+        .collect()
+}
+
+fn rdfa_item(url: &Url, item: &Node) -> Value {
+    let mut object = Map::new();
+    if let Some(vocab) = item.attr("vocab") {
+        object.insert("@context".to_string(), Value::String(vocab.to_string()));
+    }
+    let types: Vec<&str> = item.attr("typeof").unwrap_or("").split_whitespace().collect();
+    object.insert("@type".to_string(), one_or_many(types.iter().map(|t| short_type(t))));
+    if let Some(id) = item.attr("resource").or_else(|| item.attr("about")) {
+        object.insert("@id".to_string(), Value::String(id.to_string()));
+    }
+    for prop in item.find(Attr("property", ())).iter() {
+        if !is_in_scope(&prop, item, "typeof") {
+            continue;
+        }
+        let value = if prop.attr("typeof").is_some() {
+            rdfa_item(url, &prop)
+        } else {
+            Value::String(property_value(url, &prop, &["content", "resource"]))
+        };
+        for name in prop.attr("property").unwrap_or("").split_whitespace() {
+            add_property(&mut object, short_type(name), value.clone());
+        }
+    }
+    Value::Object(object)
+}
+
+/// Check if the closest ancestor of prop with a scope attribute is item
+fn is_in_scope(prop: &Node, item: &Node, scope: &str) -> bool {
+    let mut parent = prop.parent();
+    while let Some(p) = parent {
+        if p.attr(scope).is_some() {
+            return p.index() == item.index();
+        }
+        parent = p.parent();
+    }
+    false
+}
+
+/// Return the value of a property element, from the first of attrs it has or from its tag
+///
+/// Links of `href`, `src` and `data` attributes are resolved against url of the page.
+fn property_value(url: &Url, node: &Node, attrs: &[&str]) -> String {
+    for attr in attrs {
+        if let Some(value) = node.attr(attr) {
+            return value.trim().to_string();
+        }
+    }
+    let attr = match node.name() {
+        Some("a") | Some("area") | Some("link") => Some("href"),
+        Some("audio") | Some("embed") | Some("iframe") | Some("img") | Some("source") |
+        Some("track") | Some("video") => Some("src"),
+        Some("object") => Some("data"),
+        Some("data") | Some("meter") => Some("value"),
+        Some("time") => Some("datetime"),
+        _ => None,
+    };
+    match attr.and_then(|a| node.attr(a).map(|v| (a, v.trim()))) {
+        Some(("value", value)) | Some(("datetime", value)) => value.to_string(),
+        Some((_, href)) => {
+            href_to_url(url, href).map(|u| u.to_string()).unwrap_or_else(|| href.to_string())
+        }
+        None => node.text().split_whitespace().collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Return the vocabulary of a type url, `https://schema.org` for `https://schema.org/Product`
+fn vocabulary(t: &str) -> Option<&str> {
+    match t.rfind('/') {
+        Some(i) if i > 0 => Some(&t[..i]),
+        _ => None,
+    }
+}
+
+fn one_or_many<'a, I: Iterator<Item = &'a str>>(values: I) -> Value {
+    let mut values: Vec<Value> = values.map(|v| Value::String(v.to_string())).collect();
+    if values.len() == 1 {
+        values.remove(0)
+    } else {
+        Value::Array(values)
+    }
+}
+
+/// Add a value to a property, making it an array if it already has one
+fn add_property(object: &mut Map<String, Value>, name: &str, value: Value) {
+    let previous = match object.remove(name) {
+        None => value,
+        Some(Value::Array(mut values)) => {
+            values.push(value);
+            Value::Array(values)
+        }
+        Some(previous) => Value::Array(vec![previous, value]),
+    };
+    object.insert(name.to_string(), previous);
+}
+
+/// Output stream of the items found in each page, as JSON lines.
+///
+/// `types` - Types of the items to keep, all if empty
+pub struct ItemSink {
+    writer: JsonLines,
+    types: Vec<String>,
+}
+
+impl ItemSink {
+    pub fn new(writer: Box<Write + Send>, types: Vec<String>) -> ItemSink {
+        ItemSink {
+            writer: JsonLines::new(writer),
+            types: types,
+        }
+    }
+
+    pub fn types(&self) -> &[String] {
+        &self.types
+    }
+
+    /// Write a line with the url of a page and its items having the wanted types
+    ///
+    /// Nothing is written if the page has no such item. Return the number of items written.
+    pub fn write_page(&self, url: &Url, items: &[Item]) -> Result<usize> {
+        let items: Vec<Value> = items.iter()
+            .filter(|item| item.has_type(&self.types))
+            .map(|item| item.to_json())
+            .collect();
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let count = items.len();
+        let line = json!({
+            "url": url.to_string(),
+            "items": items
+        });
+        self.writer.write(&line)?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use select::document::Document;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use super::{Format, ItemSink, extract};
+
+    const PAGE: &'static str = "<script type=\"application/ld+json\">\
+        {\"@context\": \"https://schema.org\", \"@graph\": [\
+        {\"@type\": \"Organization\", \"name\": \"Shop\"},\
+        {\"@type\": \"BreadcrumbList\"}]}</script>\
+        <script type=\"application/ld+json\">{invalid</script>\
+        <div itemscope itemtype=\"http://schema.org/Product\">\
+          <h1 itemprop=\"name\">Boots</h1>\
+          <img itemprop=\"image\" src=\"/boots.png\">\
+          <div itemprop=\"offers\" itemscope itemtype=\"http://schema.org/Offer\">\
+            <meta itemprop=\"priceCurrency\" content=\"EUR\">\
+            <span itemprop=\"price\">59.90</span>\
+          </div>\
+          <span itemprop=\"color\">black</span> <span itemprop=\"color\">brown</span>\
+        </div>\
+        <div vocab=\"https://schema.org/\" typeof=\"Product\">\
+          <span property=\"name\">Sandals</span>\
+          <div property=\"offers\" typeof=\"Offer\">\
+            <span property=\"price\" content=\"19\">19 EUR</span>\
+          </div>\
+        </div>";
+
+    #[test]
+    fn extract_items() {
+        let url = "http://example.com/shop/boots".into_url().unwrap();
+        let items = extract(&url, &Document::from(PAGE));
+        let formats: Vec<Format> = items.iter().map(|i| i.format).collect();
+        assert_eq!(formats,
+                   vec![Format::JsonLd, Format::JsonLd, Format::Microdata, Format::Rdfa]);
+        assert_eq!(items[0].data,
+                   json!({"@context": "https://schema.org", "@type": "Organization",
+                          "name": "Shop"}));
+        assert_eq!(items[2].data,
+                   json!({
+                       "@context": "http://schema.org",
+                       "@type": "Product",
+                       "name": "Boots",
+                       "image": "http://example.com/boots.png",
+                       "offers": {
+                           "@context": "http://schema.org",
+                           "@type": "Offer",
+                           "priceCurrency": "EUR",
+                           "price": "59.90"
+                       },
+                       "color": ["black", "brown"]
+                   }));
+        assert_eq!(items[3].data,
+                   json!({
+                       "@context": "https://schema.org/",
+                       "@type": "Product",
+                       "name": "Sandals",
+                       "offers": {"@type": "Offer", "price": "19"}
+                   }));
+        assert_eq!(items[3].types(), vec!["Product"]);
+    }
+
+    /// Writer appending to a shared buffer
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_filtered_items() {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let sink = ItemSink::new(Box::new(Shared(out.clone())), vec!["product".to_string()]);
+        let url = "http://example.com/boots".into_url().unwrap();
+        let items = extract(&url, &Document::from(PAGE));
+        assert_eq!(sink.write_page(&url, &items).unwrap(), 2);
+        assert_eq!(sink.write_page(&url, &items[..2]).unwrap(), 0);
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.starts_with("{\"items\":[{\"data\":{\"@context\":\"http://schema.org\""));
+    }
+}
@@ -0,0 +1,408 @@
+use common::{escape_csv, store_file_name};
+use error::*;
+use hyper::Url;
+use select::document::Document;
+use select::node::Node;
+use select::predicate::Name;
+use serde_json::{self, Value};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Largest spans honored, as browsers do
+const MAX_COLSPAN: usize = 1000;
+const MAX_ROWSPAN: usize = 65534;
+
+/// A data table of a page, its spanning cells repeated in every row and column they cover.
+///
+/// `header_rows` - Number of rows at the top of `rows` which are headers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    caption: Option<String>,
+    header_rows: usize,
+    rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    /// Read the rows of a `<table>`, the rows of `<thead>` first
+    pub fn from_node(table: &Node) -> Table {
+        let mut head = Vec::new();
+        let mut body = Vec::new();
+        for tr in table.find(Name("tr")).iter() {
+            let cells = cells(&tr);
+            if cells.is_empty() {
+                continue;
+            }
+            if tr.parent().and_then(|p| p.name()) == Some("thead") {
+                head.push(cells);
+            } else {
+                body.push(cells);
+            }
+        }
+        // Leading rows of header cells are headers too
+        let header_rows = head.len() +
+                          body.iter().take_while(|row| row.iter().all(|c| c.header)).count();
+        head.extend(body);
+        Table {
+            caption: table.find(Name("caption")).first().map(|c| normalize(&c.text())),
+            header_rows: header_rows,
+            rows: expand(head),
+        }
+    }
+
+    /// Return the text of the `<caption>` of the table
+    pub fn get_caption(&self) -> Option<&str> {
+        self.caption.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the number of rows at the top of the table which are headers
+    pub fn get_header_rows(&self) -> usize {
+        self.header_rows
+    }
+
+    /// Return the text of the cells, row by row
+    pub fn get_rows(&self) -> &Vec<Vec<String>> {
+        &self.rows
+    }
+
+    /// Return the number of columns
+    pub fn width(&self) -> usize {
+        self.rows.first().map(|r| r.len()).unwrap_or(0)
+    }
+
+    /// Return the label of each column, its distinct header cells joined with ` / `
+    pub fn columns(&self) -> Vec<String> {
+        (0..self.width())
+            .map(|i| {
+                let mut labels: Vec<&str> = Vec::new();
+                for row in &self.rows[..self.header_rows] {
+                    let label = row[i].as_str();
+                    if !label.is_empty() && labels.last() != Some(&label) {
+                        labels.push(label);
+                    }
+                }
+                labels.join(" / ")
+            })
+            .collect()
+    }
+
+    /// Write the rows, headers included, as CSV
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> Result<()> {
+        for row in &self.rows {
+            let fields: Vec<String> = row.iter().map(|f| escape_csv(f)).collect();
+            writeln!(w, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// A cell as written in the page, before spans are expanded
+#[derive(Debug, Clone)]
+struct Cell {
+    text: String,
+    header: bool,
+    colspan: usize,
+    /// 0 to span the rest of the table
+    rowspan: usize,
+}
+
+/// Return the `<td>` and `<th>` of a row
+fn cells(tr: &Node) -> Vec<Cell> {
+    let span = |cell: &Node, name: &str, default: usize, max: usize| {
+        cell.attr(name)
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .map(|s| cmp::min(s, max))
+            .unwrap_or(default)
+    };
+    let mut cells = Vec::new();
+    let mut child = tr.first_child();
+    while let Some(cell) = child {
+        child = cell.next();
+        let header = match cell.name() {
+            Some("th") => true,
+            Some("td") => false,
+            _ => continue,
+        };
+        cells.push(Cell {
+            text: normalize(&cell.text()),
+            header: header,
+            colspan: cmp::max(span(&cell, "colspan", 1, MAX_COLSPAN), 1),
+            rowspan: span(&cell, "rowspan", 1, MAX_ROWSPAN),
+        });
+    }
+    cells
+}
+
+/// Lay cells out in a grid, repeating spanning cells and padding short rows
+fn expand(rows: Vec<Vec<Cell>>) -> Vec<Vec<String>> {
+    let count = rows.len();
+    let mut grid: Vec<Vec<Option<String>>> = vec![Vec::new(); count];
+    for (r, row) in rows.into_iter().enumerate() {
+        let mut col = 0;
+        for cell in row {
+            // Skip the columns taken by cells spanning from the rows above
+            while grid[r].get(col).map(|c| c.is_some()).unwrap_or(false) {
+                col += 1;
+            }
+            let rowspan = if cell.rowspan == 0 {
+                count - r
+            } else {
+                cmp::min(cell.rowspan, count - r)
+            };
+            for line in &mut grid[r..r + rowspan] {
+                if line.len() < col + cell.colspan {
+                    line.resize(col + cell.colspan, None);
+                }
+                for slot in &mut line[col..col + cell.colspan] {
+                    if slot.is_none() {
+                        *slot = Some(cell.text.clone());
+                    }
+                }
+            }
+            col += cell.colspan;
+        }
+    }
+    let width = grid.iter().map(|r| r.len()).max().unwrap_or(0);
+    grid.into_iter()
+        .map(|row| {
+            let mut row: Vec<String> =
+                row.into_iter().map(|c| c.unwrap_or_else(String::new)).collect();
+            row.resize(width, String::new());
+            row
+        })
+        .collect()
+}
+
+/// Collapse the whitespace of text
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Check if a `<table>` holds data rather than lays out the page.
+///
+/// Tables with a presentation role or nested tables are layout, tables with a caption,
+/// header cells or a summary are data, others are data if they have two rows and columns.
+pub fn is_data_table(table: &Node) -> bool {
+    let role = table.attr("role").map(|r| r.trim().to_lowercase());
+    if role == Some("presentation".to_string()) || role == Some("none".to_string()) ||
+       !table.find(Name("table")).is_empty() {
+        return false;
+    }
+    if table.attr("summary").is_some() || !table.find(Name("caption")).is_empty() ||
+       !table.find(Name("th")).is_empty() || !table.find(Name("thead")).is_empty() {
+        return true;
+    }
+    let rows = table.find(Name("tr"));
+    rows.len() >= 2 && rows.iter().any(|tr| cells(&tr).len() >= 2)
+}
+
+/// Return the data tables of doc, in document order
+pub fn extract(doc: &Document) -> Vec<Table> {
+    doc.find(Name("table"))
+        .iter()
+        .filter(is_data_table)
+        .map(|table| Table::from_node(&table))
+        .filter(|table| !table.rows.is_empty())
+        .collect()
+}
+
+/// A table written by a `TableSink`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableEntry {
+    /// Position of the table among the data tables of its page, from 1
+    index: usize,
+    file: String,
+    caption: Option<String>,
+    columns: Vec<String>,
+    header_rows: usize,
+    rows: usize,
+}
+
+impl TableEntry {
+    /// Return the position of the table among the data tables of its page, from 1
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    /// Return the name of the CSV file of the table
+    pub fn get_file(&self) -> &str {
+        &self.file
+    }
+
+    /// Return the caption of the table
+    pub fn get_caption(&self) -> Option<&str> {
+        self.caption.as_ref().map(|s| s.as_str())
+    }
+
+    /// Return the label of each column
+    pub fn get_columns(&self) -> &Vec<String> {
+        &self.columns
+    }
+
+    /// Return the number of header rows
+    pub fn get_header_rows(&self) -> usize {
+        self.header_rows
+    }
+
+    /// Return the number of rows below the headers
+    pub fn get_rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn to_json(&self, url: &str) -> Value {
+        json!({
+            "url": url,
+            "index": self.index,
+            "file": self.file,
+            "caption": self.caption,
+            "columns": self.columns,
+            "header_rows": self.header_rows,
+            "rows": self.rows
+        })
+    }
+}
+
+/// Output directory of the data tables of pages, one CSV file per table.
+///
+/// Files are named after the url of the page and the index of the table, like
+/// `http__example.com_stats_2.csv`, and listed in `index.json` by `write_index`.
+/// Files of tables a revisited page no longer has are removed.
+pub struct TableSink {
+    dir: PathBuf,
+    pages: Mutex<BTreeMap<String, Vec<TableEntry>>>,
+}
+
+impl TableSink {
+    /// Write tables in dir, creating it if needed
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<TableSink> {
+        fs::create_dir_all(&dir)?;
+        Ok(TableSink {
+            dir: dir.as_ref().to_path_buf(),
+            pages: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Write the tables of the page at url, return the number of tables written
+    ///
+    /// Tables written for a previous visit of the page are replaced in the index.
+    pub fn write_page(&self, url: &Url, tables: &[Table]) -> Result<usize> {
+        let mut entries = Vec::new();
+        for (i, table) in tables.iter().enumerate() {
+            let file = format!("{}_{}.csv", store_file_name(url), i + 1);
+            table.write_csv(&mut BufWriter::new(File::create(self.dir.join(&file))?))?;
+            entries.push(TableEntry {
+                index: i + 1,
+                file: file,
+                caption: table.caption.clone(),
+                columns: table.columns(),
+                header_rows: table.header_rows,
+                rows: table.rows.len() - table.header_rows,
+            });
+        }
+        let mut pages = match self.pages.lock() {
+            Ok(pages) => pages,
+            Err(e) => bail!(ErrorKind::PoisonError(e.to_string())),
+        };
+        let previous = if entries.is_empty() {
+            pages.remove(url.as_str())
+        } else {
+            pages.insert(url.to_string(), entries)
+        };
+        for entry in previous.unwrap_or_default().iter().skip(tables.len()) {
+            fs::remove_file(self.dir.join(&entry.file))?;
+        }
+        Ok(tables.len())
+    }
+
+    /// Write `index.json` listing the tables written, return its path and the number of tables
+    pub fn write_index(&self) -> Result<(PathBuf, usize)> {
+        let pages = match self.pages.lock() {
+            Ok(pages) => pages,
+            Err(e) => bail!(ErrorKind::PoisonError(e.to_string())),
+        };
+        let tables: Vec<Value> = pages.iter()
+            .flat_map(|(url, entries)| entries.iter().map(move |e| e.to_json(url)))
+            .collect();
+        let path = self.dir.join("index.json");
+        let mut w = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(&mut w, &json!({ "tables": tables }))?;
+        w.write_all(b"\n")?;
+        Ok((path, tables.len()))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use hyper::client::IntoUrl;
+    use select::document::Document;
+    use serde_json::{self, Value};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use super::{TableSink, extract};
+
+    const PAGE: &'static str = "<html><body>\
+        <table role=\"presentation\"><tr><td>Menu</td><td>Content</td></tr>\
+          <tr><td>a</td><td>b</td></tr></table>\
+        <table><caption> Population\n by region </caption>\
+          <thead><tr><th rowspan=\"2\">Region</th><th colspan=\"2\">Population</th></tr>\
+          <tr><th>2015</th><th>2016</th></tr></thead>\
+          <tbody><tr><td rowspan=\"2\">North, East</td><td>1 200</td><td>1 350</td></tr>\
+          <tr><td>800</td></tr>\
+          <tr><td>South</td><td colspan=\"2\">\"n/a\"</td></tr></tbody>\
+        </table>\
+        <table><tr><td>Alone</td></tr></table>\
+        </body></html>";
+
+    #[test]
+    fn expand_spans() {
+        let tables = extract(&Document::from(PAGE));
+        assert_eq!(tables.len(), 1);
+        let table = &tables[0];
+        assert_eq!(table.caption, Some("Population by region".to_string()));
+        assert_eq!(table.header_rows, 2);
+        assert_eq!(table.rows,
+                   vec![vec!["Region", "Population", "Population"],
+                        vec!["Region", "2015", "2016"],
+                        vec!["North, East", "1 200", "1 350"],
+                        vec!["North, East", "800", ""],
+                        vec!["South", "\"n/a\"", "\"n/a\""]]);
+        assert_eq!(table.columns(), vec!["Region", "Population / 2015", "Population / 2016"]);
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(2), Some("\"North, East\",1 200,1 350"));
+        assert_eq!(csv.lines().nth(4), Some("South,\"\"\"n/a\"\"\",\"\"\"n/a\"\"\""));
+    }
+
+    #[test]
+    fn write_tables_and_index() {
+        let dir = env::temp_dir().join("unibot_tables_test");
+        let _ = fs::remove_dir_all(&dir);
+        let sink = TableSink::new(&dir).unwrap();
+        let url = "http://example.com/stats".into_url().unwrap();
+        let tables = extract(&Document::from(PAGE));
+        assert_eq!(sink.write_page(&url, &tables).unwrap(), 1);
+        assert!(dir.join("http__example.com_stats_1.csv").exists());
+        let (path, count) = sink.write_index().unwrap();
+        assert_eq!(count, 1);
+        let mut json = String::new();
+        File::open(path).unwrap().read_to_string(&mut json).unwrap();
+        let index: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(index["tables"][0]["file"], json!("http__example.com_stats_1.csv"));
+        assert_eq!(index["tables"][0]["rows"], json!(3));
+
+        // A revisit without tables drops the page from the index, and its files
+        sink.write_page(&url, &[]).unwrap();
+        assert_eq!(sink.write_index().unwrap().1, 0);
+        assert!(!dir.join("http__example.com_stats_1.csv").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
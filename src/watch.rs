@@ -0,0 +1,296 @@
+use common::{hash_bytes, store_file_name};
+use crawl::fetch::Fetcher;
+use diff::{DiffLine, diff_lines};
+use error::*;
+use hyper::Url;
+use scrap::scrap_text;
+use select::document::Document;
+use selector::Selector;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Settings of a watch.
+///
+/// `selector` - Region of the pages to compare, the whole page if none
+///
+/// `state_dir` - Directory keeping the last version of each page
+///
+/// `command` - Shell command run when a page changed
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    selector: Option<Selector>,
+    state_dir: PathBuf,
+    command: Option<String>,
+}
+
+impl WatchConfig {
+    pub fn new<P: AsRef<Path>>(state_dir: P) -> WatchConfig {
+        WatchConfig {
+            selector: None,
+            state_dir: state_dir.as_ref().to_path_buf(),
+            command: None,
+        }
+    }
+
+    pub fn selector(&self) -> Option<&Selector> {
+        self.selector.as_ref()
+    }
+
+    /// Only compare the elements matching selector
+    pub fn set_selector(mut self, selector: Option<Selector>) -> WatchConfig {
+        self.selector = selector;
+        self
+    }
+
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_ref().map(|c| c.as_str())
+    }
+
+    /// Run command when a page changed, with `UNIBOT_URL`, `UNIBOT_OLD_HASH`,
+    /// `UNIBOT_NEW_HASH` and `UNIBOT_DIFF` in its environment
+    pub fn set_command(mut self, command: Option<String>) -> WatchConfig {
+        self.command = command;
+        self
+    }
+}
+
+/// A watched page which changed since its last version.
+///
+/// `diff` - File with the text diff between both versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageChange {
+    url: Url,
+    old_hash: u64,
+    new_hash: u64,
+    diff: PathBuf,
+}
+
+impl PageChange {
+    /// Return the url of the page
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Return the hash of the text of the last version
+    pub fn get_old_hash(&self) -> u64 {
+        self.old_hash
+    }
+
+    /// Return the hash of the text of the new version
+    pub fn get_new_hash(&self) -> u64 {
+        self.new_hash
+    }
+
+    /// Return the file with the text diff between both versions
+    pub fn get_diff(&self) -> &Path {
+        &self.diff
+    }
+}
+
+/// Fetch pages again and again, alerting when they change
+#[derive(Debug)]
+pub struct Watcher {
+    fetcher: Box<Fetcher>,
+    config: WatchConfig,
+}
+
+impl Watcher {
+    pub fn new(fetcher: Box<Fetcher>, config: WatchConfig) -> Watcher {
+        Watcher {
+            fetcher: fetcher,
+            config: config,
+        }
+    }
+
+    pub fn config(&self) -> &WatchConfig {
+        &self.config
+    }
+
+    /// Return the watched text of a page
+    pub fn extract(&self, body: &str) -> String {
+        let doc = Document::from(body);
+        let selector = match self.config.selector {
+            Some(ref selector) => selector,
+            None => return scrap_text(&doc),
+        };
+        let nodes = selector.select(&doc);
+        let mut texts = Vec::new();
+        for node in &nodes {
+            // Text of nested matches is already in the text of their ancestor
+            let mut parent = node.parent();
+            let mut nested = false;
+            while let Some(p) = parent {
+                if nodes.iter().any(|n| n.index() == p.index()) {
+                    nested = true;
+                    break;
+                }
+                parent = p.parent();
+            }
+            if !nested {
+                texts.push(scrap_text(&Document::from(node.html().as_str())));
+            }
+        }
+        texts.join("\n")
+    }
+
+    /// Fetch url and compare it to its last version, running the command if it changed.
+    ///
+    /// The first version of a page is only kept. A new version is kept once the command
+    /// succeeded, so a failed command runs again on the next check.
+    pub fn check(&self, url: &Url) -> Result<Option<PageChange>> {
+        let response = self.fetcher.get(url)?;
+        if !response.is_success() {
+            return Err(ErrorKind::BadStatus(response.get_status()).into());
+        }
+        let text = self.extract(&String::from_utf8_lossy(response.get_body()));
+        let name = store_file_name(url);
+        let path = self.config.state_dir.join(&name);
+        let old = match File::open(&path) {
+            Ok(mut f) => {
+                let mut old = String::new();
+                f.read_to_string(&mut old)?;
+                Some(old)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        fs::create_dir_all(&self.config.state_dir)?;
+        let old = match old {
+            Some(ref old) if *old != text => old,
+            Some(_) => return Ok(None),
+            None => {
+                File::create(&path)?.write_all(text.as_bytes())?;
+                return Ok(None);
+            }
+        };
+        let diff = self.config.state_dir.join(format!("{}.diff", name));
+        write_diff(&mut File::create(&diff)?, &diff_lines(old, &text))?;
+        let change = PageChange {
+            url: url.clone(),
+            old_hash: hash_bytes(old.as_bytes()),
+            new_hash: hash_bytes(text.as_bytes()),
+            diff: diff,
+        };
+        if let Some(ref command) = self.config.command {
+            run_command(command, &change)?;
+        }
+        File::create(&path)?.write_all(text.as_bytes())?;
+        Ok(Some(change))
+    }
+}
+
+/// Write a text diff, prefixing lines with `-` if removed, `+` if added
+fn write_diff<W: Write>(w: &mut W, lines: &[DiffLine]) -> Result<()> {
+    for line in lines {
+        match *line {
+            DiffLine::Same(ref l) => writeln!(w, "  {}", l)?,
+            DiffLine::Removed(ref l) => writeln!(w, "- {}", l)?,
+            DiffLine::Added(ref l) => writeln!(w, "+ {}", l)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Run command in a shell, describing change in its environment
+fn run_command(command: &str, change: &PageChange) -> Result<()> {
+    let status = shell(command)
+        .env("UNIBOT_URL", change.url.as_str())
+        .env("UNIBOT_OLD_HASH", format!("{:016x}", change.old_hash))
+        .env("UNIBOT_NEW_HASH", format!("{:016x}", change.new_hash))
+        .env("UNIBOT_DIFF", &change.diff)
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ErrorKind::CommandFailed(command.to_string(), status.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crawl::fetch::{FetchResponse, Fetcher};
+    use error::*;
+    use hyper::Url;
+    use hyper::client::IntoUrl;
+    use hyper::header::Headers;
+    use selector::Selector;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::sync::Mutex;
+    use super::{WatchConfig, Watcher};
+
+    /// Serve the given bodies one after the other
+    #[derive(Debug)]
+    struct Versions(Mutex<Vec<&'static str>>);
+
+    impl Fetcher for Versions {
+        fn get(&self, url: &Url) -> Result<FetchResponse> {
+            Ok(FetchResponse::new(url.clone(),
+                                  200,
+                                  Headers::new(),
+                                  self.0.lock().unwrap().remove(0).as_bytes().to_vec()))
+        }
+    }
+
+    #[test]
+    fn alert_on_change() {
+        let dir = env::temp_dir().join("unibot_watch_test");
+        let _ = fs::remove_dir_all(&dir);
+        let versions = vec!["<p>Status</p><div id=\"s\"><p>up</p><p>fast</p></div>",
+                            "<p>Status!</p><div id=\"s\"><p>up</p><p>fast</p></div>",
+                            "<p>Status</p><div id=\"s\"><p>down</p><p>fast</p></div>"];
+        let out = dir.join("out");
+        let config = WatchConfig::new(&dir)
+            .set_selector(Some(Selector::parse("#s, #s p").unwrap()))
+            .set_command(Some(format!("echo \"$UNIBOT_URL $UNIBOT_OLD_HASH\" > {}",
+                                      out.display())));
+        let watcher = Watcher::new(Box::new(Versions(Mutex::new(versions))), config);
+        let url = "http://example.com/status".into_url().unwrap();
+        assert_eq!(watcher.check(&url).unwrap(), None);
+        assert_eq!(watcher.check(&url).unwrap(), None);
+        let change = watcher.check(&url).unwrap().unwrap();
+        assert!(change.old_hash != change.new_hash);
+        let mut diff = String::new();
+        File::open(&change.diff).unwrap().read_to_string(&mut diff).unwrap();
+        assert_eq!(diff, "- up\n+ down\n  fast\n");
+        let mut out_text = String::new();
+        File::open(&out).unwrap().read_to_string(&mut out_text).unwrap();
+        assert_eq!(out_text, format!("{} {:016x}\n", url, change.old_hash));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keep_change_when_command_fails() {
+        let dir = env::temp_dir().join("unibot_watch_failure_test");
+        let _ = fs::remove_dir_all(&dir);
+        let versions = vec!["<p>up</p>", "<p>down</p>", "<p>down</p>"];
+        let config = WatchConfig::new(&dir).set_command(Some("exit 1".to_string()));
+        let watcher = Watcher::new(Box::new(Versions(Mutex::new(versions))), config);
+        let url = "http://example.com/status".into_url().unwrap();
+        assert_eq!(watcher.check(&url).unwrap(), None);
+        assert!(watcher.check(&url).is_err());
+        assert!(watcher.check(&url).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1269 @@
+use error::*;
+use select::document::Document;
+use select::node::Node;
+use std::cmp::Ordering;
+use std::f64;
+use std::fmt;
+use std::str::FromStr;
+
+/// A node of a document as XPath sees it
+#[derive(Debug, Clone, PartialEq)]
+pub enum XNode {
+    /// The root of the document, parent of the top level nodes
+    Root,
+    /// An element, text or comment, by its index in the document
+    Node(usize),
+    /// An attribute of the element at index, by its name
+    Attribute(usize, String),
+}
+
+/// Result of an XPath expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum XValue {
+    /// Nodes in document order
+    Nodes(Vec<XNode>),
+    Number(f64),
+    String(String),
+    Boolean(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Axis {
+    Ancestor,
+    AncestorOrSelf,
+    Attribute,
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Following,
+    FollowingSibling,
+    Parent,
+    Preceding,
+    PrecedingSibling,
+    SelfAxis,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Axis> {
+        Some(match name {
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "attribute" => Axis::Attribute,
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "following" => Axis::Following,
+            "following-sibling" => Axis::FollowingSibling,
+            "parent" => Axis::Parent,
+            "preceding" => Axis::Preceding,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "self" => Axis::SelfAxis,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum NodeTest {
+    /// `*`, any node of the principal type of the axis
+    Any,
+    /// A name, matched case insensitively
+    Name(String),
+    /// `text()`
+    Text,
+    /// `comment()`
+    Comment,
+    /// `node()`
+    Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+impl Step {
+    fn new(axis: Axis, test: NodeTest) -> Step {
+        Step {
+            axis: axis,
+            test: test,
+            predicates: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Union,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Literal(String),
+    Number(f64),
+    Function(String, Vec<Expr>),
+    /// Expression filtered by predicates
+    Filter(Box<Expr>, Vec<Expr>),
+    /// Steps from the root, the context node, or the nodes of an expression
+    Path(PathStart, Vec<Step>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathStart {
+    Root,
+    Context,
+    Expr(Box<Expr>),
+}
+
+/// An XPath 1.0 expression.
+///
+/// Supports every axis but `namespace`, the `*`, name, `text()`, `comment()`
+/// and `node()` tests, predicates, operators and the core function library
+/// but `id()` and `lang()`. Variables are not supported, and `@*` cannot be
+/// evaluated since attributes can only be read by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XPath {
+    source: String,
+    expr: Expr,
+}
+
+impl XPath {
+    /// Parse an expression
+    pub fn parse(source: &str) -> Result<XPath> {
+        let tokens = tokenize(source).map_err(|e| invalid(source, &e))?;
+        let mut parser = Parser {
+            tokens: tokens,
+            pos: 0,
+        };
+        let expr = parser.expr().map_err(|e| invalid(source, &e))?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(source, &format!("unexpected {}", token)));
+        }
+        Ok(XPath {
+            source: source.to_string(),
+            expr: expr,
+        })
+    }
+
+    /// Return the source of the expression
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression with the root of doc as context node
+    pub fn evaluate(&self, doc: &Document) -> Result<XValue> {
+        let evaluator = Evaluator::new(doc);
+        let context = Context {
+            node: XNode::Root,
+            position: 1,
+            size: 1,
+        };
+        evaluator.eval(&self.expr, &context).map_err(|e| invalid(&self.source, &e))
+    }
+
+    /// Return the elements, texts and comments selected in doc, in document order
+    ///
+    /// Fails if the expression is not a node-set.
+    pub fn select<'a>(&self, doc: &'a Document) -> Result<Vec<Node<'a>>> {
+        match self.evaluate(doc)? {
+            XValue::Nodes(nodes) => {
+                Ok(nodes.into_iter()
+                    .filter_map(|n| match n {
+                        XNode::Node(i) => doc.nth(i),
+                        _ => None,
+                    })
+                    .collect())
+            }
+            _ => Err(invalid(&self.source, "expected a node-set")),
+        }
+    }
+
+    /// Return the string values of the nodes selected in doc, or the value of
+    /// the expression as a string if it is not a node-set
+    pub fn strings(&self, doc: &Document) -> Result<Vec<String>> {
+        let evaluator = Evaluator::new(doc);
+        match self.evaluate(doc)? {
+            XValue::Nodes(nodes) => Ok(nodes.iter().map(|n| evaluator.string_value(n)).collect()),
+            value => Ok(vec![evaluator.to_string(&value)]),
+        }
+    }
+}
+
+impl FromStr for XPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<XPath> {
+        XPath::parse(s)
+    }
+}
+
+fn invalid(source: &str, reason: &str) -> Error {
+    ErrorKind::InvalidXPath(format!("{}: {}", source, reason)).into()
+}
+
+/// Result of parsing or evaluating, failing with the reason
+type XResult<T> = ::std::result::Result<T, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    At,
+    Comma,
+    DoubleColon,
+    Dot,
+    DoubleDot,
+    /// `*` as a name test
+    Star,
+    Op(Op),
+    Literal(String),
+    Number(f64),
+    Name(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Slash => write!(f, "'/'"),
+            Token::DoubleSlash => write!(f, "'//'"),
+            Token::LBracket => write!(f, "'['"),
+            Token::RBracket => write!(f, "']'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::At => write!(f, "'@'"),
+            Token::Comma => write!(f, "','"),
+            Token::DoubleColon => write!(f, "'::'"),
+            Token::Dot => write!(f, "'.'"),
+            Token::DoubleDot => write!(f, "'..'"),
+            Token::Star => write!(f, "'*'"),
+            Token::Op(op) => write!(f, "operator {:?}", op),
+            Token::Literal(ref s) => write!(f, "\"{}\"", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(ref n) => write!(f, "'{}'", n),
+        }
+    }
+}
+
+/// Check if a token can be followed by an operator, telling `*` and names
+/// like `div` apart from name tests
+fn ends_operand(token: Option<&Token>) -> bool {
+    match token {
+        None | Some(&Token::At) | Some(&Token::DoubleColon) | Some(&Token::LParen) |
+        Some(&Token::LBracket) | Some(&Token::Comma) | Some(&Token::Op(_)) |
+        Some(&Token::Slash) | Some(&Token::DoubleSlash) => false,
+        _ => true,
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+fn tokenize(source: &str) -> XResult<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let operand = ends_operand(tokens.last());
+        let token = match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                i += 1;
+                continue;
+            }
+            '/' if next == Some('/') => Token::DoubleSlash,
+            '/' => Token::Slash,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '@' => Token::At,
+            ',' => Token::Comma,
+            ':' if next == Some(':') => Token::DoubleColon,
+            '.' if next == Some('.') => Token::DoubleDot,
+            '.' if !next.map(|n| n.is_digit(10)).unwrap_or(false) => Token::Dot,
+            '|' => Token::Op(Op::Union),
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '=' => Token::Op(Op::Eq),
+            '!' if next == Some('=') => Token::Op(Op::Ne),
+            '<' if next == Some('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next == Some('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '*' if operand => Token::Op(Op::Mul),
+            '*' => Token::Star,
+            '"' | '\'' => {
+                let end = match chars[i + 1..].iter().position(|&q| q == c) {
+                    Some(end) => i + 1 + end,
+                    None => return Err("unclosed string".to_string()),
+                };
+                tokens.push(Token::Literal(chars[i + 1..end].iter().cloned().collect()));
+                i = end + 1;
+                continue;
+            }
+            c if c.is_digit(10) || c == '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_digit(10) || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().cloned().collect();
+                match number.parse::<f64>() {
+                    Ok(n) => tokens.push(Token::Number(n)),
+                    Err(_) => return Err(format!("invalid number {}", number)),
+                }
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && is_name_char(chars[i]) {
+                    i += 1;
+                }
+                // A prefixed name, but not an axis
+                if i + 1 < chars.len() && chars[i] == ':' && chars[i + 1] != ':' {
+                    i += 1;
+                    while i < chars.len() && is_name_char(chars[i]) {
+                        i += 1;
+                    }
+                }
+                let name: String = chars[start..i].iter().cloned().collect();
+                let op = match name.as_str() {
+                    "and" if operand => Some(Op::And),
+                    "or" if operand => Some(Op::Or),
+                    "div" if operand => Some(Op::Div),
+                    "mod" if operand => Some(Op::Mod),
+                    _ => None,
+                };
+                tokens.push(match op {
+                    Some(op) => Token::Op(op),
+                    None => Token::Name(name),
+                });
+                continue;
+            }
+            '$' => return Err("variables are not supported".to_string()),
+            c => return Err(format!("unexpected '{}'", c)),
+        };
+        i += match token {
+            Token::DoubleSlash | Token::DoubleColon | Token::DoubleDot | Token::Op(Op::Ne) |
+            Token::Op(Op::Le) | Token::Op(Op::Ge) => 2,
+            _ => 1,
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Return the number of arguments a function takes, at least and at most
+fn arity(name: &str) -> Option<(usize, usize)> {
+    Some(match name {
+        "last" | "position" | "true" | "false" => (0, 0),
+        "count" | "not" | "boolean" | "sum" | "floor" | "ceiling" | "round" => (1, 1),
+        "string" | "string-length" | "normalize-space" | "number" | "name" | "local-name" => {
+            (0, 1)
+        }
+        "starts-with" | "contains" | "substring-before" | "substring-after" => (2, 2),
+        "substring" => (2, 3),
+        "translate" => (3, 3),
+        "concat" => (2, ::std::usize::MAX),
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> XResult<()> {
+        match self.next() {
+            Some(ref token) if *token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {}", expected, token)),
+            None => Err(format!("expected {}", expected)),
+        }
+    }
+
+    fn expr(&mut self) -> XResult<Expr> {
+        self.binary(0)
+    }
+
+    /// Parse operators of precedence level and above, left associative
+    fn binary(&mut self, level: usize) -> XResult<Expr> {
+        const LEVELS: &'static [&'static [Op]] = &[&[Op::Or],
+                                                   &[Op::And],
+                                                   &[Op::Eq, Op::Ne],
+                                                   &[Op::Lt, Op::Le, Op::Gt, Op::Ge],
+                                                   &[Op::Add, Op::Sub],
+                                                   &[Op::Mul, Op::Div, Op::Mod]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Op(op)) if LEVELS[level].contains(&op) => op,
+                _ => return Ok(left),
+            };
+            self.next();
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> XResult<Expr> {
+        if let Some(&Token::Op(Op::Sub)) = self.peek() {
+            self.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        let mut left = self.path()?;
+        while let Some(&Token::Op(Op::Union)) = self.peek() {
+            self.next();
+            let right = self.path()?;
+            left = Expr::Binary(Op::Union, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    /// Check if the next tokens start a filter expression rather than a step
+    fn starts_filter(&self) -> bool {
+        match (self.peek(), self.peek_at(1)) {
+            (Some(&Token::Literal(_)), _) |
+            (Some(&Token::Number(_)), _) |
+            (Some(&Token::LParen), _) => true,
+            (Some(&Token::Name(ref name)), Some(&Token::LParen)) => !is_node_type(name),
+            _ => false,
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        match self.peek() {
+            Some(&Token::Name(_)) | Some(&Token::Star) | Some(&Token::At) | Some(&Token::Dot) |
+            Some(&Token::DoubleDot) => true,
+            _ => false,
+        }
+    }
+
+    fn path(&mut self) -> XResult<Expr> {
+        let (start, mut steps) = match self.peek().cloned() {
+            Some(Token::Slash) => {
+                self.next();
+                if !self.starts_step() {
+                    return Ok(Expr::Path(PathStart::Root, Vec::new()));
+                }
+                (PathStart::Root, Vec::new())
+            }
+            Some(Token::DoubleSlash) => {
+                self.next();
+                (PathStart::Root, vec![Step::new(Axis::DescendantOrSelf, NodeTest::Node)])
+            }
+            _ if self.starts_filter() => {
+                let primary = self.primary()?;
+                let predicates = self.predicates()?;
+                let filter = if predicates.is_empty() {
+                    primary
+                } else {
+                    Expr::Filter(Box::new(primary), predicates)
+                };
+                match self.peek() {
+                    Some(&Token::Slash) => {
+                        self.next();
+                        (PathStart::Expr(Box::new(filter)), Vec::new())
+                    }
+                    Some(&Token::DoubleSlash) => {
+                        self.next();
+                        (PathStart::Expr(Box::new(filter)),
+                         vec![Step::new(Axis::DescendantOrSelf, NodeTest::Node)])
+                    }
+                    _ => return Ok(filter),
+                }
+            }
+            _ => (PathStart::Context, Vec::new()),
+        };
+        loop {
+            steps.push(self.step()?);
+            match self.peek() {
+                Some(&Token::Slash) => {
+                    self.next();
+                }
+                Some(&Token::DoubleSlash) => {
+                    self.next();
+                    steps.push(Step::new(Axis::DescendantOrSelf, NodeTest::Node));
+                }
+                _ => return Ok(Expr::Path(start, steps)),
+            }
+        }
+    }
+
+    fn step(&mut self) -> XResult<Step> {
+        let axis = match (self.peek().cloned(), self.peek_at(1)) {
+            (Some(Token::Dot), _) => {
+                self.next();
+                return Ok(Step::new(Axis::SelfAxis, NodeTest::Node));
+            }
+            (Some(Token::DoubleDot), _) => {
+                self.next();
+                return Ok(Step::new(Axis::Parent, NodeTest::Node));
+            }
+            (Some(Token::At), _) => {
+                self.next();
+                Axis::Attribute
+            }
+            (Some(Token::Name(ref name)), Some(&Token::DoubleColon)) => {
+                let axis = match Axis::from_name(name) {
+                    Some(axis) => axis,
+                    None => return Err(format!("unknown axis {}", name)),
+                };
+                self.pos += 2;
+                axis
+            }
+            _ => Axis::Child,
+        };
+        let test = match self.next() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::Name(name)) => {
+                if let Some(&Token::LParen) = self.peek() {
+                    let test = match name.as_str() {
+                        "text" => NodeTest::Text,
+                        "comment" => NodeTest::Comment,
+                        "node" => NodeTest::Node,
+                        _ => return Err(format!("unknown node type {}", name)),
+                    };
+                    self.next();
+                    self.expect(Token::RParen)?;
+                    test
+                } else {
+                    NodeTest::Name(name.to_lowercase())
+                }
+            }
+            Some(token) => return Err(format!("expected a node test, found {}", token)),
+            None => return Err("expected a node test".to_string()),
+        };
+        if axis == Axis::Attribute && test != NodeTest::Node && !is_name_test(&test) {
+            return Err("attributes can only be selected by name".to_string());
+        }
+        Ok(Step {
+            axis: axis,
+            test: test,
+            predicates: self.predicates()?,
+        })
+    }
+
+    fn predicates(&mut self) -> XResult<Vec<Expr>> {
+        let mut predicates = Vec::new();
+        while let Some(&Token::LBracket) = self.peek() {
+            self.next();
+            predicates.push(self.expr()?);
+            self.expect(Token::RBracket)?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary(&mut self) -> XResult<Expr> {
+        match self.next() {
+            Some(Token::Literal(s)) => Ok(Expr::Literal(s)),
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) => {
+                self.expect(Token::LParen)?;
+                let mut args = Vec::new();
+                if let Some(&Token::RParen) = self.peek() {
+                    self.next();
+                } else {
+                    loop {
+                        args.push(self.expr()?);
+                        match self.next() {
+                            Some(Token::Comma) => continue,
+                            Some(Token::RParen) => break,
+                            _ => return Err(format!("unclosed arguments of {}", name)),
+                        }
+                    }
+                }
+                match arity(&name) {
+                    Some((min, max)) if args.len() >= min && args.len() <= max => {}
+                    Some(_) => return Err(format!("wrong number of arguments to {}", name)),
+                    None => return Err(format!("unknown function {}", name)),
+                }
+                Ok(Expr::Function(name, args))
+            }
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("expected an expression".to_string()),
+        }
+    }
+}
+
+fn is_node_type(name: &str) -> bool {
+    name == "text" || name == "comment" || name == "node" || name == "processing-instruction"
+}
+
+fn is_name_test(test: &NodeTest) -> bool {
+    match *test {
+        NodeTest::Name(_) => true,
+        _ => false,
+    }
+}
+
+struct Context {
+    node: XNode,
+    position: usize,
+    size: usize,
+}
+
+/// Evaluate expressions over a document, nodes being compared by their rank in document order
+struct Evaluator<'a> {
+    doc: &'a Document,
+    roots: Vec<usize>,
+    ranks: Vec<usize>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(doc: &'a Document) -> Evaluator<'a> {
+        let mut roots = Vec::new();
+        let mut count = 0;
+        while let Some(node) = doc.nth(count) {
+            if node.parent().is_none() {
+                roots.push(count);
+            }
+            count += 1;
+        }
+        let mut ranks = vec![0; count];
+        let mut rank = 0;
+        let mut stack: Vec<usize> = roots.iter().rev().cloned().collect();
+        while let Some(i) = stack.pop() {
+            ranks[i] = rank;
+            rank += 1;
+            let mut child = doc.nth(i).and_then(|n| n.last_child());
+            while let Some(c) = child {
+                stack.push(c.index());
+                child = c.prev();
+            }
+        }
+        Evaluator {
+            doc: doc,
+            roots: roots,
+            ranks: ranks,
+        }
+    }
+
+    fn node(&self, i: usize) -> Option<Node<'a>> {
+        self.doc.nth(i)
+    }
+
+    fn compare_order(&self, a: &XNode, b: &XNode) -> Ordering {
+        let key = |n: &XNode| -> (usize, usize) {
+            match *n {
+                XNode::Root => (0, 0),
+                XNode::Node(i) => (self.ranks[i] + 1, 0),
+                XNode::Attribute(i, _) => (self.ranks[i] + 1, 1),
+            }
+        };
+        match (a, b) {
+            (&XNode::Attribute(i, ref x), &XNode::Attribute(j, ref y)) if i == j => x.cmp(y),
+            _ => key(a).cmp(&key(b)),
+        }
+    }
+
+    /// Sort nodes in document order, removing duplicates
+    fn sort(&self, nodes: &mut Vec<XNode>) {
+        nodes.sort_by(|a, b| self.compare_order(a, b));
+        nodes.dedup();
+    }
+
+    fn children(&self, node: &XNode) -> Vec<XNode> {
+        match *node {
+            XNode::Root => self.roots.iter().map(|&i| XNode::Node(i)).collect(),
+            XNode::Node(i) => {
+                let mut children = Vec::new();
+                let mut child = self.node(i).and_then(|n| n.first_child());
+                while let Some(c) = child {
+                    children.push(XNode::Node(c.index()));
+                    child = c.next();
+                }
+                children
+            }
+            XNode::Attribute(..) => Vec::new(),
+        }
+    }
+
+    fn parent(&self, node: &XNode) -> Option<XNode> {
+        match *node {
+            XNode::Root => None,
+            XNode::Node(i) => {
+                Some(self.node(i)
+                    .and_then(|n| n.parent())
+                    .map(|p| XNode::Node(p.index()))
+                    .unwrap_or(XNode::Root))
+            }
+            XNode::Attribute(i, _) => Some(XNode::Node(i)),
+        }
+    }
+
+    /// Return the descendants of node in document order
+    fn descendants(&self, node: &XNode) -> Vec<XNode> {
+        let mut descendants = Vec::new();
+        let mut stack: Vec<XNode> = self.children(node).into_iter().rev().collect();
+        while let Some(n) = stack.pop() {
+            stack.extend(self.children(&n).into_iter().rev());
+            descendants.push(n);
+        }
+        descendants
+    }
+
+    fn ancestors(&self, node: &XNode) -> Vec<XNode> {
+        let mut ancestors = Vec::new();
+        let mut parent = self.parent(node);
+        while let Some(p) = parent {
+            parent = self.parent(&p);
+            ancestors.push(p);
+        }
+        ancestors
+    }
+
+    /// Return the siblings before and after node, the ones before in reverse document order
+    fn siblings(&self, node: &XNode) -> (Vec<XNode>, Vec<XNode>) {
+        if let XNode::Attribute(..) = *node {
+            return (Vec::new(), Vec::new());
+        }
+        let siblings = match self.parent(node) {
+            Some(parent) => self.children(&parent),
+            None => return (Vec::new(), Vec::new()),
+        };
+        match siblings.iter().position(|s| s == node) {
+            Some(i) => {
+                (siblings[..i].iter().rev().cloned().collect(), siblings[i + 1..].to_vec())
+            }
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+
+    /// Return the nodes of axis from node, nearest first
+    fn axis(&self, axis: Axis, node: &XNode) -> Vec<XNode> {
+        match axis {
+            Axis::SelfAxis => vec![node.clone()],
+            Axis::Child => self.children(node),
+            Axis::Parent => self.parent(node).into_iter().collect(),
+            Axis::Descendant => self.descendants(node),
+            Axis::DescendantOrSelf => {
+                let mut nodes = vec![node.clone()];
+                nodes.extend(self.descendants(node));
+                nodes
+            }
+            Axis::Ancestor => self.ancestors(node),
+            Axis::AncestorOrSelf => {
+                let mut nodes = vec![node.clone()];
+                nodes.extend(self.ancestors(node));
+                nodes
+            }
+            Axis::FollowingSibling => self.siblings(node).1,
+            Axis::PrecedingSibling => self.siblings(node).0,
+            Axis::Following => {
+                let mut nodes = Vec::new();
+                let mut current = Some(node.clone());
+                if let XNode::Attribute(i, _) = *node {
+                    nodes.extend(self.descendants(&XNode::Node(i)));
+                    current = Some(XNode::Node(i));
+                }
+                while let Some(n) = current {
+                    for sibling in self.siblings(&n).1 {
+                        nodes.extend(self.descendants(&sibling).into_iter().rev());
+                        nodes.push(sibling);
+                    }
+                    current = self.parent(&n);
+                }
+                // Nodes were pushed subtree by subtree, each in reverse order
+                self.sort(&mut nodes);
+                nodes
+            }
+            Axis::Preceding => {
+                let mut nodes = Vec::new();
+                let mut current = Some(match *node {
+                    XNode::Attribute(i, _) => XNode::Node(i),
+                    ref n => n.clone(),
+                });
+                while let Some(n) = current {
+                    for sibling in self.siblings(&n).0 {
+                        nodes.extend(self.descendants(&sibling).into_iter().rev());
+                        nodes.push(sibling);
+                    }
+                    current = self.parent(&n);
+                }
+                nodes
+            }
+            // Attributes are selected by name in the step
+            Axis::Attribute => Vec::new(),
+        }
+    }
+
+    fn matches(&self, test: &NodeTest, node: &XNode) -> bool {
+        let n = match *node {
+            XNode::Node(i) => self.node(i),
+            XNode::Root | XNode::Attribute(..) => return *test == NodeTest::Node,
+        };
+        let n = match n {
+            Some(n) => n,
+            None => return false,
+        };
+        match *test {
+            NodeTest::Node => true,
+            NodeTest::Any => n.name().is_some(),
+            NodeTest::Name(ref name) => {
+                n.name().map(|m| m.to_lowercase() == *name).unwrap_or(false)
+            }
+            NodeTest::Text => n.as_text().is_some(),
+            NodeTest::Comment => n.as_comment().is_some(),
+        }
+    }
+
+    /// Return the nodes selected by step from node, in axis order
+    fn step_nodes(&self, step: &Step, node: &XNode) -> Vec<XNode> {
+        if step.axis != Axis::Attribute {
+            return self.axis(step.axis, node)
+                .into_iter()
+                .filter(|n| self.matches(&step.test, n))
+                .collect();
+        }
+        match (node, &step.test) {
+            (&XNode::Node(i), &NodeTest::Name(ref name)) => {
+                match self.node(i).and_then(|n| n.attr(name)) {
+                    Some(_) => vec![XNode::Attribute(i, name.clone())],
+                    None => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Keep the nodes matching every predicate, positions being their index in nodes
+    fn filter(&self, mut nodes: Vec<XNode>, predicates: &[Expr]) -> XResult<Vec<XNode>> {
+        for predicate in predicates {
+            let size = nodes.len();
+            let mut kept = Vec::new();
+            for (i, node) in nodes.into_iter().enumerate() {
+                let context = Context {
+                    node: node,
+                    position: i + 1,
+                    size: size,
+                };
+                let keep = match self.eval(predicate, &context)? {
+                    XValue::Number(n) => n == (i + 1) as f64,
+                    value => self.to_boolean(&value),
+                };
+                if keep {
+                    kept.push(context.node);
+                }
+            }
+            nodes = kept;
+        }
+        Ok(nodes)
+    }
+
+    fn eval(&self, expr: &Expr, context: &Context) -> XResult<XValue> {
+        match *expr {
+            Expr::Literal(ref s) => Ok(XValue::String(s.clone())),
+            Expr::Number(n) => Ok(XValue::Number(n)),
+            Expr::Neg(ref e) => Ok(XValue::Number(-self.to_number(&self.eval(e, context)?))),
+            Expr::Binary(op, ref left, ref right) => self.binary(op, left, right, context),
+            Expr::Function(ref name, ref args) => self.function(name, args, context),
+            Expr::Filter(ref e, ref predicates) => {
+                let mut nodes = self.node_set(self.eval(e, context)?)?;
+                self.sort(&mut nodes);
+                Ok(XValue::Nodes(self.filter(nodes, predicates)?))
+            }
+            Expr::Path(ref start, ref steps) => {
+                let mut nodes = match *start {
+                    PathStart::Root => vec![XNode::Root],
+                    PathStart::Context => vec![context.node.clone()],
+                    PathStart::Expr(ref e) => self.node_set(self.eval(e, context)?)?,
+                };
+                for step in steps {
+                    let mut next = Vec::new();
+                    for node in &nodes {
+                        next.extend(self.filter(self.step_nodes(step, node), &step.predicates)?);
+                    }
+                    nodes = next;
+                    self.sort(&mut nodes);
+                }
+                Ok(XValue::Nodes(nodes))
+            }
+        }
+    }
+
+    fn node_set(&self, value: XValue) -> XResult<Vec<XNode>> {
+        match value {
+            XValue::Nodes(nodes) => Ok(nodes),
+            _ => Err("expected a node-set".to_string()),
+        }
+    }
+
+    fn binary(&self, op: Op, left: &Expr, right: &Expr, context: &Context) -> XResult<XValue> {
+        match op {
+            Op::Or => {
+                let left = self.to_boolean(&self.eval(left, context)?);
+                Ok(XValue::Boolean(left || self.to_boolean(&self.eval(right, context)?)))
+            }
+            Op::And => {
+                let left = self.to_boolean(&self.eval(left, context)?);
+                Ok(XValue::Boolean(left && self.to_boolean(&self.eval(right, context)?)))
+            }
+            Op::Union => {
+                let mut nodes = self.node_set(self.eval(left, context)?)?;
+                nodes.extend(self.node_set(self.eval(right, context)?)?);
+                self.sort(&mut nodes);
+                Ok(XValue::Nodes(nodes))
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+                let a = self.to_number(&self.eval(left, context)?);
+                let b = self.to_number(&self.eval(right, context)?);
+                Ok(XValue::Number(match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    _ => a % b,
+                }))
+            }
+            _ => {
+                let left = self.eval(left, context)?;
+                let right = self.eval(right, context)?;
+                Ok(XValue::Boolean(self.compare(op, &left, &right)))
+            }
+        }
+    }
+
+    /// Compare two values as XPath does, node-sets comparing true if any of their nodes does
+    fn compare(&self, op: Op, left: &XValue, right: &XValue) -> bool {
+        match (left, right) {
+            (&XValue::Nodes(ref nodes), &XValue::Boolean(_)) |
+            (&XValue::Boolean(_), &XValue::Nodes(ref nodes)) => {
+                let nodes = XValue::Boolean(!nodes.is_empty());
+                match *left {
+                    XValue::Boolean(_) => self.compare(op, left, &nodes),
+                    _ => self.compare(op, &nodes, right),
+                }
+            }
+            (&XValue::Nodes(ref nodes), _) => {
+                nodes.iter().any(|n| {
+                    let value = XValue::String(self.string_value(n));
+                    self.compare(op, &value, right)
+                })
+            }
+            (_, &XValue::Nodes(ref nodes)) => {
+                nodes.iter().any(|n| {
+                    let value = XValue::String(self.string_value(n));
+                    self.compare(op, left, &value)
+                })
+            }
+            _ if op == Op::Eq || op == Op::Ne => {
+                let equal = match (left, right) {
+                    (&XValue::Boolean(_), _) |
+                    (_, &XValue::Boolean(_)) => self.to_boolean(left) == self.to_boolean(right),
+                    (&XValue::Number(_), _) |
+                    (_, &XValue::Number(_)) => self.to_number(left) == self.to_number(right),
+                    _ => self.to_string(left) == self.to_string(right),
+                };
+                equal == (op == Op::Eq)
+            }
+            _ => {
+                let (a, b) = (self.to_number(left), self.to_number(right));
+                match op {
+                    Op::Lt => a < b,
+                    Op::Le => a <= b,
+                    Op::Gt => a > b,
+                    _ => a >= b,
+                }
+            }
+        }
+    }
+
+    fn string_value(&self, node: &XNode) -> String {
+        match *node {
+            XNode::Root => {
+                self.roots.iter().filter_map(|&i| self.node(i)).map(|n| n.text()).collect()
+            }
+            XNode::Node(i) => {
+                match self.node(i) {
+                    Some(n) => {
+                        n.as_text()
+                            .or_else(|| n.as_comment())
+                            .map(|t| t.to_string())
+                            .unwrap_or_else(|| n.text())
+                    }
+                    None => String::new(),
+                }
+            }
+            XNode::Attribute(i, ref name) => {
+                self.node(i).and_then(|n| n.attr(name)).unwrap_or("").to_string()
+            }
+        }
+    }
+
+    fn to_string(&self, value: &XValue) -> String {
+        match *value {
+            XValue::Nodes(ref nodes) => {
+                nodes.first().map(|n| self.string_value(n)).unwrap_or_else(String::new)
+            }
+            XValue::String(ref s) => s.clone(),
+            XValue::Boolean(b) => b.to_string(),
+            XValue::Number(n) => number_to_string(n),
+        }
+    }
+
+    fn to_number(&self, value: &XValue) -> f64 {
+        match *value {
+            XValue::Number(n) => n,
+            XValue::Boolean(b) => if b { 1.0 } else { 0.0 },
+            _ => string_to_number(&self.to_string(value)),
+        }
+    }
+
+    fn to_boolean(&self, value: &XValue) -> bool {
+        match *value {
+            XValue::Nodes(ref nodes) => !nodes.is_empty(),
+            XValue::String(ref s) => !s.is_empty(),
+            XValue::Boolean(b) => b,
+            XValue::Number(n) => n != 0.0 && !n.is_nan(),
+        }
+    }
+
+    fn function(&self, name: &str, args: &[Expr], context: &Context) -> XResult<XValue> {
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.eval(arg, context)?);
+        }
+        // Functions taking an optional argument default to the context node
+        let context_node = XValue::Nodes(vec![context.node.clone()]);
+        let first = values.first().unwrap_or(&context_node);
+        let string = |i: usize| self.to_string(&values[i]);
+        let number = |i: usize| self.to_number(&values[i]);
+        Ok(match name {
+            "last" => XValue::Number(context.size as f64),
+            "position" => XValue::Number(context.position as f64),
+            "true" => XValue::Boolean(true),
+            "false" => XValue::Boolean(false),
+            "count" => XValue::Number(self.node_set(values[0].clone())?.len() as f64),
+            "not" => XValue::Boolean(!self.to_boolean(&values[0])),
+            "boolean" => XValue::Boolean(self.to_boolean(&values[0])),
+            "sum" => {
+                let nodes = self.node_set(values[0].clone())?;
+                XValue::Number(nodes.iter()
+                    .map(|n| string_to_number(&self.string_value(n)))
+                    .fold(0.0, |a, b| a + b))
+            }
+            "floor" => XValue::Number(number(0).floor()),
+            "ceiling" => XValue::Number(number(0).ceil()),
+            "round" => XValue::Number(round(number(0))),
+            "string" => XValue::String(self.to_string(first)),
+            "number" => XValue::Number(self.to_number(first)),
+            "string-length" => XValue::Number(self.to_string(first).chars().count() as f64),
+            "normalize-space" => {
+                let s = self.to_string(first);
+                XValue::String(s.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            "name" | "local-name" => {
+                let nodes = self.node_set(first.clone())?;
+                let node_name = match nodes.first() {
+                    Some(&XNode::Node(i)) => {
+                        self.node(i).and_then(|n| n.name()).unwrap_or("").to_string()
+                    }
+                    Some(&XNode::Attribute(_, ref name)) => name.clone(),
+                    _ => String::new(),
+                };
+                if name == "local-name" {
+                    XValue::String(node_name.rsplit(':').next().unwrap_or("").to_string())
+                } else {
+                    XValue::String(node_name)
+                }
+            }
+            "concat" => XValue::String((0..values.len()).map(string).collect()),
+            "starts-with" => XValue::Boolean(string(0).starts_with(&string(1))),
+            "contains" => XValue::Boolean(string(0).contains(&string(1))),
+            "substring-before" => {
+                let (s, pattern) = (string(0), string(1));
+                XValue::String(s.find(&pattern).map(|i| &s[..i]).unwrap_or("").to_string())
+            }
+            "substring-after" => {
+                let (s, pattern) = (string(0), string(1));
+                XValue::String(s.find(&pattern)
+                    .map(|i| &s[i + pattern.len()..])
+                    .unwrap_or("")
+                    .to_string())
+            }
+            "substring" => {
+                let start = round(number(1));
+                let end = if values.len() > 2 {
+                    start + round(number(2))
+                } else {
+                    f64::INFINITY
+                };
+                // Characters are counted from 1
+                XValue::String(string(0)
+                    .chars()
+                    .enumerate()
+                    .filter(|&(i, _)| (i + 1) as f64 >= start && ((i + 1) as f64) < end)
+                    .map(|(_, c)| c)
+                    .collect())
+            }
+            "translate" => {
+                let from: Vec<char> = string(1).chars().collect();
+                let to: Vec<char> = string(2).chars().collect();
+                XValue::String(string(0)
+                    .chars()
+                    .filter_map(|c| match from.iter().position(|&f| f == c) {
+                        Some(i) => to.get(i).cloned(),
+                        None => Some(c),
+                    })
+                    .collect())
+            }
+            _ => return Err(format!("unknown function {}", name)),
+        })
+    }
+}
+
+/// Round to the nearest integer, halves towards positive infinity
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == n.trunc() && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+/// Parse a number as XPath does, NaN unless it is digits with an optional dot and minus sign
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim();
+    let digits = if s.starts_with('-') { &s[1..] } else { s };
+    let valid = !digits.is_empty() && digits != "." &&
+                digits.chars().all(|c| c.is_digit(10) || c == '.') &&
+                digits.chars().filter(|&c| c == '.').count() <= 1;
+    if valid {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use select::document::Document;
+    use super::{XPath, XValue};
+
+    const PAGE: &'static str = "<html><body>\
+        <table id=\"stats\">\
+          <tr><th>Year</th><th>Population</th></tr>\
+          <tr><td>2015</td><td>1 200</td></tr>\
+          <tr><td>2016</td><td>1 350</td></tr>\
+          <tr><td>Total</td><td>2 550</td></tr>\
+        </table>\
+        <dl><dt>Price</dt><dd>59.90</dd><dt>Stock</dt><dd>3</dd></dl>\
+        <p class=\"note\">Updated <b>today</b></p><!-- end -->\
+        <a href=\"/a\">A</a><a href=\"/b\" rel=\"next\">B</a>\
+        </body></html>";
+
+    fn strings(xpath: &str) -> Vec<String> {
+        let doc = Document::from(PAGE);
+        XPath::parse(xpath).unwrap().strings(&doc).unwrap()
+    }
+
+    fn value(xpath: &str) -> XValue {
+        let doc = Document::from(PAGE);
+        XPath::parse(xpath).unwrap().evaluate(&doc).unwrap()
+    }
+
+    #[test]
+    fn select_nodes() {
+        assert_eq!(strings("//table[@id='stats']//tr[position() > 1 and last()]/td[1]"),
+                   vec!["2015", "2016", "Total"]);
+        assert_eq!(strings("//dt[. = 'Price']/following-sibling::dd[1]"), vec!["59.90"]);
+        assert_eq!(strings("//td[text() = 'Total']/../preceding-sibling::tr[1]/td[2]"),
+                   vec!["1 350"]);
+        assert_eq!(strings("//tr[last()]/td[1]"), vec!["Total"]);
+        assert_eq!(strings("(//td)[last()]"), vec!["2 550"]);
+        assert_eq!(strings("//a[@rel]/@href | //a[1]/@href"), vec!["/a", "/b"]);
+        assert_eq!(strings("//p[contains(@class, 'note')]/text()"), vec!["Updated "]);
+        assert_eq!(strings("//b/ancestor::*[1]/@class"), vec!["note"]);
+        assert_eq!(strings("//dl/following::a[1]"), vec!["A"]);
+        assert_eq!(strings("//a[1]/preceding::dd[1]"), vec!["3"]);
+        assert_eq!(strings("//comment()"), vec![" end "]);
+        assert_eq!(strings("//th | //nothing"), vec!["Year", "Population"]);
+    }
+
+    #[test]
+    fn evaluate_expressions() {
+        assert_eq!(value("count(//tr) * 2 div 4"), XValue::Number(2.0));
+        assert_eq!(value("7 mod 4 - -1"), XValue::Number(4.0));
+        assert_eq!(value("sum(//dd) > 62"), XValue::Boolean(true));
+        assert_eq!(value("//td = '2016' and not(//td = 2017)"), XValue::Boolean(true));
+        assert_eq!(strings("normalize-space(//p)"), vec!["Updated today"]);
+        assert_eq!(strings("concat(substring('XPath', 2, 3), '-', translate('abc', 'ab', 'B'))"),
+                   vec!["Pat-Bc"]);
+        assert_eq!(strings("substring-before(//dd[1], '.')"), vec!["59"]);
+        assert_eq!(strings("substring-after(//dd[1], '.')"), vec!["90"]);
+        assert_eq!(strings("string-length(name(//table))"), vec!["5"]);
+        assert_eq!(strings("round(2.5) + floor(-1.5) + ceiling(0.2)"), vec!["2"]);
+        assert_eq!(strings("number('1 200')"), vec!["NaN"]);
+        assert_eq!(strings("1 div 0"), vec!["Infinity"]);
+        assert_eq!(strings("0.5 * 3"), vec!["1.5"]);
+        assert_eq!(strings("string(true())"), vec!["true"]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for xpath in &["", "//", "//p[", "//p[1", "foo(1)", "count()", "//p/unknown::a",
+                       "'unclosed", "$var", "//@*", "//p)", "1 +"] {
+            assert!(XPath::parse(xpath).is_err(), "{}", xpath);
+        }
+        let doc = Document::from(PAGE);
+        assert!(XPath::parse("count(1)").unwrap().evaluate(&doc).is_err());
+    }
+}